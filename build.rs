@@ -22,6 +22,7 @@ fn main() {
     let mut cx = proc_macro::Context::new();
     let mut grammar = proc_macro::builtin(&mut cx);
     grammar.extend(grammer::grammar_grammar(&mut cx));
+    grammar.extend(grammar_grammar_extensions(&mut cx));
 
     fs::write(
         &out_dir.join("parse_grammar.rs"),
@@ -29,3 +30,53 @@ fn main() {
     )
     .unwrap();
 }
+
+/// Syntax supported by `gll`, on top of (or replacing rules in) `grammer`'s.
+fn grammar_grammar_extensions(cx: &mut proc_macro::Context) -> grammer::Grammar {
+    use grammer::rule::{call, eat};
+
+    let mut g = grammer::Grammar::new();
+
+    let str_lit = || call("STR_LIT");
+    let char_lit = || call("CHAR_LIT");
+    let int_lit = || call("INT_LIT");
+    g.define(
+        cx.intern("Pattern"),
+        (str_lit().field("Str")
+            | (char_lit().opt().field("start") + eat("..") + char_lit().opt().field("end"))
+                .field("CharRange")
+            | (char_lit().opt().field("start") + eat("..=") + char_lit().field("end"))
+                .field("CharRangeInclusive")
+            | (eat("!").opt().field("negated")
+                + eat("[")
+                + call("ClassItem").repeat_many().field("items")
+                + eat("]"))
+            .field("Class")
+            | (eat("!") + char_lit().field("ch")).field("NegatedChar"))
+        .finish(cx),
+    );
+    g.define(
+        cx.intern("ClassItem"),
+        ((char_lit().field("start") + eat("..=") + char_lit().field("end")).field("Range")
+            | char_lit().field("Char")
+            | call("IDENT").field("Property"))
+        .finish(cx),
+    );
+    // NOTE `{...}` after a rule is only unambiguous with groups
+    // because patterns are restricted to string and character literals.
+    g.define(
+        cx.intern("Modifier"),
+        (eat("?").field("Opt")
+            | (call("Repeat").field("repeat")
+                + (call("Primary").field("sep") + call("SepKind").field("kind")).opt())
+            .field("Repeat")
+            | (eat("{")
+                + int_lit().field("min")
+                + (eat(",") + int_lit().field("max")).opt()
+                + eat("}"))
+            .field("Count"))
+        .finish(cx),
+    );
+
+    g
+}
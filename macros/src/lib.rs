@@ -2,24 +2,208 @@
 
 extern crate proc_macro;
 
-use proc_macro::TokenStream;
+use gll::analysis::{Lint, Report};
+use gll::generate::rust::{Backend, Options};
+use proc_macro::{Delimiter, Ident, Span, TokenStream, TokenTree};
 use proc_quote::ToTokens as _;
 
 #[proc_macro]
 pub fn scannerless_parser(input: TokenStream) -> TokenStream {
+    let (attrs, input) = match split_attrs(input) {
+        Ok(attrs_and_input) => attrs_and_input,
+        Err(error) => return error,
+    };
     let mut cx = gll::scannerless::Context::new();
-    let grammar = gll::parse_grammar(&mut cx, input.into()).unwrap();
-    gll::generate::rust::generate(&mut cx, &grammar)
+    let grammar = match gll::parse_grammar(&mut cx, input.into()) {
+        Ok(grammar) => grammar,
+        Err(error) => return error.to_compile_error().into(),
+    };
+    let starts = match attrs
+        .starts(|name| Some(cx.intern(name)).filter(|name| grammar.rules.contains_key(name)))
+    {
+        Ok(starts) => starts,
+        Err(error) => return error,
+    };
+    if let Some(error) = attrs.check_lints(|| gll::analysis::analyze(&cx, &grammar, &starts)) {
+        return error;
+    }
+    gll::generate::rust::generate_with_options(&mut cx, &grammar, attrs.options)
         .into_token_stream()
         .into()
 }
 
 #[proc_macro]
 pub fn proc_macro_parser(input: TokenStream) -> TokenStream {
+    let (attrs, input) = match split_attrs(input) {
+        Ok(attrs_and_input) => attrs_and_input,
+        Err(error) => return error,
+    };
     let mut cx = gll::proc_macro::Context::new();
     let mut grammar = gll::proc_macro::builtin(&mut cx);
-    grammar.extend(gll::parse_grammar(&mut cx, input.into()).unwrap());
-    gll::generate::rust::generate(&mut cx, &grammar)
+    let builtins: Vec<_> = grammar.rules.keys().cloned().collect();
+    match gll::parse_grammar(&mut cx, input.into()) {
+        Ok(user_grammar) => grammar.extend(user_grammar),
+        Err(error) => return error.to_compile_error().into(),
+    }
+    let mut starts = match attrs
+        .starts(|name| Some(cx.intern(name)).filter(|name| grammar.rules.contains_key(name)))
+    {
+        Ok(starts) => starts,
+        Err(error) => return error,
+    };
+    // NOTE builtin rules are always usable, even if the grammar doesn't.
+    if !starts.is_empty() {
+        starts.extend(builtins);
+    }
+    if let Some(error) = attrs.check_lints(|| gll::analysis::analyze(&cx, &grammar, &starts)) {
+        return error;
+    }
+    gll::generate::rust::generate_with_options(&mut cx, &grammar, attrs.options)
         .into_token_stream()
         .into()
 }
+
+/// Settings from `#![...]` attributes at the start of the grammar.
+#[derive(Default)]
+struct Attrs {
+    /// From `#![backend = "..."]` and `#![first_sets = ...]`.
+    options: Options,
+    /// From `#![deny(...)]`, the `Lint`s that are errors in the grammar.
+    deny: Vec<Lint>,
+    /// From `#![start(...)]`, the names of the rules the grammar is used
+    /// through, for analysis (see `gll::analysis::analyze`).
+    start: Vec<Ident>,
+}
+
+impl Attrs {
+    /// The rules named in `#![start(...)]`, looked up with `rule` (which
+    /// returns `None` for names not in the grammar), or a `compile_error!`
+    /// for the first name not found.
+    fn starts<R>(&self, mut rule: impl FnMut(&str) -> Option<R>) -> Result<Vec<R>, TokenStream> {
+        self.start
+            .iter()
+            .map(|name| {
+                rule(&name.to_string()).ok_or_else(|| {
+                    compile_error(&format!("unknown start rule `{}`", name), name.span())
+                })
+            })
+            .collect()
+    }
+
+    /// Analyze the grammar (using `analyze`), if any lints were denied,
+    /// returning a `compile_error!` listing all the denied lints found.
+    fn check_lints(&self, analyze: impl FnOnce() -> Report) -> Option<TokenStream> {
+        if self.deny.is_empty() {
+            return None;
+        }
+        let findings: Vec<_> = analyze()
+            .lints()
+            .into_iter()
+            .filter(|finding| self.deny.contains(&finding.lint))
+            .map(|finding| finding.to_string())
+            .collect();
+        if findings.is_empty() {
+            return None;
+        }
+        let message = findings.join("\n");
+        Some(compile_error(&message, Span::call_site()))
+    }
+}
+
+/// A `compile_error!("...");` invocation reporting `message` at `span`
+/// (see `gll::proc_macro::compile_error`).
+fn compile_error(message: &str, span: Span) -> TokenStream {
+    gll::proc_macro::compile_error(message, span.into()).into()
+}
+
+/// Remove all `#![...]` attributes from the start of the grammar,
+/// returning the `Attrs` they specify (or the default ones), or
+/// a `compile_error!` for the first invalid attribute.
+fn split_attrs(input: TokenStream) -> Result<(Attrs, TokenStream), TokenStream> {
+    let mut attrs = Attrs::default();
+    let mut tokens: Vec<_> = input.into_iter().collect();
+    loop {
+        let (attr, span) = match &tokens[..] {
+            [TokenTree::Punct(hash), TokenTree::Punct(bang), TokenTree::Group(g), ..]
+                if hash.as_char() == '#'
+                    && bang.as_char() == '!'
+                    && g.delimiter() == Delimiter::Bracket =>
+            {
+                (g.stream(), g.span())
+            }
+            _ => return Ok((attrs, tokens.into_iter().collect())),
+        };
+        tokens.drain(..3);
+
+        let attr: Vec<_> = attr.into_iter().collect();
+        match &attr[..] {
+            [TokenTree::Ident(name), TokenTree::Punct(eq), TokenTree::Literal(value)]
+                if name.to_string() == "backend" && eq.as_char() == '=' =>
+            {
+                attrs.options.backend = match &value.to_string()[..] {
+                    "\"code\"" => Backend::Code,
+                    "\"tables\"" => Backend::Tables,
+                    _ => {
+                        return Err(compile_error(
+                            "expected `\"code\"` or `\"tables\"` as the backend",
+                            value.span(),
+                        ))
+                    }
+                };
+            }
+            [TokenTree::Ident(name), TokenTree::Punct(eq), TokenTree::Ident(value)]
+                if name.to_string() == "first_sets" && eq.as_char() == '=' =>
+            {
+                attrs.options.first_sets = match &value.to_string()[..] {
+                    "true" => true,
+                    "false" => false,
+                    _ => return Err(compile_error("expected `true` or `false`", value.span())),
+                };
+            }
+            [TokenTree::Ident(name), TokenTree::Group(lints)]
+                if name.to_string() == "deny" && lints.delimiter() == Delimiter::Parenthesis =>
+            {
+                for tt in lints.stream() {
+                    match tt {
+                        TokenTree::Punct(ref comma) if comma.as_char() == ',' => {}
+                        _ => match Lint::from_name(&tt.to_string()) {
+                            Some(lint) => attrs.deny.push(lint),
+                            None => {
+                                let names: Vec<_> = Lint::ALL
+                                    .iter()
+                                    .map(|lint| format!("`{}`", lint.name()))
+                                    .collect();
+                                return Err(compile_error(
+                                    &format!(
+                                        "unknown grammar lint `{}`, expected one of {}",
+                                        tt,
+                                        names.join(", ")
+                                    ),
+                                    tt.span(),
+                                ));
+                            }
+                        },
+                    }
+                }
+            }
+            [TokenTree::Ident(name), TokenTree::Group(rules)]
+                if name.to_string() == "start" && rules.delimiter() == Delimiter::Parenthesis =>
+            {
+                for tt in rules.stream() {
+                    match tt {
+                        TokenTree::Punct(ref comma) if comma.as_char() == ',' => {}
+                        TokenTree::Ident(rule) => attrs.start.push(rule),
+                        _ => return Err(compile_error("expected a rule name", tt.span())),
+                    }
+                }
+            }
+            _ => {
+                return Err(compile_error(
+                    "expected `#![backend = \"...\"]`, `#![first_sets = ...]`, \
+                     `#![deny(...)]` or `#![start(...)]`",
+                    span,
+                ))
+            }
+        }
+    }
+}
@@ -0,0 +1,173 @@
+#![deny(rust_2018_idioms)]
+
+use gll::analysis::{analyze, Lint};
+
+#[test]
+fn analyze_grammar() {
+    let mut cx = gll::scannerless::Context::new();
+    let grammar = gll::parse_grammar(
+        &mut cx,
+        r#"
+            Expr = Add:{ a:Expr "+" b:Atom } | Atom:Atom;
+            Atom = Num:['0'..='9']+ | Paren:{ "(" Expr ")" };
+            List = "[" elems:Atom* % "," "]";
+            Maybe = "a"? "a";
+            Loop = "!" Loop;
+            Lonely = "?";
+        "#
+        .parse()
+        .unwrap(),
+    )
+    .unwrap();
+    let starts = [cx.intern("Expr"), cx.intern("List"), cx.intern("Maybe")];
+    let report = analyze(&cx, &grammar, &starts);
+
+    assert_eq!(report.left_recursive_cycles, [["Expr"]]);
+    assert_eq!(report.unreachable, ["Loop", "Lonely"]);
+    assert_eq!(report.unproductive, ["Loop"]);
+
+    let expr = &report.rules["Expr"];
+    assert!(!expr.nullable);
+    assert!(!expr.ll1);
+    assert!(expr.follow_end);
+
+    let atom = &report.rules["Atom"];
+    assert!(atom.ll1, "{:?}", atom.ll1_conflicts);
+    assert!(!atom.follow_end);
+    assert_eq!(atom.follow.len(), 4, "{:?}", atom.follow);
+
+    let list = &report.rules["List"];
+    assert!(list.ll1, "{:?}", list.ll1_conflicts);
+    assert_eq!(list.first, [r#""[""#]);
+
+    let maybe = &report.rules["Maybe"];
+    assert!(!maybe.ll1);
+    assert_eq!(
+        maybe.ll1_conflicts,
+        [r#""a" can either start or follow an optional rule"#]
+    );
+
+    let lints: Vec<_> = report.lints().iter().map(|l| l.lint).collect();
+    assert!(lints.contains(&Lint::Unproductive));
+    assert!(lints.contains(&Lint::LeftRecursion));
+    assert!(report
+        .to_string()
+        .contains("left-recursive cycle: Expr -> Expr"));
+
+    // Build scripts get warnings for all but the denied lints' findings.
+    let denied = report
+        .emit_cargo_warnings(&[Lint::Unproductive])
+        .unwrap_err();
+    assert_eq!(denied.len(), 1);
+    assert_eq!(
+        denied[0].to_string(),
+        "Loop: can't match any input [unproductive]"
+    );
+    assert!(report.emit_cargo_warnings(&[]).is_ok());
+}
+
+#[test]
+fn ll1_compares_first_characters() {
+    let mut cx = gll::scannerless::Context::new();
+    let grammar = gll::parse_grammar(
+        &mut cx,
+        r#"
+            Keyword = "if" | "in" | "else";
+            Ident = "if" | ['a'..='z']+;
+            Digit = ['0'..='9'] | [Numeric];
+            Letter = ['a'..='z'] | ['A'..='Z'];
+        "#
+        .parse()
+        .unwrap(),
+    )
+    .unwrap();
+    let report = analyze(&cx, &grammar, &[]);
+
+    assert_eq!(
+        report.rules["Keyword"].ll1_conflicts,
+        [r#""if" and "in" can start both alternatives 1 and 2 (of 3)"#]
+    );
+    assert!(!report.rules["Ident"].ll1);
+    assert!(!report.rules["Digit"].ll1);
+    let letter = &report.rules["Letter"];
+    assert!(letter.ll1, "{:?}", letter.ll1_conflicts);
+}
+
+#[test]
+fn ll1_compares_first_tokens() {
+    let mut cx = gll::proc_macro::Context::new();
+    let grammar = gll::parse_grammar(
+        &mut cx,
+        r#"
+            Item = "fn main" | "fn" "(" ")";
+            Path = "a" | "b" | "a::b";
+        "#
+        .parse()
+        .unwrap(),
+    )
+    .unwrap();
+    let report = analyze(&cx, &grammar, &[]);
+
+    assert!(!report.rules["Item"].ll1);
+    let path = &report.rules["Path"];
+    assert_eq!(path.ll1_conflicts.len(), 1, "{:?}", path.ll1_conflicts);
+}
+
+// Grammars without any of the denied lints compile as usual.
+mod denied_lints {
+    ::gll_macros::scannerless_parser! {
+        #![deny(unproductive, unreachable, left_recursion)]
+        #![start(Pair)]
+        #![backend = "tables"]
+        Pair = "(" a:Elem "," b:Elem ")";
+        Elem = "x" | Pair;
+    }
+
+    // Builtin rules (like `IDENT`) are never unreachable.
+    pub mod tokens {
+        ::gll_macros::proc_macro_parser! {
+            #![deny(unreachable)]
+            #![start(Pair)]
+            Pair = "(" a:IDENT "," b:IDENT ")";
+        }
+    }
+}
+
+#[test]
+fn denied_lints_compile() {
+    assert!(denied_lints::Pair::parse("(x,(x,x))").is_ok());
+    assert!(denied_lints::tokens::Pair::parse(proc_quote::quote!((x, y))).is_ok());
+}
+
+mod ambiguous {
+    ::gll_macros::scannerless_parser! {
+        Expr = Add:{ a:Expr "+" b:Expr } | Num:{ digit:['0'..='9'] };
+    }
+}
+
+#[test]
+fn shortest_ambiguity() {
+    let mut cx = gll::scannerless::Context::new();
+    let grammar = gll::parse_grammar(
+        &mut cx,
+        r#"
+            Expr = Add:{ a:Expr "+" b:Expr } | Num:{ digit:['0'..='9'] };
+        "#
+        .parse()
+        .unwrap(),
+    )
+    .unwrap();
+    let expr = cx.intern("Expr");
+    let sentences = gll::analysis::sentences(&cx, &grammar, expr, 5);
+    assert_eq!(sentences[..4], ["0", "9", "0+0", "0+9"]);
+
+    assert!(ambiguous::Expr::find_ambiguity(vec!["0", "0+0"]).is_none());
+    let witness = ambiguous::Expr::find_ambiguity(sentences.iter().map(|s| &s[..])).unwrap();
+    assert_eq!(witness.input, "0+0+0");
+    assert_eq!(witness.rule, "Expr");
+    assert_eq!(witness.source_info, "1:1-1:6");
+    assert_eq!(witness.derivations.len(), 2);
+    assert!(witness
+        .to_string()
+        .starts_with("Expr is ambiguous at 1:1-1:6"));
+}
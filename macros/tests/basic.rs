@@ -6,6 +6,10 @@ macro_rules! testcases {
     ($($name:ident { $($grammar:tt)* }: $($rule:ident($input:expr) => $expected:expr),* ;)*) => {
         $(mod $name {
             ::gll_macros::scannerless_parser!($($grammar)*);
+
+            pub mod tables {
+                ::gll_macros::scannerless_parser!(#![backend = "tables"] $($grammar)*);
+            }
         }
         #[test]
         fn $name() {$(
@@ -37,12 +41,7 @@ macro_rules! testcases {
 
             let result = match &result {
                 Ok(result) => format!("{:#?}", result),
-                Err(gll::parser::ParseError {
-                    at,
-                    expected,
-                }) => {
-                    format!("{:?}: error: expected {:?}", at, expected)
-                }
+                Err(error) => error.to_string(),
             };
             // FIXME(eddyb) Remove this trailing-comma-ignoring hack
             // once rust-lang/rust#59076 reaches the stable channel.
@@ -55,6 +54,23 @@ macro_rules! testcases {
                 $expected,
                 result
             );
+
+            // The table-driven backend has to produce the exact same result.
+            let tables_result = match $name::tables::$rule::parse($input) {
+                Ok(result) => format!("{:#?}", result),
+                Err(error) => error.to_string(),
+            };
+            assert_eq!(tables_result, result);
+
+            // So does parsing on multiple threads.
+            #[cfg(feature = "parallel")]
+            {
+                let parallel_result = match $name::$rule::parse_parallel($input, 4) {
+                    Ok(result) => format!("{:#?}", result),
+                    Err(error) => error.to_string(),
+                };
+                assert_eq!(parallel_result, result);
+            }
         )*})*
     };
 }
@@ -99,8 +115,8 @@ testcases![
         1:1-1:2
     )
 }",
-// FIXME(eddyb) get replace quotes with backticks and pretify the `expected` list.
-    S("aax") => r#"1:3: error: expected ["d", "d", "a", "b", "a", "c"]"#;
+    S("aax") => "1:3: expected `a` or `c` (in A), `a` or `b` (in B), or `d` (in S)",
+    S("aa") => "1:3: unexpected end of input, expected `a` or `c` (in A), `a` or `b` (in B), or `d` (in S)";
 
     gll10_g0_opaque {
         S = { a:A s:S "d" } |
@@ -187,7 +203,7 @@ testcases![
         1:3-1:4,
     ],
 }",
-    A("b") => r#"1:1: error: expected ["a"]"#;
+    A("b") => "1:1: expected `a` (in A)";
 
     nested_or {
         A = x:"x" { a:"a" | b:"b" };
@@ -198,3 +214,505 @@ testcases![
     a: 1:2-1:3,
 }";
 ];
+
+mod nested_parens {
+    ::gll_macros::scannerless_parser! {
+        P = Nested:{ "(" inner:P ")" } | Empty:{};
+    }
+}
+
+#[test]
+fn gc_keeps_enclosing_calls() {
+    // Deep enough to go through several GC cycles, while the outermost calls
+    // (started long before the current position) still need to return.
+    let depth = 2000;
+    let balanced = format!("{}{}", "(".repeat(depth), ")".repeat(depth));
+    assert!(nested_parens::P::parse(&balanced[..]).is_ok());
+
+    let unbalanced = format!("{}{}", "(".repeat(depth), ")".repeat(depth - 1));
+    assert!(nested_parens::P::parse(&unbalanced[..]).is_err());
+}
+
+mod long_list {
+    ::gll_macros::scannerless_parser! {
+        Pairs = "{" pairs:Pair* % "," "}";
+        Pair = key:Name ":" value:Value;
+        Name = ['a'..='z']+;
+        Value = Num:['0'..='9']+ | List:{ "[" elems:Value* % "," "]" };
+    }
+}
+
+#[test]
+fn gc_bounds_live_calls() {
+    let input = format!("{{{}}}", vec!["key:[1,23,[4,5],678]"; 2000].join(","));
+    let (result, stats) = long_list::Pairs::parse_with_stats(&input[..]);
+    assert!(result.is_ok());
+    assert!(stats.gc_runs > 1, "{:?}", stats);
+
+    // Out of the dozens of calls made for each pair, only the one continuing
+    // the list of pairs is kept (as it only returns at the very end), along
+    // with at most as much garbage as was left after the previous collection.
+    let total_calls = stats.gss_nodes + stats.memoizer_entries;
+    assert!(stats.max_live_calls * 4 < total_calls, "{:?}", stats);
+}
+
+mod stream_items {
+    ::gll_macros::scannerless_parser! {
+        Item = "a"+ ";";
+    }
+}
+
+/// Parse all the `Item`s in `input`, read one byte at a time, to force
+/// parsing incomplete input.
+fn read_items(input: &'static [u8]) -> std::io::Result<(Vec<String>, usize)> {
+    struct ByteByByte(&'static [u8]);
+    impl std::io::Read for ByteByByte {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(1);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    let mut input = gll::input::ReadInput::new(ByteByByte(input));
+    let mut items = vec![];
+    input.consume_with(|buffered, eof| {
+        let parse = stream_items::Item::parse_prefix(buffered);
+        if parse.reached_end && !eof {
+            return Ok(None);
+        }
+        let (_, len) = parse.result.map_err(|e| {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{:?}", e))
+        })?;
+        items.push(buffered[..len].to_string());
+        Ok(Some(len))
+    })?;
+    Ok((items, input.consumed()))
+}
+
+#[test]
+fn parse_prefixes_of_read_input() {
+    let (items, consumed) = read_items(b"aa;a;aaa;").unwrap();
+    assert_eq!(items, ["aa;", "a;", "aaa;"]);
+    assert_eq!(consumed, 9);
+
+    let parse = stream_items::Item::parse_prefix("aa");
+    assert!(parse.result.is_err() && parse.reached_end);
+
+    // A truncated last item is an error, not a request for more input.
+    let error = read_items(b"aa;a").unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+
+    // Even when more input keeps being requested, past the end.
+    let mut input = gll::input::ReadInput::new(&b"aa;a"[..]);
+    let error = input
+        .consume_with(|buffered, _| -> std::io::Result<_> {
+            let parse = stream_items::Item::parse_prefix(buffered);
+            if parse.reached_end {
+                return Ok(None);
+            }
+            Ok(parse.result.ok().map(|(_, len)| len))
+        })
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+    assert_eq!(input.consumed(), 3);
+}
+
+struct Chunks(Vec<&'static str>);
+
+impl gll::input::Rope for Chunks {
+    fn chunks(&self) -> Box<dyn Iterator<Item = &str> + '_> {
+        Box::new(self.0.iter().cloned())
+    }
+
+    fn line_column(&self, byte_offset: usize) -> gll::input::LineColumn {
+        let prefix = &self.0.concat()[..byte_offset];
+        gll::input::LineColumn {
+            line: prefix.matches('\n').count(),
+            column: prefix.rsplit('\n').next().unwrap().chars().count(),
+        }
+    }
+}
+
+#[test]
+fn rope_input_matches_str_input() {
+    for chunks in vec![vec!["aad"], vec!["a", "", "ad"], vec!["a", "a", "d"]] {
+        let rope = Chunks(chunks);
+        assert_eq!(
+            format!("{:#?}", gll10_g0::S::parse(gll::input::RopeInput(&rope))),
+            format!("{:#?}", gll10_g0::S::parse("aad"))
+        );
+    }
+
+    let rope = Chunks(vec!["x", "a"]);
+    nested_or::A::parse(gll::input::RopeInput(&rope))
+        .unwrap()
+        .with(|a| assert_eq!(&*a.source(), "xa"));
+
+    // Slicing across chunks doesn't cache anything in the container,
+    // so it can still be shared between threads.
+    #[cfg(feature = "parallel")]
+    nested_or::A::parse_parallel(gll::input::RopeInput(&rope), 4)
+        .unwrap()
+        .with(|a| assert_eq!(&*a.source(), "xa"));
+}
+
+mod file_ext {
+    ::gll_macros::scannerless_parser! {
+        Ext = "." "gz";
+        Exts = exts:AnyExt+;
+        AnyExt = "." ['a'..='z']+;
+    }
+}
+
+#[test]
+fn parse_suffixes_of_generated_rules() {
+    let (ext, len) = file_ext::Ext::parse_suffix("foo.tar.gz").unwrap();
+    assert_eq!(len, 3);
+    ext.with(|ext| assert_eq!(&*ext.source(), ".gz"));
+    assert!(file_ext::Ext::parse_suffix("foo.tar.xz").is_err());
+
+    // The longest suffix matching is picked, even if shorter ones also do.
+    let (exts, len) = file_ext::Exts::parse_suffix("foo.tar.gz").unwrap();
+    assert_eq!(len, 7);
+    exts.with(|exts| assert_eq!(&*exts.source(), ".tar.gz"));
+    assert!(file_ext::Exts::parse_suffix("foo.tar.gz1").is_err());
+}
+
+#[test]
+fn parse_suffixes_of_non_ascii_input() {
+    // Suffixes can only start at character boundaries, not inside `é`.
+    let (exts, len) = file_ext::Exts::parse_suffix("fé.tar.gz").unwrap();
+    assert_eq!(len, 7);
+    exts.with(|exts| assert_eq!(&*exts.source(), ".tar.gz"));
+    assert!(file_ext::Ext::parse_suffix("é").is_err());
+    assert!(file_ext::Exts::parse_suffix("é.gé").is_err());
+
+    // Even if the character is split across chunks, or starts a chunk.
+    for chunks in vec![vec!["fé.tar", ".gz"], vec!["f", "é.t", "ar.gz"]] {
+        let rope = Chunks(chunks);
+        let (_, len) = file_ext::Exts::parse_suffix(gll::input::RopeInput(&rope)).unwrap();
+        assert_eq!(len, 7);
+    }
+}
+
+#[test]
+fn parse_suffixes_right_to_left() {
+    use gll::forest::ParseNode;
+    use gll::parser::Parser;
+
+    // Hand-written right-to-left matching of `Ext`.
+    let ext = |input| {
+        Parser::parse_suffix_with(file_ext::_G, input, |mut p| {
+            let mut p = p.input_consume_right(file_ext::_P::Ext, &"gz")?;
+            let p = p.input_consume_right(file_ext::_P::Ext, &".")?;
+            Some(ParseNode {
+                kind: file_ext::_P::Ext,
+                range: p.result(),
+            })
+        })
+        .map(|(_, len)| len)
+    };
+    assert_eq!(ext("foo.tar.gz").unwrap(), 3);
+    assert_eq!(
+        ext("foo.tar.xz").unwrap_err().to_string(),
+        "1:11: expected `gz` (in Ext)"
+    );
+    assert_eq!(
+        ext("foogz").unwrap_err().to_string(),
+        "1:4: expected `.` (in Ext)"
+    );
+    assert_eq!(
+        ext("gz").unwrap_err().to_string(),
+        "1:1: unexpected end of input, expected `.` (in Ext)"
+    );
+}
+
+mod char_classes {
+    ::gll_macros::scannerless_parser! {
+        Ident = [XID_Start] [XID_Continue '-']*;
+        Str = "\"" !['"' '\\']* "\"";
+        NotQuote = !'"';
+        Spaces = [White_Space]+;
+    }
+}
+
+#[test]
+fn char_classes_and_unicode_properties() {
+    assert!(char_classes::Ident::parse("héllo-wörld_2").is_ok());
+    assert_eq!(
+        char_classes::Ident::parse("2x").unwrap_err().to_string(),
+        "1:1: expected `[XID_Start]` (in Ident)"
+    );
+
+    assert!(char_classes::Str::parse("\"a'b\"").is_ok());
+    assert!(char_classes::Str::parse("\"a\\b\"").is_err());
+    assert!(char_classes::Str::parse("\"a\"b\"").is_err());
+
+    assert!(char_classes::NotQuote::parse("x").is_ok());
+    assert!(char_classes::NotQuote::parse("\"").is_err());
+
+    assert!(char_classes::Spaces::parse(" \t\n\u{3000}").is_ok());
+    assert!(char_classes::Spaces::parse(" _ ").is_err());
+}
+
+#[test]
+fn invalid_grammars_are_parse_errors() {
+    let scannerless = |grammar: &str| {
+        let mut cx = gll::scannerless::Context::new();
+        gll::parse_grammar(&mut cx, grammar.parse().unwrap())
+            .map(|_| ())
+            .map_err(|error| error.message())
+    };
+
+    // Unicode properties are only recognized in classes, not as rule names.
+    assert!(scannerless("XID_Start = [XID_Start]; A = XID_Start;").is_ok());
+    assert_eq!(
+        scannerless("A = [Foo_Bar];").unwrap_err(),
+        "expected `Alphabetic`, `Lowercase`, `Numeric`, `Uppercase`, \
+         `White_Space`, `XID_Continue` or `XID_Start` (in ClassItem)"
+    );
+
+    assert_eq!(
+        scannerless(r#"A = "a"{5,2};"#).unwrap_err(),
+        "expected a maximum count of at least 5 (in Modifier)"
+    );
+    assert_eq!(
+        scannerless(r#"A = "a"{99999999999999999999999};"#).unwrap_err(),
+        "expected a repetition count (fitting in `usize`) (in Modifier)"
+    );
+
+    for grammar in &["A = ['a'..='z'];", r#"A = i"select";"#] {
+        let mut cx = gll::proc_macro::Context::new();
+        let error = gll::parse_grammar(&mut cx, grammar.parse().unwrap()).unwrap_err();
+        assert_eq!(
+            error.message(),
+            "expected a string literal (character patterns are only \
+             supported in scannerless mode) (in Pattern)"
+        );
+    }
+}
+
+mod case_and_counts {
+    ::gll_macros::scannerless_parser! {
+        Select = i"select" " " ['0'..='9' 'a'..='f']{4};
+        Fields = Field{2,3};
+        Field = "<" ['0'..='9']{1,2} ">";
+    }
+}
+
+#[test]
+fn case_insensitive_strings_and_counted_repetition() {
+    assert!(case_and_counts::Select::parse("select beef").is_ok());
+    assert!(case_and_counts::Select::parse("SeLeCt 0123").is_ok());
+    assert!(case_and_counts::Select::parse("select 012").is_err());
+    assert!(case_and_counts::Select::parse("select 01234").is_err());
+    assert_eq!(
+        case_and_counts::Select::parse("update 0000")
+            .unwrap_err()
+            .to_string(),
+        "1:1: expected case-insensitive `select` (in Select)"
+    );
+
+    assert!(case_and_counts::Fields::parse("<1><23>").is_ok());
+    assert!(case_and_counts::Fields::parse("<1><2><34>").is_ok());
+    assert!(case_and_counts::Fields::parse("<1>").is_err());
+    assert!(case_and_counts::Fields::parse("<1><2><3><4>").is_err());
+    assert!(case_and_counts::Fields::parse("<123><4>").is_err());
+}
+
+mod keywords {
+    ::gll_macros::scannerless_parser! {
+        Kw = If:"if" | In:"in" | Int:"int" | Else:{ "else" "!"? } | Ident:['a'..='z']+;
+    }
+}
+
+#[test]
+fn literal_alternatives_dispatch() {
+    for &s in &["if", "in", "int", "else", "else!", "xyz"] {
+        assert!(keywords::Kw::parse(s).is_ok(), "{:?}", s);
+    }
+    keywords::Kw::parse("int").unwrap().with(|kw| {
+        assert_eq!(kw.all().count(), 2);
+    });
+    assert!(keywords::Kw::parse("in!").is_err());
+    assert_eq!(
+        keywords::Kw::parse("1").unwrap_err().to_string(),
+        "1:1: expected `['a'..='z']`, `else`, `if`, `in` or `int` (in Kw)"
+    );
+}
+
+mod call_stats {
+    ::gll_macros::scannerless_parser! {
+        A = "a" B;
+        B = "b";
+    }
+
+    pub mod tables {
+        ::gll_macros::scannerless_parser! {
+            #![backend = "tables"]
+            A = "a" B;
+            B = "b";
+        }
+    }
+}
+
+/// All the counts in `stats` (i.e. everything but times and `gc_runs`).
+fn stats_counts(stats: &gll::runtime::ParseStats) -> [usize; 8] {
+    [
+        stats.threads_spawned,
+        stats.threads_deduplicated,
+        stats.max_queue_len,
+        stats.gss_nodes,
+        stats.gss_edges,
+        stats.memoizer_entries,
+        stats.forest_choice_nodes,
+        stats.forest_split_nodes,
+    ]
+}
+
+#[test]
+fn exact_parse_stats() {
+    let (result, stats) = call_stats::A::parse_with_stats("ab");
+    assert!(result.is_ok());
+    let (result, tables_stats) = call_stats::tables::A::parse_with_stats("ab");
+    assert!(result.is_ok());
+    assert_eq!(stats_counts(&tables_stats), stats_counts(&stats));
+
+    // Threads: `A` itself, `B` (called from `A`), and `A` after `B` returns.
+    assert_eq!(stats.threads_spawned, 3);
+    assert_eq!(stats.threads_deduplicated, 0);
+    assert_eq!(stats.max_queue_len, 1);
+    // The only call in the GSS is `B`, returning to `A`, but both of
+    // them return (`A` being the call the whole parse started with).
+    assert_eq!(stats.gss_nodes, 1);
+    assert_eq!(stats.gss_edges, 1);
+    assert_eq!(stats.memoizer_entries, 2);
+
+    // `B` is never called, as it can't start with `c`.
+    let (result, stats) = call_stats::A::parse_with_stats("ac");
+    assert!(result.is_err());
+    let (result, tables_stats) = call_stats::tables::A::parse_with_stats("ac");
+    assert!(result.is_err());
+    assert_eq!(stats_counts(&tables_stats), stats_counts(&stats));
+
+    assert_eq!(stats.threads_spawned, 1);
+    assert_eq!(stats.gss_nodes, 0);
+    assert_eq!(stats.gss_edges, 0);
+    assert_eq!(stats.memoizer_entries, 0);
+}
+
+#[test]
+fn parse_stats() {
+    let (result, stats) = keywords::Kw::parse_with_stats("int");
+    assert!(result.is_ok());
+
+    // `int` is both a keyword and an identifier, which repeats characters.
+    assert!(stats.forest_choice_nodes >= 1);
+    assert!(stats.forest_split_nodes >= 1);
+
+    assert!(stats.threads_spawned > stats.threads_deduplicated);
+    assert!(stats.max_queue_len >= 1);
+    assert!(stats.gss_nodes >= 1);
+    assert!(stats.gss_edges >= stats.gss_nodes);
+    assert!(stats.memoizer_entries >= 1);
+    assert!(stats.total_time >= stats.run_time + stats.gc_time);
+
+    // Failed parses still have statistics about what was attempted.
+    let (result, stats) = keywords::Kw::parse_with_stats("1");
+    assert!(result.is_err());
+    assert_eq!(stats.forest_choice_nodes, 0);
+    assert!(stats.threads_spawned >= 1);
+}
+
+mod statements {
+    ::gll_macros::scannerless_parser! {
+        Stmts = Stmt*;
+        Stmt = Let:Let | Fn:Fn | Use:Use | Mod:Mod
+            | Pub:Pub | Type:Type | Const:Const | Static:Static;
+        Let = "let " Name ";";
+        Fn = "fn " Name ";";
+        Use = "use " Name ";";
+        Mod = "mod " Name ";";
+        Pub = "pub " Name ";";
+        Type = "type " Name ";";
+        Const = "const " Name ";";
+        Static = "static " Name ";";
+        Name = ['a'..='z']+;
+    }
+}
+
+#[test]
+fn first_sets_skip_calls() {
+    let n = 100;
+    let input = "static x;".repeat(n);
+    let (result, stats) = statements::Stmts::parse_with_stats(&input[..]);
+    assert!(result.is_ok());
+
+    // Without FIRST set checks, every statement would call all 8 of the
+    // `Stmt` alternatives, but only `Static` (and `Name`) can start there.
+    assert!(stats.gss_nodes < 8 * n, "{:?}", stats);
+}
+
+mod exact_literals {
+    ::gll_macros::proc_macro_parser! {
+        Foo = "\"foo\"";
+        Bar = "\"bar\"";
+        ByteX = "b'x'";
+        Suffixed = "1u8";
+    }
+}
+
+#[test]
+fn exact_literal_patterns() {
+    assert!(exact_literals::Foo::parse(proc_quote::quote!("foo")).is_ok());
+    assert!(exact_literals::Foo::parse(proc_quote::quote!("bar")).is_err());
+    assert!(exact_literals::Bar::parse(proc_quote::quote!("bar")).is_ok());
+    // Literals are compared as written, not by their value.
+    assert!(exact_literals::Foo::parse(proc_quote::quote!(r"foo")).is_err());
+    assert!(exact_literals::Foo::parse(proc_quote::quote!(b"foo")).is_err());
+
+    assert!(exact_literals::ByteX::parse(proc_quote::quote!(b'x')).is_ok());
+    assert!(exact_literals::ByteX::parse(proc_quote::quote!(b'y')).is_err());
+    assert!(exact_literals::ByteX::parse(proc_quote::quote!('x')).is_err());
+
+    assert!(exact_literals::Suffixed::parse(proc_quote::quote!(1u8)).is_ok());
+    assert!(exact_literals::Suffixed::parse(proc_quote::quote!(1)).is_err());
+    assert!(exact_literals::Suffixed::parse(proc_quote::quote!(1u16)).is_err());
+}
+
+#[test]
+fn literal_kinds_of_suffixed_and_raw_literals() {
+    use gll::proc_macro::{Literal, LiteralKind, TokenStream, TokenTree};
+
+    let kind = |tokens: TokenStream| match tokens.into_iter().next() {
+        Some(TokenTree::Literal(lit)) => LiteralKind::of(&lit),
+        tt => panic!("expected a literal, found {:?}", tt),
+    };
+
+    assert_eq!(kind(proc_quote::quote!(1u8)), LiteralKind::Int);
+    assert_eq!(kind(proc_quote::quote!(1_000i64)), LiteralKind::Int);
+    assert_eq!(kind(proc_quote::quote!(0x1f)), LiteralKind::Int);
+    assert_eq!(kind(proc_quote::quote!(0xefu32)), LiteralKind::Int);
+    assert_eq!(kind(proc_quote::quote!(0b1010usize)), LiteralKind::Int);
+    assert_eq!(kind(proc_quote::quote!(1f32)), LiteralKind::Float);
+    assert_eq!(kind(proc_quote::quote!(2.5f64)), LiteralKind::Float);
+    assert_eq!(kind(proc_quote::quote!(1e3)), LiteralKind::Float);
+    assert_eq!(kind(proc_quote::quote!(1.5E-3)), LiteralKind::Float);
+
+    assert_eq!(kind(proc_quote::quote!(r"s")), LiteralKind::Str);
+    assert_eq!(kind(proc_quote::quote!(r#"s"#)), LiteralKind::Str);
+    assert_eq!(kind(proc_quote::quote!(b"s")), LiteralKind::ByteStr);
+    assert_eq!(kind(proc_quote::quote!(br#"s"#)), LiteralKind::ByteStr);
+    assert_eq!(kind(proc_quote::quote!('c')), LiteralKind::Char);
+    assert_eq!(kind(proc_quote::quote!('\u{e9}')), LiteralKind::Char);
+    assert_eq!(kind(proc_quote::quote!(b'c')), LiteralKind::Byte);
+
+    // Literals created with `Literal` methods can be negative.
+    let kind = |lit: Literal| LiteralKind::of(&lit);
+    assert_eq!(kind(Literal::i32_suffixed(-1)), LiteralKind::Int);
+    assert_eq!(kind(Literal::i32_unsuffixed(-1)), LiteralKind::Int);
+    assert_eq!(kind(Literal::f64_suffixed(-1.5)), LiteralKind::Float);
+    assert_eq!(kind(Literal::f64_unsuffixed(-1.0)), LiteralKind::Float);
+}
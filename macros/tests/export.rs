@@ -0,0 +1,78 @@
+#![deny(rust_2018_idioms)]
+
+use gll::export::{self, Export};
+
+const GRAMMAR: &str = r#"
+    Expr = Add:{ a:Expr "+" b:Atom } | Atom:Atom;
+    Atom = Num:['0'..='9' '_']+ | Name:{ [XID_Start] [XID_Continue]* } | Paren:{ "(" Expr ")" };
+    List = "[" elems:Atom* % "," "]";
+    Kw = i"if" "'\""?;
+"#;
+
+fn export_all() -> [Export; 3] {
+    let mut cx = gll::scannerless::Context::new();
+    let grammar = gll::parse_grammar(&mut cx, GRAMMAR.parse().unwrap()).unwrap();
+    [
+        export::ebnf(&cx, &grammar),
+        export::antlr(&cx, &grammar, "Test"),
+        export::tree_sitter(&cx, &grammar, "test"),
+    ]
+}
+
+#[test]
+fn export_ebnf() {
+    let [ebnf, _, _] = export_all();
+    assert_eq!(
+        ebnf.source,
+        r#"Expr ::= /* Add: */ /* a: */ Expr "+" /* b: */ Atom
+       | /* Atom: */ Atom
+Atom ::= /* Num: */ [0-9#x5F]+
+       | /* Name: */ XID_Start XID_Continue*
+       | /* Paren: */ "(" Expr ")"
+List ::= "[" /* elems: */ (Atom ("," Atom)*)? "]"
+Kw ::= [iI] [fF] ("'" '"')?
+"#
+    );
+    assert_eq!(ebnf.warnings.len(), 3, "{:#?}", ebnf.warnings);
+    assert!(ebnf.warnings.iter().any(|w| w.contains("`XID_Start`")));
+}
+
+#[test]
+fn export_antlr() {
+    let [_, antlr, _] = export_all();
+    assert!(antlr.source.starts_with("grammar Test;\n"));
+    for rule in &[
+        "expr\n    : a=expr '+' b=atom # Add\n    | atom # ExprAtom\n    ;\n",
+        "    | CHARS_1 CHARS_2* # Name\n",
+        "list\n    : '[' (elems+=atom (',' elems+=atom)*)? ']'\n    ;\n",
+        "kw\n    : CHARS_3 CHARS_4 '\\'\"'?\n    ;\n",
+        "CHARS_0 : [0-9_] ;\n",
+        "CHARS_1 : [\\p{XID_Start}] ;\n",
+    ] {
+        assert!(antlr.source.contains(rule), "{}", antlr.source);
+    }
+    assert!(antlr
+        .warnings
+        .iter()
+        .any(|w| w.contains("renamed to `ExprAtom`")));
+    assert!(antlr.warnings.iter().any(|w| w.contains("CHARS_*")));
+}
+
+#[test]
+fn export_tree_sitter() {
+    let [_, _, tree_sitter] = export_all();
+    for rule in &[
+        "  name: 'test',\n",
+        "    Expr: $ => choice(\n      field('Add', seq(field('a', $.Expr), '+', field('b', $.Atom))),\n",
+        "      field('Name', seq(/[\\p{XID_Start}]/u, repeat(/[\\p{XID_Continue}]/u))),\n",
+        "    List: $ => seq('[', field('elems', optional(seq($.Atom, repeat(seq(',', $.Atom))))), ']'),\n",
+        "    Kw: $ => seq(/[iI]/, /[fF]/, optional('\\'\"')),\n",
+    ] {
+        assert!(tree_sitter.source.contains(rule), "{}", tree_sitter.source);
+    }
+    assert!(
+        tree_sitter.warnings.is_empty(),
+        "{:#?}",
+        tree_sitter.warnings
+    );
+}
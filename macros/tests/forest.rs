@@ -0,0 +1,95 @@
+#![deny(rust_2018_idioms)]
+
+use gll::forest::{GrammarReflector, TreeCount};
+use gll::parse_node::ParseNodeShape;
+
+mod gll10_g0 {
+    ::gll_macros::scannerless_parser! {
+        S = X:{ a:A s:S d:"d" } |
+            Y:{ b:B s:S } |
+            Z:{};
+
+        A = A:"a" |
+            C:"c";
+
+        B = A:"a" |
+            B:"b";
+    }
+}
+
+#[test]
+fn count_and_extract_trees() {
+    gll10_g0::S::parse("aad").unwrap().with(|s| {
+        let forest = s.forest;
+        assert_eq!(forest.count_trees(s.node), TreeCount::Finite(2));
+
+        let first = forest.kth_tree(s.node, 0).unwrap();
+        let second = forest.kth_tree(s.node, 1).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(forest.kth_tree(s.node, 2), None);
+
+        assert_eq!(forest.random_tree(s.node, |n| n - 1), Some(second));
+    });
+}
+
+#[test]
+fn sorted_choices_and_splits() {
+    gll10_g0::S::parse("aad").unwrap().with(|s| {
+        let forest = s.forest;
+        let mut trees = vec![
+            forest.kth_tree(s.node, 0).unwrap(),
+            forest.kth_tree(s.node, 1).unwrap(),
+        ];
+        while let Some(tree) = trees.pop() {
+            let node = tree.node;
+            match forest.grammar.parse_node_shape(node.kind) {
+                ParseNodeShape::Choice => {
+                    let kinds: Vec<_> = forest.all_choices(node).map(|c| c.kind).collect();
+                    assert!(kinds.windows(2).all(|w| w[0] < w[1]), "{:?}", kinds);
+                    assert_eq!(forest.one_choice(node).is_ok(), kinds.len() == 1);
+                }
+                ParseNodeShape::Split(..) => {
+                    let lens: Vec<_> = forest
+                        .all_splits(node)
+                        .map(|(left, _)| left.range.len())
+                        .collect();
+                    assert!(lens.windows(2).all(|w| w[0] < w[1]), "{:?}", lens);
+                    assert_eq!(forest.one_split(node).is_ok(), lens.len() == 1);
+                }
+                _ => {}
+            }
+            trees.extend(tree.children.iter().cloned());
+        }
+    });
+}
+
+#[test]
+fn prune_ambiguities() {
+    let mut result = gll10_g0::S::parse("aad").unwrap();
+    result.with_forest_mut(|forest, root| {
+        // Keep only the first choice, out of each set of choices.
+        forest
+            .retain_choices(|forest, node, choice| forest.all_choices(node).next() == Some(choice));
+        assert!(forest.collect_garbage(root));
+        assert_eq!(forest.count_trees(root), TreeCount::Finite(1));
+    });
+    result.with(|s| assert!(s.one().is_ok()));
+}
+
+mod list {
+    ::gll_macros::scannerless_parser! {
+        List = "[" elems:"x"* % "," "]";
+    }
+}
+
+#[test]
+fn count_and_extract_long_list() {
+    // Long lists produce deeply nested splits, which used to overflow the
+    // stack, when counting or extracting trees recursively.
+    let input = format!("[{}x]", "x,".repeat(50_000));
+    list::List::parse(&input[..]).unwrap().with(|list| {
+        let forest = list.forest;
+        assert_eq!(forest.count_trees(list.node), TreeCount::Finite(1));
+        assert!(forest.kth_tree(list.node, 0).is_some());
+    });
+}
@@ -0,0 +1,94 @@
+#![deny(rust_2018_idioms)]
+
+//! The memory and time it takes to parse (into a forest) large JSON-like
+//! inputs. Only run on demand, e.g. on two commits, to compare them, with:
+//! `cargo test --release --test forest_memory -- --ignored --nocapture`
+//!
+//! NOTE this only uses APIs which haven't changed since before the forest
+//! was made compact, so it can be copied to older commits as-is.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// `System`, but keeping track of how many bytes are allocated
+/// (at any given time, and at most, since the last reset of `PEAK`).
+struct Counting;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            let mut peak = PEAK.load(Ordering::SeqCst);
+            while current > peak {
+                match PEAK.compare_exchange_weak(peak, current, Ordering::SeqCst, Ordering::SeqCst)
+                {
+                    Ok(_) => break,
+                    Err(p) => peak = p,
+                }
+            }
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOC: Counting = Counting;
+
+mod json_like {
+    ::gll_macros::proc_macro_parser! {
+        Value =
+            | Null:"null"
+            | False:"false"
+            | True:"true"
+            | Literal:LITERAL
+            | Array:{ "[" elems:Value* % "," "]" }
+            | Object:{ "{" fields:Field* % "," "}" }
+            | InterpolateRust:{ "(" TOKEN_TREE+ ")" }
+            ;
+        Field = name:IDENT ":" value:Value;
+    }
+}
+
+#[test]
+#[ignore]
+fn json_like_forest_memory_and_time() {
+    for &n in &[100, 1_000, 10_000] {
+        let value = proc_quote::quote! {
+            {
+                name: "John Doe",
+                age: 43,
+                address: { street: "10 Downing Street", city: "London" },
+                phones: ["+44 1234567", "+44 2345678"],
+                test: [null, false, true, (format!("{:?}", Some(1 + 2)))]
+            }
+        };
+        let values = std::iter::repeat(value).take(n);
+        let tokens: gll::proc_macro::TokenStream = proc_quote::quote!([#(#values),*]);
+
+        // NOTE the input is counted too (as flattened tokens), but its
+        // `TokenStream` is freed while parsing, so these can be negative.
+        let before = CURRENT.load(Ordering::SeqCst) as isize;
+        PEAK.store(before as usize, Ordering::SeqCst);
+        let start = Instant::now();
+        let result = json_like::Value::parse(tokens).unwrap();
+        let time = start.elapsed();
+        let retained = CURRENT.load(Ordering::SeqCst) as isize - before;
+        let peak = PEAK.load(Ordering::SeqCst) as isize - before;
+        drop(result);
+
+        println!(
+            "{} values: parsed in {:?}, {} bytes kept (forest and input), {} bytes at most",
+            n, time, retained, peak
+        );
+    }
+}
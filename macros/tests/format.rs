@@ -0,0 +1,61 @@
+#![deny(rust_2018_idioms)]
+
+fn format(grammar: &str) -> String {
+    gll::format_grammar(grammar.parse().unwrap()).unwrap()
+}
+
+/// Generate a (scannerless) parser, to compare grammars by.
+fn generate(grammar: &str) -> String {
+    let mut cx = gll::scannerless::Context::new();
+    let grammar = gll::parse_grammar(&mut cx, grammar.parse().unwrap()).unwrap();
+    gll::generate::rust::generate(&mut cx, &grammar).to_pretty_string()
+}
+
+const GRAMMAR: &str = r#"
+    Expr   =   Add:{a:Expr "+" b:Atom}|Atom:Atom ;
+    Atom =
+        | Num: ['0'..='9' '_']+
+        | Paren: { "(" Expr ")" }
+        | Char: { "'" !['\'' '\\'] "'" }
+        | Keyword: i"select"
+        ;
+    List = "[" elems:Atom* % "," "]" | "{" elems:Atom+ %% ";" "}";
+    Chars = 'a'..'z' ..='Z' !'"';
+    Counted = x:"x"{2,5} y:Atom{3};
+    Long = First:"first alternative" | Second:"second alternative" | Third:"third alternative";
+"#;
+
+#[test]
+fn format_grammar() {
+    let formatted = format(GRAMMAR);
+    assert_eq!(
+        formatted,
+        r#"Expr = Add:{ a:Expr "+" b:Atom } | Atom:Atom;
+Atom =
+    | Num:['0'..='9' '_']+
+    | Paren:{ "(" Expr ")" }
+    | Char:{ "'" !['\'' '\\'] "'" }
+    | Keyword:i"select"
+    ;
+List = "[" elems:Atom* % "," "]" | "{" elems:Atom+ %% ";" "}";
+Chars = 'a'..'z' ..='Z' !'"';
+Counted = x:"x"{2,5} y:Atom{3};
+Long =
+    | First:"first alternative"
+    | Second:"second alternative"
+    | Third:"third alternative"
+    ;
+"#
+    );
+
+    // Formatting is idempotent, and doesn't change the grammar.
+    assert_eq!(format(&formatted), formatted);
+    assert_eq!(generate(&formatted), generate(GRAMMAR));
+}
+
+// Without span locations, `i "..."` can't be told apart from `i"..."`.
+#[cfg(feature = "span-locations")]
+#[test]
+fn case_insensitive_strings_need_adjacent_tokens() {
+    assert_eq!(format(r#"A = i"x" i "x";"#), "A = i\"x\" i \"x\";\n");
+}
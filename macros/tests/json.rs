@@ -13,6 +13,38 @@ mod json_like {
             ;
         Field = name:IDENT ":" value:Value;
     }
+
+    pub mod tables {
+        ::gll_macros::proc_macro_parser! {
+            #![backend = "tables"]
+            Value =
+                | Null:"null"
+                | False:"false"
+                | True:"true"
+                | Literal:LITERAL
+                | Array:{ "[" elems:Value* % "," "]" }
+                | Object:{ "{" fields:Field* % "," "}" }
+                | InterpolateRust:{ "(" TOKEN_TREE+ ")" }
+                ;
+            Field = name:IDENT ":" value:Value;
+        }
+    }
+
+    pub mod no_first_sets {
+        ::gll_macros::proc_macro_parser! {
+            #![first_sets = false]
+            Value =
+                | Null:"null"
+                | False:"false"
+                | True:"true"
+                | Literal:LITERAL
+                | Array:{ "[" elems:Value* % "," "]" }
+                | Object:{ "{" fields:Field* % "," "}" }
+                | InterpolateRust:{ "(" TOKEN_TREE+ ")" }
+                ;
+            Field = name:IDENT ":" value:Value;
+        }
+    }
 }
 
 #[test]
@@ -35,7 +67,11 @@ fn json_like_proc_macro() {
         }
     };
 
-    let result = format!("{:#?}", json_like::Value::parse(tokens).unwrap());
+    let result = format!("{:#?}", json_like::Value::parse(tokens.clone()).unwrap());
+    assert_eq!(
+        format!("{:#?}", json_like::tables::Value::parse(tokens).unwrap()),
+        result
+    );
     // HACK(eddyb) clean up the result, as we have no span info.
     let result = result
         .replace("Span..Span => ", "")
@@ -118,3 +154,237 @@ Value::Object {
         result
     );
 }
+
+#[test]
+fn json_like_first_sets() {
+    let value = proc_quote::quote! {
+        {
+            name: "John Doe",
+            age: 43,
+            phones: ["+44 1234567", "+44 2345678"],
+            test: [null, false, true, {}, []]
+        }
+    };
+    let values = std::iter::repeat(value).take(100);
+    let tokens = proc_quote::quote!([#(#values),*]);
+
+    let (result, stats) = json_like::Value::parse_with_stats(tokens.clone());
+    let (no_first_result, no_first_stats) =
+        json_like::no_first_sets::Value::parse_with_stats(tokens);
+
+    // FIRST set checks don't change the forest, only how much work it takes.
+    assert_eq!(
+        format!("{:#?}", result.unwrap()),
+        format!("{:#?}", no_first_result.unwrap())
+    );
+    assert_eq!(
+        stats.forest_choice_nodes,
+        no_first_stats.forest_choice_nodes
+    );
+    assert_eq!(stats.forest_split_nodes, no_first_stats.forest_split_nodes);
+
+    // Without them, `LITERAL` is called for every `Value` (not only for
+    // literals), and `Value` for the `]` of every empty array.
+    assert!(
+        stats.gss_nodes < no_first_stats.gss_nodes,
+        "with FIRST sets: {:?}\nwithout: {:?}",
+        stats,
+        no_first_stats
+    );
+    assert!(
+        stats.threads_spawned < no_first_stats.threads_spawned,
+        "with FIRST sets: {:?}\nwithout: {:?}",
+        stats,
+        no_first_stats
+    );
+}
+
+#[test]
+fn json_like_flat_tokens() {
+    let tokens: gll::proc_macro::TokenStream = proc_quote::quote! {
+        { a: [null, false, true], b: 1 }
+    };
+    let mut flat = vec![];
+    gll::proc_macro::flatten(tokens, &mut flat);
+
+    // Token slices are inputs too, with token indices as source locations.
+    let value = json_like::Value::parse(&flat[..]).unwrap();
+    assert_eq!(value.source_info(), 0..flat.len());
+    let value = json_like::tables::Value::parse(&flat[..]).unwrap();
+    assert_eq!(value.source_info(), 0..flat.len());
+
+    let error = json_like::Value::parse(&flat[..flat.len() - 1]).unwrap_err();
+    assert_eq!(error.at, flat.len() - 1);
+    assert!(error.at_end);
+}
+
+#[test]
+fn json_like_token_suffixes() {
+    use gll::forest::ParseNode;
+    use gll::parser::Parser;
+    use gll::proc_macro::FlatTokenPat;
+
+    // The suffix is counted in flattened tokens, i.e. `[`, `null`, `,`, `true`, `]`.
+    let (value, len) =
+        json_like::Value::parse_suffix(proc_quote::quote!(a b [null, true])).unwrap();
+    assert_eq!(len, 5);
+    assert_eq!(
+        value.with(|value| value.to_token_stream().unwrap().to_string()),
+        proc_quote::quote!([null, true]).to_string()
+    );
+    let (_, len) =
+        json_like::tables::Value::parse_suffix(proc_quote::quote!(a b [null, true])).unwrap();
+    assert_eq!(len, 5);
+
+    // Hand-written right-to-left matching of the last two tokens.
+    static NULL_FALSE: &[FlatTokenPat<&str>] = &[
+        FlatTokenPat::Ident(Some("null")),
+        FlatTokenPat::Ident(Some("false")),
+    ];
+    let null_false = |tokens| {
+        Parser::parse_suffix_with(json_like::_G, tokens, |mut p| {
+            let p = p.input_consume_right(json_like::_P::Value, &NULL_FALSE)?;
+            Some(ParseNode {
+                kind: json_like::_P::Value,
+                range: p.result(),
+            })
+        })
+        .map(|(_, len)| len)
+    };
+    assert_eq!(null_false(proc_quote::quote!(a b null false)).unwrap(), 2);
+    assert!(null_false(proc_quote::quote!(null false a b)).is_err());
+}
+
+mod none_groups {
+    ::gll_macros::proc_macro_parser! {
+        Args = args:Arg* % ",";
+        Arg = Expr:NONE_GROUP | Ident:IDENT;
+    }
+}
+
+#[test]
+fn none_groups_are_opaque() {
+    use gll::proc_macro::{Delimiter, Group, TokenStream, TokenTree};
+
+    let expr = Group::new(Delimiter::None, proc_quote::quote!(a + b));
+    let tokens: TokenStream = vec![
+        TokenTree::from(expr),
+        proc_quote::quote!(,).into_iter().next().unwrap(),
+        proc_quote::quote!(c).into_iter().next().unwrap(),
+    ]
+    .into_iter()
+    .collect();
+
+    assert!(none_groups::Args::parse(tokens.clone()).is_ok());
+    assert!(none_groups::Args::parse(gll::proc_macro::flatten_none_groups(tokens)).is_err());
+}
+
+mod literals {
+    ::gll_macros::proc_macro_parser! {
+        Zero = "0";
+        Lit =
+              Int:INT_LIT
+            | Float:FLOAT_LIT
+            | Str:STR_LIT
+            | ByteStr:BYTE_STR_LIT
+            | Char:CHAR_LIT
+            | Bool:BOOL;
+    }
+}
+
+#[test]
+fn literal_kinds_and_values() {
+    let kind = |tokens| {
+        literals::Lit::parse(tokens).ok().map(|lit| {
+            lit.with(|lit| match lit.one().unwrap() {
+                literals::Lit::Int(_) => "int",
+                literals::Lit::Float(_) => "float",
+                literals::Lit::Str(_) => "str",
+                literals::Lit::ByteStr(_) => "byte_str",
+                literals::Lit::Char(_) => "char",
+                literals::Lit::Bool(_) => "bool",
+            })
+        })
+    };
+    assert_eq!(kind(proc_quote::quote!(1)), Some("int"));
+    assert_eq!(kind(proc_quote::quote!(0x1f)), Some("int"));
+    assert_eq!(kind(proc_quote::quote!(2.5)), Some("float"));
+    assert_eq!(kind(proc_quote::quote!(1e3)), Some("float"));
+    assert_eq!(kind(proc_quote::quote!(1f32)), Some("float"));
+    assert_eq!(kind(proc_quote::quote!("s")), Some("str"));
+    assert_eq!(kind(proc_quote::quote!(r"s")), Some("str"));
+    assert_eq!(kind(proc_quote::quote!(b"b")), Some("byte_str"));
+    assert_eq!(kind(proc_quote::quote!('c')), Some("char"));
+    assert_eq!(kind(proc_quote::quote!(true)), Some("bool"));
+    assert_eq!(kind(proc_quote::quote!(b'x')), None);
+    assert_eq!(kind(proc_quote::quote!(x)), None);
+
+    assert!(literals::Zero::parse(proc_quote::quote!(0)).is_ok());
+    assert!(literals::Zero::parse(proc_quote::quote!(1)).is_err());
+    assert!(literals::Zero::parse(proc_quote::quote!(0u8)).is_err());
+}
+
+#[test]
+fn handles_to_token_streams() {
+    let rust = proc_quote::quote!(format!("{:?}", [Some(1 + 2)]));
+    let tokens = proc_quote::quote!([null, (#rust)]);
+
+    json_like::Value::parse(tokens.clone())
+        .unwrap()
+        .with(|value| {
+            assert_eq!(
+                value.to_token_stream().unwrap().to_string(),
+                tokens.to_string()
+            );
+            let elems = match value.one().unwrap() {
+                json_like::Value::Array { elems } => elems,
+                _ => unreachable!(),
+            };
+            let interpolated = elems
+                .filter_map(|elem| match elem.unwrap().one().unwrap() {
+                    json_like::Value::InterpolateRust(parens) => {
+                        Some(parens.to_token_stream().unwrap())
+                    }
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(interpolated.len(), 1);
+            assert_eq!(
+                interpolated[0].to_string(),
+                proc_quote::quote!((#rust)).to_string()
+            );
+        });
+}
+
+mod delims {
+    ::gll_macros::proc_macro_parser! {
+        Call = name:IDENT open:Open IDENT* ")";
+        Open = "(";
+    }
+}
+
+#[test]
+fn unbalanced_handles_to_token_streams() {
+    delims::Call::parse(proc_quote::quote!(f(a b)))
+        .unwrap()
+        .with(|call| {
+            assert_eq!(
+                call.to_token_stream().unwrap().to_string(),
+                proc_quote::quote!(f(a b)).to_string()
+            );
+            // Only `(` was matched by `Open`, without its `)`.
+            let open = call.one().unwrap().open;
+            assert_eq!(open.source().len(), 1);
+            assert!(open.to_token_stream().is_none());
+        });
+}
+
+#[test]
+fn parse_errors_to_compile_errors() {
+    let error = json_like::Value::parse(proc_quote::quote!({ a: null b: null })).unwrap_err();
+    assert_eq!(error.describe_expected(), "`,` or `}` (in Value)");
+    assert_eq!(
+        error.to_compile_error().to_string(),
+        proc_quote::quote!(compile_error!("expected `,` or `}` (in Value)");).to_string()
+    );
+}
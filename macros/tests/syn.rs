@@ -0,0 +1,49 @@
+#![cfg(feature = "syn")]
+#![deny(rust_2018_idioms)]
+
+mod let_like {
+    ::gll_macros::proc_macro_parser! {
+        Let = "let" name:IDENT { ":" ty:RUST_TYPE }? "=" init:RUST_EXPR ";";
+    }
+}
+
+#[test]
+fn rust_syntax_builtins() {
+    let tokens = proc_quote::quote!(let x: Vec<u8> = vec![1, 2].into_iter().collect(););
+    let_like::Let::parse(tokens).unwrap().with(|let_| {
+        let let_ = let_.one().unwrap();
+        let ty = let_.ty.unwrap().to_syn::<gll::syn::Type>().unwrap();
+        let init = let_.init.to_syn::<gll::syn::Expr>().unwrap();
+        assert_eq!(
+            proc_quote::quote!(#ty).to_string(),
+            proc_quote::quote!(Vec<u8>).to_string()
+        );
+        match init {
+            gll::syn::Expr::MethodCall(call) => assert_eq!(call.method, "collect"),
+            _ => unreachable!(),
+        }
+    });
+
+    // The expression can't end in the middle of a group, nor cover the `;`.
+    assert!(let_like::Let::parse(proc_quote::quote!(let x = (1 +);)).is_err());
+    assert!(let_like::Let::parse(proc_quote::quote!(let x = 1)).is_err());
+}
+
+#[test]
+fn rust_syntax_builtins_right_to_left() {
+    use gll::input::{Input, InputMatch};
+    use gll::proc_macro::{FlatTokenPat, RustSyntax};
+
+    let expr: &'static [FlatTokenPat<&'static str>] = &[FlatTokenPat::Rust(RustSyntax::Expr)];
+    let ty: &'static [FlatTokenPat<&'static str>] = &[FlatTokenPat::Rust(RustSyntax::Type)];
+
+    // The longest suffix `syn` accepts is matched, without crossing an
+    // unclosed opening delimiter, or starting inside a group.
+    let tokens = proc_quote::quote!(; a + f(b, c)).to_container();
+    assert_eq!(tokens[..].match_right(&expr), Some(8));
+    let tokens = proc_quote::quote!(-> Vec<u8>).to_container();
+    assert_eq!(tokens[..].match_right(&ty), Some(4));
+    let tokens = proc_quote::quote!(f(a + b)).to_container();
+    assert_eq!(tokens[..5].match_right(&expr), Some(3));
+    assert_eq!(tokens[..2].match_right(&expr), None);
+}
@@ -0,0 +1,965 @@
+//! Static analysis of grammars, before (or instead of) generating parsers,
+//! e.g. to find rules which can't ever match, or to check whether a rule
+//! could be parsed with a simpler algorithm, like LL(1), and enumerating
+//! the inputs rules can match, e.g. to find ambiguities with short witnesses.
+
+use crate::generate::first::FirstSets;
+use crate::generate::rust::RustInputPat;
+use crate::scannerless::{self, ClassItem};
+use grammer::context::{Context, IRule, IStr};
+use grammer::rule::{MatchesEmpty, Rule, RuleWithNamedFields, SepKind};
+use indexmap::{IndexMap, IndexSet};
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::hash::Hash;
+use std::rc::Rc;
+
+/// Everything `analyze` found out about a grammar.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    /// Per named rule, in the order they're defined in the grammar.
+    pub rules: IndexMap<String, RuleReport>,
+    /// Rules which can call themselves (through other rules) without
+    /// consuming any input first, each as a cycle of calls, i.e. where the
+    /// last rule calls the first one. There's one cycle for every group of
+    /// mutually left-recursive rules (i.e. even if there are more cycles).
+    pub left_recursive_cycles: Vec<Vec<String>>,
+    /// Rules which can't be called (even indirectly) from the start rules.
+    /// Without explicit start rules, all the rules not called by others are
+    /// used as start rules, so only (mutually) recursive rules can be found.
+    pub unreachable: Vec<String>,
+    /// Rules which can't match any input, e.g. `A = "a" A;`.
+    pub unproductive: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RuleReport {
+    /// Whether the rule can match without consuming any input. Patterns
+    /// which may (or may not) match empty input are assumed to, so this
+    /// can be `true` for rules which are never actually empty.
+    pub nullable: bool,
+    /// The patterns (as they'd appear in generated code) that can start a
+    /// (non-empty) match of the rule, or come right after the rule, in any
+    /// rule calling it (with `follow_end` indicating the end of the input).
+    pub first: Vec<String>,
+    pub follow: Vec<String>,
+    pub follow_end: bool,
+    /// Whether one character (or token) is always enough to decide, when
+    /// parsing the rule, which alternative to take, or whether an optional
+    /// (or repeated) rule is matched (again). Patterns are compared by what
+    /// they can start with (see `OverlapsFirst`), so e.g. both `"if" | "in"`
+    /// and `"if" | 'a'..='z'` count as conflicts.
+    pub ll1: bool,
+    /// Why the rule isn't LL(1), if it isn't.
+    pub ll1_conflicts: Vec<String>,
+}
+
+/// Patterns which can be compared by the inputs they can start matching
+/// (e.g. their first character, or token), to find LL(1) conflicts.
+pub trait OverlapsFirst {
+    /// Whether any input can start a match of both `self` and `other`
+    /// (assuming so for patterns which may match empty input).
+    fn overlaps_first(&self, other: &Self) -> bool;
+}
+
+/// Analyze all the rules in `grammar`, as used by (i.e. called, directly or
+/// indirectly, from) `starts`. If `starts` is empty, all rules which aren't
+/// called by any other rule are used (like builtin and top-level rules),
+/// or only the first rule, if they all are (e.g. in a mutually recursive way).
+pub fn analyze<Pat: Eq + Hash + MatchesEmpty + RustInputPat + OverlapsFirst>(
+    cx: &Context<Pat>,
+    grammar: &grammer::Grammar,
+    starts: &[IStr],
+) -> Report {
+    let named = &grammar.rules;
+    let mut analyzer = Analyzer {
+        cx,
+        named,
+        first: FirstSets::compute(cx, named),
+        follow: named
+            .keys()
+            .map(|&name| (name, Follow::default()))
+            .collect(),
+        overlaps: RefCell::new(HashMap::new()),
+    };
+
+    let starts: Vec<IStr> = if starts.is_empty() {
+        let called: IndexSet<IStr> = named
+            .iter()
+            .flat_map(|(&name, rule)| analyzer.calls(name, rule.rule, false))
+            .collect();
+        let uncalled: Vec<_> = named
+            .keys()
+            .cloned()
+            .filter(|name| !called.contains(name))
+            .collect();
+        if uncalled.is_empty() {
+            named.keys().cloned().take(1).collect()
+        } else {
+            uncalled
+        }
+    } else {
+        starts.to_vec()
+    };
+
+    // Rules can be (mutually) recursive, so keep recomputing FOLLOW sets
+    // until nothing changes anymore (which ends, as they can only grow).
+    for &start in &starts {
+        if let Some(follow) = analyzer.follow.get_mut(&start) {
+            follow.end = true;
+        }
+    }
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (&name, rule) in named {
+            let mut walk = Walk::default();
+            analyzer.walk(name, rule.rule, &analyzer.follow[&name], &mut walk);
+            for (callee, follow) in walk.calls {
+                if let Some(old) = analyzer.follow.get_mut(&callee) {
+                    changed |= old.extend(follow);
+                }
+            }
+        }
+    }
+
+    // Calls which can happen before any input is consumed, i.e. left calls.
+    let left_calls: IndexMap<IStr, IndexSet<IStr>> = named
+        .iter()
+        .map(|(&name, rule)| (name, analyzer.calls(name, rule.rule, true)))
+        .collect();
+    let left_reachable: IndexMap<IStr, IndexSet<IStr>> = named
+        .keys()
+        .map(|&name| (name, reachable(&left_calls, &[name])))
+        .collect();
+    let left_recursive = |name: IStr| {
+        left_calls[&name].iter().any(|callee| {
+            left_reachable
+                .get(callee)
+                .map_or(false, |r| r.contains(&name))
+        })
+    };
+
+    let mut report = Report::default();
+    for (&name, rule) in named {
+        let first = analyzer.first.first(cx, name, rule.rule);
+        let follow = &analyzer.follow[&name];
+        let mut walk = Walk::default();
+        analyzer.walk(name, rule.rule, follow, &mut walk);
+        let ll1 = walk.conflicts.is_empty() && !left_recursive(name);
+        report.rules.insert(
+            cx[name].to_string(),
+            RuleReport {
+                nullable: first.nullable,
+                first: first
+                    .eats
+                    .iter()
+                    .map(|&(_, eat)| eat)
+                    .collect::<IndexSet<_>>()
+                    .into_iter()
+                    .map(|eat| analyzer.describe(eat))
+                    .collect(),
+                follow: follow
+                    .eats
+                    .iter()
+                    .map(|&eat| analyzer.describe(eat))
+                    .collect(),
+                follow_end: follow.end,
+                ll1,
+                ll1_conflicts: walk.conflicts,
+            },
+        );
+    }
+
+    // Find one cycle for each group of mutually left-recursive rules.
+    let mut in_cycle = IndexSet::new();
+    for &name in named.keys() {
+        if in_cycle.contains(&name) || !left_recursive(name) {
+            continue;
+        }
+        let cycle = shortest_cycle(&left_calls, name);
+        in_cycle.extend(
+            named
+                .keys()
+                .cloned()
+                .filter(|&other| left_reachable[&name].contains(&other))
+                .filter(|other| left_reachable[other].contains(&name)),
+        );
+        in_cycle.insert(name);
+        report
+            .left_recursive_cycles
+            .push(cycle.into_iter().map(|r| cx[r].to_string()).collect());
+    }
+
+    let all_calls: IndexMap<IStr, IndexSet<IStr>> = named
+        .iter()
+        .map(|(&name, rule)| (name, analyzer.calls(name, rule.rule, false)))
+        .collect();
+    let used = reachable(&all_calls, &starts);
+    report.unreachable = named
+        .keys()
+        .filter(|name| !used.contains(name))
+        .map(|&name| cx[name].to_string())
+        .collect();
+
+    // Rules can be (mutually) recursive, so keep finding more productive
+    // rules until there aren't any left (which ends, as they can only grow).
+    let mut productive = IndexSet::new();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (&name, rule) in named {
+            if !productive.contains(&name) && analyzer.productive(rule.rule, &productive) {
+                productive.insert(name);
+                changed = true;
+            }
+        }
+    }
+    report.unproductive = named
+        .keys()
+        .filter(|name| !productive.contains(name))
+        .map(|&name| cx[name].to_string())
+        .collect();
+
+    report
+}
+
+/// All the rules reachable from `starts`, through `calls`.
+fn reachable(calls: &IndexMap<IStr, IndexSet<IStr>>, starts: &[IStr]) -> IndexSet<IStr> {
+    let mut reachable = IndexSet::new();
+    let mut stack = starts.to_vec();
+    while let Some(name) = stack.pop() {
+        if reachable.insert(name) {
+            if let Some(callees) = calls.get(&name) {
+                stack.extend(callees.iter().cloned());
+            }
+        }
+    }
+    reachable
+}
+
+/// The shortest cycle of `calls` starting (and ending) at `start`,
+/// which has to be part of at least one cycle.
+fn shortest_cycle(calls: &IndexMap<IStr, IndexSet<IStr>>, start: IStr) -> Vec<IStr> {
+    let mut parent = IndexMap::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    while let Some(name) = queue.pop_front() {
+        for &callee in calls.get(&name).into_iter().flatten() {
+            if callee == start {
+                let mut cycle = vec![name];
+                while let Some(&caller) = parent.get(cycle.last().unwrap()) {
+                    cycle.push(caller);
+                }
+                cycle.reverse();
+                return cycle;
+            }
+            if !parent.contains_key(&callee) {
+                parent.insert(callee, name);
+                queue.push_back(callee);
+            }
+        }
+    }
+    unreachable!("`{:?}` isn't part of any cycle", start)
+}
+
+/// The patterns (by their `Rule::Eat`s) that can follow a rule,
+/// and whether the end of the input can follow it.
+#[derive(Clone, Default)]
+struct Follow {
+    eats: IndexSet<IRule>,
+    end: bool,
+}
+
+impl Follow {
+    /// Add `other` to `self`, returning `true` if anything was new.
+    fn extend(&mut self, other: Follow) -> bool {
+        let len = self.eats.len();
+        self.eats.extend(other.eats);
+        let changed = self.eats.len() != len || (other.end && !self.end);
+        self.end |= other.end;
+        changed
+    }
+}
+
+/// What `Analyzer::walk` found in a rule.
+#[derive(Default)]
+struct Walk {
+    /// Every named rule called, with what can follow that call.
+    calls: Vec<(IStr, Follow)>,
+    /// Descriptions of every choice in the rule that isn't LL(1).
+    conflicts: Vec<String>,
+}
+
+struct Analyzer<'a, Pat> {
+    cx: &'a Context<Pat>,
+    named: &'a IndexMap<IStr, RuleWithNamedFields>,
+    first: FirstSets,
+    follow: IndexMap<IStr, Follow>,
+    /// Cached results of `overlaps`, as the same choices are checked
+    /// (by `walk`) every time FOLLOW sets are recomputed.
+    overlaps: RefCell<HashMap<(IRule, IRule), bool>>,
+}
+
+impl<Pat: Eq + Hash + MatchesEmpty + RustInputPat + OverlapsFirst> Analyzer<'_, Pat> {
+    fn describe(&self, eat: IRule) -> String {
+        match self.cx[eat] {
+            Rule::Eat(ref pat) => pat.rust_matcher().to_pretty_string(),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Whether the patterns of the `Rule::Eat`s `a` and `b` can start
+    /// matching the same input (see `OverlapsFirst`).
+    fn overlaps(&self, a: IRule, b: IRule) -> bool {
+        if a == b {
+            return true;
+        }
+        if let Some(&overlaps) = self.overlaps.borrow().get(&(a, b)) {
+            return overlaps;
+        }
+        let overlaps = match (&self.cx[a], &self.cx[b]) {
+            (Rule::Eat(a), Rule::Eat(b)) => a.overlaps_first(b),
+            _ => unreachable!(),
+        };
+        self.overlaps.borrow_mut().insert((a, b), overlaps);
+        overlaps
+    }
+
+    /// What can come first in a match of `rule` (found in `enclosing`), or
+    /// after it (i.e. from `follow`), if it can match without consuming input.
+    fn predict(&self, enclosing: IStr, rule: IRule, follow: &Follow) -> Follow {
+        let first = self.first.first(self.cx, enclosing, rule);
+        let mut predict = Follow {
+            eats: first.eats.into_iter().map(|(_, eat)| eat).collect(),
+            end: false,
+        };
+        if first.nullable {
+            predict.extend(follow.clone());
+        }
+        predict
+    }
+
+    /// Record the choice between all the `cases` (predicted by `predict`)
+    /// as a conflict, if any patterns which can start matching the same input
+    /// (or the end of the input) predict more than one of them, with `what`
+    /// describing the choice (given the cases).
+    fn choice(&self, cases: &[Follow], what: impl Fn(usize, usize) -> String, walk: &mut Walk) {
+        let mut end = None;
+        for (i, case) in cases.iter().enumerate() {
+            for &eat in &case.eats {
+                // Only the closest earlier case is reported, preferring
+                // the same pattern to any other it overlaps with.
+                let earlier = cases[..i].iter().enumerate().rev().find_map(|(j, other)| {
+                    other
+                        .eats
+                        .get(&eat)
+                        .or_else(|| other.eats.iter().find(|&&other| self.overlaps(other, eat)))
+                        .map(|&other| (j, other))
+                });
+                if let Some((j, other)) = earlier {
+                    let pats = if other == eat {
+                        self.describe(eat)
+                    } else {
+                        format!("{} and {}", self.describe(other), self.describe(eat))
+                    };
+                    walk.conflicts.push(format!("{} can {}", pats, what(j, i)));
+                }
+            }
+            if case.end {
+                if let Some(j) = end.replace(i) {
+                    walk.conflicts
+                        .push(format!("the end of the input can {}", what(j, i)));
+                }
+            }
+        }
+    }
+
+    /// Find all the calls in `rule` (and what can follow each of them, given
+    /// `follow`, which can follow `rule`), and all conflicts (for LL(1)).
+    fn walk(&self, enclosing: IStr, rule: IRule, follow: &Follow, walk: &mut Walk) {
+        match self.cx[rule] {
+            Rule::Empty | Rule::Eat(_) => {}
+            Rule::Call(r) => walk.calls.push((r, follow.clone())),
+            Rule::Concat([left, right]) => {
+                self.walk(enclosing, right, follow, walk);
+                let follow_left = self.predict(enclosing, right, follow);
+                self.walk(enclosing, left, &follow_left, walk);
+            }
+            Rule::Or(ref cases) => {
+                let predicts: Vec<_> = cases
+                    .iter()
+                    .map(|&case| self.predict(enclosing, case, follow))
+                    .collect();
+                self.choice(
+                    &predicts,
+                    |a, b| {
+                        format!(
+                            "start both alternatives {} and {} (of {})",
+                            a + 1,
+                            b + 1,
+                            cases.len()
+                        )
+                    },
+                    walk,
+                );
+                for &case in cases {
+                    self.walk(enclosing, case, follow, walk);
+                }
+            }
+            Rule::Opt(inner) => {
+                let predicts = [self.predict(enclosing, inner, follow), follow.clone()];
+                self.choice(
+                    &predicts,
+                    |_, _| "either start or follow an optional rule".to_string(),
+                    walk,
+                );
+                self.walk(enclosing, inner, follow, walk);
+            }
+            Rule::RepeatMany(elem, sep) | Rule::RepeatMore(elem, sep) => {
+                // After each element, there can be another iteration (starting
+                // with the separator, if any), or whatever follows `rule`.
+                let next = sep.map_or(elem, |(sep, _)| sep);
+                let predict_next = self.predict(enclosing, next, follow);
+                let mut follow_elem = predict_next.clone();
+                follow_elem.extend(follow.clone());
+                self.choice(
+                    &[predict_next, follow.clone()],
+                    |_, _| "either continue or follow a repetition".to_string(),
+                    walk,
+                );
+                if let Rule::RepeatMany(..) = self.cx[rule] {
+                    self.choice(
+                        &[self.predict(enclosing, elem, &follow_elem), follow.clone()],
+                        |_, _| "either start or follow a repetition".to_string(),
+                        walk,
+                    );
+                }
+                self.walk(enclosing, elem, &follow_elem, walk);
+                if let Some((sep, kind)) = sep {
+                    let predict_elem = self.predict(enclosing, elem, &follow_elem);
+                    let mut follow_sep = predict_elem.clone();
+                    if let SepKind::Trailing = kind {
+                        follow_sep.extend(follow.clone());
+                        self.choice(
+                            &[predict_elem, follow.clone()],
+                            |_, _| "either continue or follow a trailing separator".to_string(),
+                            walk,
+                        );
+                    }
+                    self.walk(enclosing, sep, &follow_sep, walk);
+                }
+            }
+        }
+    }
+
+    /// All the named rules `rule` (found in `enclosing`) calls, or, if `left`,
+    /// only those that can be called before `rule` has consumed any input.
+    fn calls(&self, enclosing: IStr, rule: IRule, left: bool) -> IndexSet<IStr> {
+        let nullable = |rule| self.first.first(self.cx, enclosing, rule).nullable;
+        match self.cx[rule] {
+            Rule::Empty | Rule::Eat(_) => IndexSet::new(),
+            Rule::Call(r) => Some(r).into_iter().collect(),
+            Rule::Concat([left_rule, right_rule]) => {
+                let mut calls = self.calls(enclosing, left_rule, left);
+                if !left || nullable(left_rule) {
+                    calls.extend(self.calls(enclosing, right_rule, left));
+                }
+                calls
+            }
+            Rule::Or(ref cases) => cases
+                .iter()
+                .flat_map(|&case| self.calls(enclosing, case, left))
+                .collect(),
+            Rule::Opt(inner) => self.calls(enclosing, inner, left),
+            Rule::RepeatMany(elem, sep) | Rule::RepeatMore(elem, sep) => {
+                let mut calls = self.calls(enclosing, elem, left);
+                if let Some((sep, _)) = sep {
+                    if !left || nullable(elem) {
+                        calls.extend(self.calls(enclosing, sep, left));
+                    }
+                }
+                calls
+            }
+        }
+    }
+
+    /// Whether `rule` can match any input at all, assuming only the named
+    /// rules in `productive` can (and that undefined ones can, as those
+    /// are reported by `Grammar::check` instead).
+    fn productive(&self, rule: IRule, productive: &IndexSet<IStr>) -> bool {
+        match self.cx[rule] {
+            Rule::Empty | Rule::Eat(_) => true,
+            Rule::Call(r) => productive.contains(&r) || !self.named.contains_key(&r),
+            Rule::Concat([left, right]) => {
+                self.productive(left, productive) && self.productive(right, productive)
+            }
+            Rule::Or(ref cases) => cases.iter().any(|&case| self.productive(case, productive)),
+            Rule::Opt(_) | Rule::RepeatMany(..) => true,
+            Rule::RepeatMore(elem, _) => self.productive(elem, productive),
+        }
+    }
+}
+
+/// Kinds of problems `Report::lints` can find in a grammar, which can be
+/// denied in grammars passed to `gll-macros` (e.g. `#![deny(unreachable)]`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Lint {
+    Unproductive,
+    Unreachable,
+    LeftRecursion,
+    NotLl1,
+}
+
+impl Lint {
+    pub const ALL: &'static [Self] = &[
+        Lint::Unproductive,
+        Lint::Unreachable,
+        Lint::LeftRecursion,
+        Lint::NotLl1,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::Unproductive => "unproductive",
+            Lint::Unreachable => "unreachable",
+            Lint::LeftRecursion => "left_recursion",
+            Lint::NotLl1 => "not_ll1",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|l| l.name() == name)
+    }
+}
+
+/// A problem found by `Report::lints`, in the rule named `rule`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Finding {
+    pub lint: Lint,
+    pub rule: String,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} [{}]", self.rule, self.message, self.lint.name())
+    }
+}
+
+impl Report {
+    /// All the problems found, as `Finding`s of the various `Lint`s
+    /// (e.g. for build scripts to print as `cargo:warning=...` lines).
+    pub fn lints(&self) -> Vec<Finding> {
+        let mut findings = vec![];
+        let mut add = |lint, rule: &str, message: String| {
+            findings.push(Finding {
+                lint,
+                rule: rule.to_string(),
+                message,
+            })
+        };
+        for rule in &self.unproductive {
+            add(
+                Lint::Unproductive,
+                rule,
+                "can't match any input".to_string(),
+            );
+        }
+        for rule in &self.unreachable {
+            add(
+                Lint::Unreachable,
+                rule,
+                "isn't used by any start rule".to_string(),
+            );
+        }
+        for cycle in &self.left_recursive_cycles {
+            add(
+                Lint::LeftRecursion,
+                &cycle[0],
+                format!("is left-recursive ({} -> {})", cycle.join(" -> "), cycle[0]),
+            );
+        }
+        for (name, rule) in &self.rules {
+            if !rule.ll1_conflicts.is_empty() {
+                add(
+                    Lint::NotLl1,
+                    name,
+                    format!("isn't LL(1): {}", rule.ll1_conflicts.join("; ")),
+                );
+            }
+        }
+        findings
+    }
+
+    /// Print all the findings (see `lints`) as `cargo:warning=...` lines,
+    /// for build scripts, except for those of the `deny` lints, which are
+    /// returned as errors instead (e.g. to fail the build with).
+    pub fn emit_cargo_warnings(&self, deny: &[Lint]) -> Result<(), Vec<Finding>> {
+        let (denied, warnings): (Vec<_>, Vec<_>) = self
+            .lints()
+            .into_iter()
+            .partition(|finding| deny.contains(&finding.lint));
+        for finding in warnings {
+            println!("cargo:warning={}", finding);
+        }
+        if denied.is_empty() {
+            Ok(())
+        } else {
+            Err(denied)
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, rule) in &self.rules {
+            writeln!(f, "{}:", name)?;
+            if rule.nullable {
+                writeln!(f, "    nullable")?;
+            }
+            writeln!(f, "    FIRST: {}", rule.first.join(", "))?;
+            let mut follow = rule.follow.clone();
+            if rule.follow_end {
+                follow.push("end of input".to_string());
+            }
+            writeln!(f, "    FOLLOW: {}", follow.join(", "))?;
+            if rule.ll1 {
+                writeln!(f, "    LL(1)")?;
+            } else {
+                writeln!(f, "    not LL(1)")?;
+                for conflict in &rule.ll1_conflicts {
+                    writeln!(f, "        {}", conflict)?;
+                }
+            }
+        }
+        for cycle in &self.left_recursive_cycles {
+            writeln!(
+                f,
+                "left-recursive cycle: {} -> {}",
+                cycle.join(" -> "),
+                cycle[0]
+            )?;
+        }
+        if !self.unreachable.is_empty() {
+            writeln!(f, "unreachable: {}", self.unreachable.join(", "))?;
+        }
+        if !self.unproductive.is_empty() {
+            writeln!(f, "unproductive: {}", self.unproductive.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// An input which was parsed in more than one way, as found by the
+/// generated `find_ambiguity` methods (e.g. when given `sentences`).
+#[derive(Clone, Debug)]
+pub struct AmbiguityWitness<I> {
+    pub input: I,
+    /// The ambiguous named rule, i.e. either the one being parsed,
+    /// or one parsed as part of it, with more than one derivation.
+    pub rule: String,
+    /// Where in `input` the ambiguous `rule` was found, through `Debug`.
+    pub source_info: String,
+    /// All the ways `rule` was parsed (i.e. at least two), through `Debug`.
+    pub derivations: Vec<String>,
+}
+
+impl<I: fmt::Debug> fmt::Display for AmbiguityWitness<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is ambiguous at {}, in {:?}:",
+            self.rule, self.source_info, self.input
+        )?;
+        for derivation in &self.derivations {
+            write!(f, "\n    {}", derivation)?;
+        }
+        Ok(())
+    }
+}
+
+/// All the inputs, up to `max_len` characters long, which `rule` can match
+/// in a scannerless grammar, shortest first, e.g. for the generated
+/// `find_ambiguity` methods, to find the shortest ambiguous input.
+///
+/// Character ranges and classes only contribute the characters they
+/// share with the rest of the grammar (i.e. characters from strings, and
+/// the bounds of ranges), or just one character, if there aren't any.
+/// The number of inputs grows exponentially with `max_len`, so it should
+/// be kept small (e.g. under 10, for grammars with many characters).
+pub fn sentences<S: AsRef<str> + Eq + Hash>(
+    cx: &scannerless::Context<S>,
+    grammar: &grammer::Grammar,
+    rule: IStr,
+    max_len: usize,
+) -> Vec<String> {
+    let named = &grammar.rules;
+
+    // Find all the rules used by `rule`, and all the patterns they contain.
+    let mut used = IndexSet::new();
+    let mut eats = IndexSet::new();
+    let mut stack = vec![rule];
+    while let Some(name) = stack.pop() {
+        if used.insert(name) {
+            if let Some(rule) = named.get(&name) {
+                collect_calls_and_eats(cx, rule.rule, &mut stack, &mut eats);
+            }
+        }
+    }
+
+    let mut chars = BTreeSet::new();
+    for &eat in &eats {
+        match cx[eat] {
+            Rule::Eat(scannerless::Pat::String(ref s)) => chars.extend(s.as_ref().chars()),
+            Rule::Eat(scannerless::Pat::CaseInsensitive(ref s)) => {
+                let s = s.as_ref();
+                chars.extend(s.chars().chain(s.to_lowercase().chars()));
+                chars.extend(s.to_uppercase().chars());
+            }
+            Rule::Eat(scannerless::Pat::Range(start, end)) => chars.extend(vec![start, end]),
+            Rule::Eat(scannerless::Pat::Class(ref class)) => {
+                for &item in &class.items {
+                    match item {
+                        ClassItem::Range(start, end) => chars.extend(vec![start, end]),
+                        ClassItem::Property(property) => {
+                            chars.extend(any_char().find(|&c| property.contains(c)))
+                        }
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+    let mut sentences = Sentences {
+        cx,
+        named,
+        samples: eats
+            .iter()
+            .map(|&eat| {
+                let samples: IndexSet<String> = match cx[eat] {
+                    Rule::Eat(scannerless::Pat::String(ref s)) => {
+                        Some(s.as_ref().to_string()).into_iter().collect()
+                    }
+                    Rule::Eat(scannerless::Pat::CaseInsensitive(ref s)) => {
+                        let s = s.as_ref();
+                        vec![s.to_string(), s.to_lowercase(), s.to_uppercase()]
+                            .into_iter()
+                            .collect()
+                    }
+                    Rule::Eat(scannerless::Pat::Range(start, end)) => chars
+                        .iter()
+                        .filter(|&&c| start <= c && c <= end)
+                        .map(|c| c.to_string())
+                        .collect(),
+                    Rule::Eat(scannerless::Pat::Class(ref class)) => {
+                        let mut samples: IndexSet<String> = chars
+                            .iter()
+                            .filter(|&&c| class.contains(c))
+                            .map(|c| c.to_string())
+                            .collect();
+                        if samples.is_empty() {
+                            samples
+                                .extend(any_char().find(|&c| class.contains(c)).map(String::from));
+                        }
+                        samples
+                    }
+                    _ => unreachable!(),
+                };
+                (eat, samples)
+            })
+            .collect(),
+        named_inputs: HashMap::new(),
+        inputs: HashMap::new(),
+    };
+
+    // Rules can be (mutually) recursive, even without consuming any input,
+    // so, for each length, keep recomputing the inputs each rule can match,
+    // until nothing changes anymore (which ends, as they can only grow).
+    for len in 0..=max_len {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            // NOTE inputs of this length may have been computed
+            // from the previous (incomplete) inputs of the named rules.
+            sentences.inputs.retain(|&(_, l), _| l < len);
+            for &name in &used {
+                if let Some(rule) = named.get(&name) {
+                    let inputs = sentences.inputs(rule.rule, len);
+                    let old = sentences.named_inputs.entry((name, len)).or_default();
+                    if inputs.len() > old.len() {
+                        *old = inputs;
+                        changed = true;
+                    }
+                }
+            }
+        }
+    }
+
+    (0..=max_len)
+        .flat_map(|len| {
+            let mut inputs: Vec<_> = sentences
+                .named_inputs
+                .get(&(rule, len))
+                .map_or(vec![], |inputs| inputs.iter().cloned().collect());
+            inputs.sort();
+            inputs
+        })
+        .collect()
+}
+
+/// All characters, starting with the printable ASCII ones.
+fn any_char() -> impl Iterator<Item = char> {
+    (' '..='~').chain((0..=char::MAX as u32).filter_map(char::from_u32))
+}
+
+fn collect_calls_and_eats<Pat: Eq + Hash>(
+    cx: &Context<Pat>,
+    rule: IRule,
+    calls: &mut Vec<IStr>,
+    eats: &mut IndexSet<IRule>,
+) {
+    match cx[rule] {
+        Rule::Empty => {}
+        Rule::Eat(_) => {
+            eats.insert(rule);
+        }
+        Rule::Call(r) => calls.push(r),
+        Rule::Concat([left, right]) => {
+            collect_calls_and_eats(cx, left, calls, eats);
+            collect_calls_and_eats(cx, right, calls, eats);
+        }
+        Rule::Or(ref cases) => {
+            for &case in cases {
+                collect_calls_and_eats(cx, case, calls, eats);
+            }
+        }
+        Rule::Opt(inner) => collect_calls_and_eats(cx, inner, calls, eats),
+        Rule::RepeatMany(elem, sep) | Rule::RepeatMore(elem, sep) => {
+            collect_calls_and_eats(cx, elem, calls, eats);
+            if let Some((sep, _)) = sep {
+                collect_calls_and_eats(cx, sep, calls, eats);
+            }
+        }
+    }
+}
+
+struct Sentences<'a, S> {
+    cx: &'a scannerless::Context<S>,
+    named: &'a IndexMap<IStr, RuleWithNamedFields>,
+    /// The inputs each `Eat` rule can match.
+    samples: HashMap<IRule, IndexSet<String>>,
+    /// The inputs of each length each named rule can match, found so far.
+    named_inputs: HashMap<(IStr, usize), Rc<IndexSet<String>>>,
+    /// The inputs of each length each rule can match, given `named_inputs`.
+    inputs: HashMap<(IRule, usize), Rc<IndexSet<String>>>,
+}
+
+impl<S: AsRef<str> + Eq + Hash> Sentences<'_, S> {
+    /// All the inputs `len` characters long which `rule` can match.
+    fn inputs(&mut self, rule: IRule, len: usize) -> Rc<IndexSet<String>> {
+        if let Some(inputs) = self.inputs.get(&(rule, len)) {
+            return inputs.clone();
+        }
+        let cx = self.cx;
+        let mut inputs = IndexSet::new();
+        match cx[rule] {
+            Rule::Empty => {
+                if len == 0 {
+                    inputs.insert(String::new());
+                }
+            }
+            Rule::Eat(_) => inputs.extend(
+                self.samples[&rule]
+                    .iter()
+                    .filter(|s| s.chars().count() == len)
+                    .cloned(),
+            ),
+            Rule::Call(r) => {
+                if let Some(named_inputs) = self.named_inputs.get(&(r, len)) {
+                    inputs.extend(named_inputs.iter().cloned());
+                }
+            }
+            Rule::Concat([left, right]) => {
+                for left_len in 0..=len {
+                    inputs.extend(self.concat(left, right, left_len, len - left_len));
+                }
+            }
+            Rule::Or(ref cases) => {
+                for &case in cases {
+                    inputs.extend(self.inputs(case, len).iter().cloned());
+                }
+            }
+            Rule::Opt(inner) => {
+                if len == 0 {
+                    inputs.insert(String::new());
+                }
+                inputs.extend(self.inputs(inner, len).iter().cloned());
+            }
+            Rule::RepeatMany(elem, sep) | Rule::RepeatMore(elem, sep) => {
+                if let (Rule::RepeatMany(..), 0) = (&cx[rule], len) {
+                    inputs.insert(String::new());
+                }
+
+                // The inputs of each length `X+ % S` can match, computed
+                // from the shorter ones, as `X` or `X S {X+ % S}`.
+                let mut more: Vec<IndexSet<String>> = vec![];
+                for more_len in 0..=len {
+                    let mut more_inputs = (*self.inputs(elem, more_len)).clone();
+                    for prefix_len in 1..=more_len {
+                        for elem_len in 0..=prefix_len {
+                            let sep_len = prefix_len - elem_len;
+                            let prefixes = match sep {
+                                Some((sep, _)) => self.concat(elem, sep, elem_len, sep_len),
+                                None if sep_len == 0 => (*self.inputs(elem, elem_len)).clone(),
+                                None => continue,
+                            };
+                            for prefix in &prefixes {
+                                for rest in &more[more_len - prefix_len] {
+                                    more_inputs.insert(prefix.clone() + rest);
+                                }
+                            }
+                        }
+                    }
+                    more.push(more_inputs);
+                }
+                inputs.extend(more[len].iter().cloned());
+
+                // `X+ %% S` can also end in `S`.
+                if let Some((sep, SepKind::Trailing)) = sep {
+                    for more_len in 0..=len {
+                        for suffix in self.inputs(sep, len - more_len).iter() {
+                            for prefix in &more[more_len] {
+                                inputs.insert(prefix.clone() + suffix);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let inputs = Rc::new(inputs);
+        self.inputs.insert((rule, len), inputs.clone());
+        inputs
+    }
+
+    /// All the inputs `left` followed by `right` can match, with
+    /// `left` matching `left_len` characters, and `right` the rest.
+    fn concat(
+        &mut self,
+        left: IRule,
+        right: IRule,
+        left_len: usize,
+        right_len: usize,
+    ) -> IndexSet<String> {
+        let lefts = self.inputs(left, left_len);
+        if lefts.is_empty() {
+            return IndexSet::new();
+        }
+        let rights = self.inputs(right, right_len);
+        lefts
+            .iter()
+            .flat_map(|left| rights.iter().map(move |right| left.clone() + right))
+            .collect()
+    }
+}
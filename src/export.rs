@@ -0,0 +1,837 @@
+//! Exporting scannerless grammars to the formats of other parsing tools,
+//! i.e. W3C EBNF (as used in the XML specification), ANTLR 4 and tree-sitter.
+//! Anything which can't be expressed exactly in a format is approximated
+//! (or left out) with a warning, describing what changed.
+
+use crate::analysis;
+use crate::scannerless::{self, CaseInsensitive, ClassItem, UnicodeProperty};
+use grammer::context::IRule;
+use grammer::rule::{Rule, SepKind};
+use indexmap::{IndexMap, IndexSet};
+use std::hash::Hash;
+
+/// The source of an exported grammar, along with warnings about anything
+/// which couldn't be expressed exactly (i.e. it may match different inputs).
+#[derive(Clone, Debug, Default)]
+pub struct Export {
+    pub source: String,
+    pub warnings: Vec<String>,
+}
+
+/// Export `grammar` to W3C EBNF, where fields can only be kept as comments.
+pub fn ebnf<S: AsRef<str> + Eq + Hash>(
+    cx: &scannerless::Context<S>,
+    grammar: &grammer::Grammar,
+) -> Export {
+    let mut exporter = Exporter::default();
+    let mut source = String::new();
+    for (name, expr) in lower_grammar(cx, grammar) {
+        exporter.rule = name.clone();
+        source += &name;
+        source += " ::= ";
+        match expr {
+            Expr::Alt(ref cases) => {
+                for (i, case) in cases.iter().enumerate() {
+                    if i > 0 {
+                        source += &format!("\n{:1$}| ", "", name.len() + 3);
+                    }
+                    source += &exporter.text(Syntax::Ebnf, case, ALT);
+                }
+            }
+            _ => source += &exporter.text(Syntax::Ebnf, &expr, ALT),
+        }
+        source += "\n";
+    }
+    exporter.finish(source)
+}
+
+/// Export `grammar` to an ANTLR 4 (combined) grammar named `name`, with
+/// parser rules (named like the original ones, but starting in lowercase,
+/// as ANTLR requires), and lexer rules (named `CHARS_*`) for character
+/// classes. Fields become labels, or alternative labels (e.g. `# Add`)
+/// for rules where every alternative is a field.
+pub fn antlr<S: AsRef<str> + Eq + Hash>(
+    cx: &scannerless::Context<S>,
+    grammar: &grammer::Grammar,
+    name: &str,
+) -> Export {
+    let mut exporter = Exporter::default();
+    let rules = lower_grammar(cx, grammar);
+
+    let antlr_names: IndexSet<_> = rules
+        .iter()
+        .map(|(rule, _)| antlr_rule_name(rule))
+        .collect();
+    if antlr_names.len() < rules.len() {
+        exporter.warn(
+            "some rules only differ in the case of their first letter, which ANTLR requires \
+             to be lowercase, so they end up with the same name"
+                .to_string(),
+        );
+    }
+    for cycle in analysis::analyze(cx, grammar, &[]).left_recursive_cycles {
+        if cycle.len() > 1 {
+            exporter.warn(format!(
+                "ANTLR doesn't support mutually left-recursive rules ({} -> {})",
+                cycle.join(" -> "),
+                cycle[0]
+            ));
+        }
+    }
+
+    let mut source = format!("grammar {};\n", name);
+    for (rule, expr) in &rules {
+        exporter.rule = rule.clone();
+        source += &format!("\n{}\n", antlr_rule_name(rule));
+        let cases = match expr {
+            Expr::Alt(cases) => &cases[..],
+            _ => std::slice::from_ref(expr),
+        };
+        let variants: Option<Vec<_>> = if cases.len() > 1 {
+            cases
+                .iter()
+                .map(|case| match case {
+                    Expr::Field(variant, inner) => Some((variant, inner)),
+                    _ => None,
+                })
+                .collect()
+        } else {
+            None
+        };
+        for (i, case) in cases.iter().enumerate() {
+            source += if i == 0 { "    : " } else { "    | " };
+            match variants {
+                Some(ref variants) => {
+                    let (variant, inner) = variants[i];
+                    // NOTE ANTLR generates e.g. `AtomContext` for both
+                    // an `Atom` alternative label and an `atom` rule.
+                    let mut label = variant.clone();
+                    if rules.iter().any(|(r, _)| r.eq_ignore_ascii_case(variant)) {
+                        label = format!("{}{}", rule, variant);
+                        exporter.warn(format!(
+                            "alternative label `{}` in rule `{}` clashes with a rule, \
+                             renamed to `{}`",
+                            variant, rule, label
+                        ));
+                    }
+                    source += &exporter.text(Syntax::Antlr, inner, ALT);
+                    source += &format!(" # {}", label);
+                }
+                None => source += &exporter.text(Syntax::Antlr, case, ALT),
+            }
+            source += "\n";
+        }
+        source += "    ;\n";
+    }
+
+    if !exporter.tokens.is_empty() {
+        exporter.warn(
+            "ANTLR splits the input into tokens before parsing, and character classes \
+             (the `CHARS_*` tokens) never match characters which start a literal, \
+             or a longer token, so the grammar may accept less inputs"
+                .to_string(),
+        );
+        source += "\n";
+        for (set, token) in &exporter.tokens {
+            source += &format!("{} : {} ;\n", token, set);
+        }
+    }
+    exporter.finish(source)
+}
+
+/// Export `grammar` to a tree-sitter `grammar.js`, for a language named
+/// `name`, starting at the first rule, and without any (implicit) `extras`.
+pub fn tree_sitter<S: AsRef<str> + Eq + Hash>(
+    cx: &scannerless::Context<S>,
+    grammar: &grammer::Grammar,
+    name: &str,
+) -> Export {
+    let mut exporter = Exporter::default();
+    let report = analysis::analyze(cx, grammar, &[]);
+
+    let mut source = format!(
+        "module.exports = grammar({{\n  name: {},\n\n  extras: $ => [],\n\n  rules: {{\n",
+        js_string(name)
+    );
+    for (i, (rule, expr)) in lower_grammar(cx, grammar).into_iter().enumerate() {
+        if i > 0 && report.rules[&rule].nullable {
+            exporter.warn(format!(
+                "`{}` can match an empty input, which tree-sitter only allows for the start rule",
+                rule
+            ));
+        }
+        exporter.rule = rule.clone();
+        source += &format!("    {}: $ => ", rule);
+        match expr {
+            Expr::Alt(ref cases) => {
+                source += "choice(\n";
+                for case in cases {
+                    source += &format!("      {},\n", exporter.tree_sitter(case));
+                }
+                source += "    ),\n";
+            }
+            _ => source += &format!("{},\n", exporter.tree_sitter(&expr)),
+        }
+    }
+    source += "  },\n});\n";
+    exporter.finish(source)
+}
+
+/// A rule, simplified to what all the formats have in common.
+enum Expr {
+    Empty,
+    Str(String),
+    Class(Class),
+    Call(String),
+    Seq(Vec<Expr>),
+    Alt(Vec<Expr>),
+    Opt(Box<Expr>),
+    Repeat {
+        elem: Box<Expr>,
+        sep: Option<(Box<Expr>, SepKind)>,
+        /// Whether this is `X+` (instead of `X*`).
+        more: bool,
+    },
+    Field(String, Box<Expr>),
+}
+
+/// A single character in any of `ranges` or `properties`
+/// (or, if `negated`, one in none of them).
+struct Class {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+    properties: Vec<UnicodeProperty>,
+}
+
+fn lower_grammar<S: AsRef<str> + Eq + Hash>(
+    cx: &scannerless::Context<S>,
+    grammar: &grammer::Grammar,
+) -> Vec<(String, Expr)> {
+    grammar
+        .rules
+        .iter()
+        .map(|(&name, rule)| {
+            let mut fields: IndexMap<Vec<usize>, Vec<String>> = IndexMap::new();
+            for (&field, paths) in &rule.fields {
+                for path in &paths.0 {
+                    fields
+                        .entry(path.clone())
+                        .or_default()
+                        .push(cx[field].to_string());
+                }
+            }
+            (
+                cx[name].to_string(),
+                lower(cx, rule.rule, &mut vec![], &fields),
+            )
+        })
+        .collect()
+}
+
+/// Lower `rule`, found at `path` in a named rule with `fields`.
+fn lower<S: AsRef<str> + Eq + Hash>(
+    cx: &scannerless::Context<S>,
+    rule: IRule,
+    path: &mut Vec<usize>,
+    fields: &IndexMap<Vec<usize>, Vec<String>>,
+) -> Expr {
+    let child = |i, rule, path: &mut Vec<usize>| {
+        path.push(i);
+        let expr = lower(cx, rule, path, fields);
+        path.pop();
+        expr
+    };
+    let mut expr = match cx[rule] {
+        Rule::Empty => Expr::Empty,
+        Rule::Eat(ref pat) => lower_pat(pat),
+        Rule::Call(r) => Expr::Call(cx[r].to_string()),
+        Rule::Concat([left, right]) => seq(vec![child(0, left, path), child(1, right, path)]),
+        Rule::Or(ref cases) => alt(cases
+            .iter()
+            .enumerate()
+            .map(|(i, &case)| child(i, case, path))
+            .collect()),
+        Rule::Opt(inner) => opt(child(0, inner, path)),
+        // NOTE fields can't be found inside repetitions.
+        Rule::RepeatMany(elem, sep) | Rule::RepeatMore(elem, sep) => {
+            let no_fields = &IndexMap::new();
+            Expr::Repeat {
+                elem: Box::new(lower(cx, elem, &mut vec![], no_fields)),
+                sep: sep
+                    .map(|(sep, kind)| (Box::new(lower(cx, sep, &mut vec![], no_fields)), kind)),
+                more: match cx[rule] {
+                    Rule::RepeatMore(..) => true,
+                    _ => false,
+                },
+            }
+        }
+    };
+    if let Some(names) = fields.get(&path[..]) {
+        for name in names.iter().rev() {
+            expr = Expr::Field(name.clone(), Box::new(expr));
+        }
+    }
+    expr
+}
+
+fn lower_pat<S: AsRef<str>>(pat: &scannerless::Pat<S>) -> Expr {
+    match pat {
+        scannerless::Pat::String(s) if s.as_ref().is_empty() => Expr::Empty,
+        scannerless::Pat::String(s) => Expr::Str(s.as_ref().to_string()),
+        scannerless::Pat::CaseInsensitive(s) => {
+            // Characters with more than one case become character classes.
+            let mut exprs = vec![];
+            let mut plain = String::new();
+            for c in s.as_ref().chars() {
+                let mut cases = IndexSet::new();
+                cases.insert(c);
+                let others: [Vec<char>; 2] =
+                    [c.to_lowercase().collect(), c.to_uppercase().collect()];
+                for other in &others {
+                    if let [other] = other[..] {
+                        if CaseInsensitive::chars_eq(c, other) {
+                            cases.insert(other);
+                        }
+                    }
+                }
+                if cases.len() == 1 {
+                    plain.push(c);
+                    continue;
+                }
+                if !plain.is_empty() {
+                    exprs.push(Expr::Str(plain.split_off(0)));
+                }
+                exprs.push(Expr::Class(Class {
+                    negated: false,
+                    ranges: cases.into_iter().map(|c| (c, c)).collect(),
+                    properties: vec![],
+                }));
+            }
+            if !plain.is_empty() {
+                exprs.push(Expr::Str(plain));
+            }
+            seq(exprs)
+        }
+        &scannerless::Pat::Range(start, end) => Expr::Class(Class {
+            negated: false,
+            ranges: vec![(start, end)],
+            properties: vec![],
+        }),
+        scannerless::Pat::Class(class) => Expr::Class(Class {
+            negated: class.negated,
+            ranges: class
+                .items
+                .iter()
+                .filter_map(|&item| match item {
+                    ClassItem::Range(start, end) => Some((start, end)),
+                    ClassItem::Property(_) => None,
+                })
+                .collect(),
+            properties: class
+                .items
+                .iter()
+                .filter_map(|&item| match item {
+                    ClassItem::Range(..) => None,
+                    ClassItem::Property(property) => Some(property),
+                })
+                .collect(),
+        }),
+    }
+}
+
+fn seq(exprs: Vec<Expr>) -> Expr {
+    let mut flat = vec![];
+    for expr in exprs {
+        match expr {
+            Expr::Empty => {}
+            Expr::Seq(exprs) => flat.extend(exprs),
+            expr => flat.push(expr),
+        }
+    }
+    if flat.len() == 1 {
+        flat.pop().unwrap()
+    } else if flat.is_empty() {
+        Expr::Empty
+    } else {
+        Expr::Seq(flat)
+    }
+}
+
+/// Alternation, with empty cases turned into an optional.
+fn alt(cases: Vec<Expr>) -> Expr {
+    let len = cases.len();
+    let mut cases: Vec<_> = cases
+        .into_iter()
+        .filter(|case| match case {
+            Expr::Empty => false,
+            _ => true,
+        })
+        .collect();
+    let nullable = cases.len() < len;
+    let expr = if cases.len() == 1 {
+        cases.pop().unwrap()
+    } else if cases.is_empty() {
+        Expr::Empty
+    } else {
+        Expr::Alt(cases)
+    };
+    if nullable {
+        opt(expr)
+    } else {
+        expr
+    }
+}
+
+fn opt(expr: Expr) -> Expr {
+    match expr {
+        Expr::Empty | Expr::Opt(_) => expr,
+        expr => Expr::Opt(Box::new(expr)),
+    }
+}
+
+// Precedence levels for `Exporter::text`, from loosest to tightest.
+const ALT: u8 = 0;
+const SEQ: u8 = 1;
+const POSTFIX: u8 = 2;
+const ATOM: u8 = 3;
+
+#[derive(Copy, Clone)]
+enum Syntax {
+    Ebnf,
+    Antlr,
+}
+
+#[derive(Default)]
+struct Exporter {
+    /// The rule being exported, for warnings.
+    rule: String,
+    warnings: IndexSet<String>,
+    /// The ANTLR lexer rules for character classes, by the set they match.
+    tokens: IndexMap<String, String>,
+}
+
+impl Exporter {
+    fn warn(&mut self, warning: String) {
+        self.warnings.insert(warning);
+    }
+
+    fn finish(self, source: String) -> Export {
+        Export {
+            source,
+            warnings: self.warnings.into_iter().collect(),
+        }
+    }
+
+    /// The EBNF or ANTLR syntax for `expr`, in parentheses,
+    /// if its precedence is lower than `min_prec`.
+    fn text(&mut self, syntax: Syntax, expr: &Expr, min_prec: u8) -> String {
+        let (text, prec) = match (syntax, expr) {
+            (Syntax::Ebnf, Expr::Empty) => {
+                self.warn(format!(
+                    "W3C EBNF can't express matching an empty input, \
+                     so `\"\"` is used in rule `{}`",
+                    self.rule
+                ));
+                ("\"\"".to_string(), ATOM)
+            }
+            (Syntax::Antlr, Expr::Empty) => (String::new(), SEQ),
+            (Syntax::Ebnf, Expr::Str(s)) => ebnf_string(s),
+            (Syntax::Antlr, Expr::Str(s)) => (antlr_string(s), ATOM),
+            (Syntax::Ebnf, Expr::Class(class)) => self.ebnf_class(class),
+            (Syntax::Antlr, Expr::Class(class)) => {
+                let set = self.antlr_set(class);
+                let len = self.tokens.len();
+                let token = self
+                    .tokens
+                    .entry(set)
+                    .or_insert_with(|| format!("CHARS_{}", len));
+                (token.clone(), ATOM)
+            }
+            (Syntax::Ebnf, Expr::Call(name)) => (name.clone(), ATOM),
+            (Syntax::Antlr, Expr::Call(name)) => (antlr_rule_name(name), ATOM),
+            (_, Expr::Seq(exprs)) => {
+                let exprs: Vec<_> = exprs
+                    .iter()
+                    .map(|expr| self.text(syntax, expr, SEQ))
+                    .collect();
+                (exprs.join(" "), SEQ)
+            }
+            (_, Expr::Alt(cases)) => {
+                let cases: Vec<_> = cases
+                    .iter()
+                    .map(|case| self.text(syntax, case, ALT))
+                    .collect();
+                (cases.join(" | "), ALT)
+            }
+            (_, Expr::Opt(inner)) => (self.text(syntax, inner, ATOM) + "?", POSTFIX),
+            (_, Expr::Repeat { elem, sep, more }) => {
+                let elem = self.text(syntax, elem, ATOM);
+                let sep = sep
+                    .as_ref()
+                    .map(|(sep, kind)| (self.text(syntax, sep, ATOM), *kind));
+                repeat(elem, sep, *more)
+            }
+            (Syntax::Ebnf, Expr::Field(name, inner)) => {
+                self.warn(
+                    "W3C EBNF has no way to label parts of rules, \
+                     so fields are only kept as comments"
+                        .to_string(),
+                );
+                return format!("/* {}: */ {}", name, self.text(syntax, inner, min_prec));
+            }
+            (Syntax::Antlr, Expr::Field(name, inner)) => {
+                match self.antlr_labeled(name, inner, false) {
+                    Some(labeled) => labeled,
+                    None => {
+                        self.warn(format!(
+                            "field `{}` in rule `{}` can't be an ANTLR label, \
+                             as those can only be on single tokens or rules, and was left out",
+                            name, self.rule
+                        ));
+                        return self.text(syntax, inner, min_prec);
+                    }
+                }
+            }
+        };
+        if prec < min_prec {
+            format!("({})", text)
+        } else {
+            text
+        }
+    }
+
+    fn ebnf_class(&mut self, class: &Class) -> (String, u8) {
+        let ranges: String = class
+            .ranges
+            .iter()
+            .map(|&(start, end)| {
+                if start == end {
+                    ebnf_char(start)
+                } else {
+                    format!("{}-{}", ebnf_char(start), ebnf_char(end))
+                }
+            })
+            .collect();
+        let properties: Vec<_> = class
+            .properties
+            .iter()
+            .map(|property| {
+                self.warn(format!(
+                    "W3C EBNF has no Unicode properties, so `{0}` refers \
+                     to a rule named `{0}` instead, which has to be defined",
+                    property.name()
+                ));
+                property.name()
+            })
+            .collect();
+        const ANY: &str = "[#x0-#x10FFFF]";
+
+        if !class.negated {
+            let mut cases: Vec<_> = properties.iter().map(|p| p.to_string()).collect();
+            if !ranges.is_empty() {
+                cases.insert(0, format!("[{}]", ranges));
+            }
+            return match cases.len() {
+                0 => {
+                    self.warn(format!(
+                        "a character class in rule `{}` can't match anything",
+                        self.rule
+                    ));
+                    (format!("({} - {})", ANY, ANY), ATOM)
+                }
+                1 => (cases.pop().unwrap(), ATOM),
+                _ => (cases.join(" | "), ALT),
+            };
+        }
+
+        let any_but_ranges = if ranges.is_empty() {
+            ANY.to_string()
+        } else {
+            format!("[^{}]", ranges)
+        };
+        match properties.len() {
+            0 => (any_but_ranges, ATOM),
+            1 => (format!("({} - {})", any_but_ranges, properties[0]), ATOM),
+            _ => (
+                format!("({} - ({}))", any_but_ranges, properties.join(" | ")),
+                ATOM,
+            ),
+        }
+    }
+
+    /// The ANTLR lexer set matching the same characters as `class`.
+    fn antlr_set(&mut self, class: &Class) -> String {
+        if class.ranges.is_empty() && class.properties.is_empty() {
+            if class.negated {
+                return ".".to_string();
+            }
+            self.warn(format!(
+                "a character class in rule `{}` can't match anything",
+                self.rule
+            ));
+        }
+        let mut set = String::new();
+        if class.negated {
+            set.push('~');
+        }
+        set.push('[');
+        for &(start, end) in &class.ranges {
+            set += &antlr_set_char(start);
+            if start != end {
+                set.push('-');
+                set += &antlr_set_char(end);
+            }
+        }
+        for &property in &class.properties {
+            set += &self.property_escape(property);
+        }
+        set.push(']');
+        set
+    }
+
+    /// `expr` with the ANTLR label `name` (or `name+=` if `list`),
+    /// if it's a single token or rule, or optional or repeated ones.
+    fn antlr_labeled(&mut self, name: &str, expr: &Expr, list: bool) -> Option<(String, u8)> {
+        let op = if list { "+=" } else { "=" };
+        match expr {
+            Expr::Str(_) | Expr::Class(_) | Expr::Call(_) => {
+                let elem = self.text(Syntax::Antlr, expr, ATOM);
+                Some((format!("{}{}{}", name, op, elem), ATOM))
+            }
+            Expr::Opt(inner) => {
+                let (inner, prec) = self.antlr_labeled(name, inner, list)?;
+                let inner = if prec < ATOM {
+                    format!("({})", inner)
+                } else {
+                    inner
+                };
+                Some((inner + "?", POSTFIX))
+            }
+            Expr::Repeat { elem, sep, more } => {
+                let (elem, prec) = self.antlr_labeled(name, elem, true)?;
+                let elem = if prec < ATOM {
+                    format!("({})", elem)
+                } else {
+                    elem
+                };
+                let sep = sep
+                    .as_ref()
+                    .map(|(sep, kind)| (self.text(Syntax::Antlr, sep, ATOM), *kind));
+                Some(repeat(elem, sep, *more))
+            }
+            _ => None,
+        }
+    }
+
+    fn tree_sitter(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::Empty => "blank()".to_string(),
+            Expr::Str(s) => js_string(s),
+            Expr::Class(class) => self.tree_sitter_regex(class),
+            Expr::Call(name) => format!("$.{}", name),
+            Expr::Seq(exprs) => {
+                let exprs: Vec<_> = exprs.iter().map(|expr| self.tree_sitter(expr)).collect();
+                format!("seq({})", exprs.join(", "))
+            }
+            Expr::Alt(cases) => {
+                let cases: Vec<_> = cases.iter().map(|case| self.tree_sitter(case)).collect();
+                format!("choice({})", cases.join(", "))
+            }
+            Expr::Opt(inner) => format!("optional({})", self.tree_sitter(inner)),
+            Expr::Repeat { elem, sep, more } => {
+                let elem = self.tree_sitter(elem);
+                let repeat = if *more { "repeat1" } else { "repeat" };
+                match sep {
+                    None => format!("{}({})", repeat, elem),
+                    Some((sep, kind)) => {
+                        let sep = self.tree_sitter(sep);
+                        let mut exprs =
+                            vec![elem.clone(), format!("repeat(seq({}, {}))", sep, elem)];
+                        if let SepKind::Trailing = kind {
+                            exprs.push(format!("optional({})", sep));
+                        }
+                        let list = format!("seq({})", exprs.join(", "));
+                        if *more {
+                            list
+                        } else {
+                            format!("optional({})", list)
+                        }
+                    }
+                }
+            }
+            Expr::Field(name, inner) => {
+                format!("field({}, {})", js_string(name), self.tree_sitter(inner))
+            }
+        }
+    }
+
+    fn tree_sitter_regex(&mut self, class: &Class) -> String {
+        if class.ranges.is_empty() && class.properties.is_empty() {
+            if class.negated {
+                return r"/[\u0000-\u{10FFFF}]/u".to_string();
+            }
+            self.warn(format!(
+                "a character class in rule `{}` can't match anything",
+                self.rule
+            ));
+        }
+        let mut regex = "/[".to_string();
+        if class.negated {
+            regex.push('^');
+        }
+        for &(start, end) in &class.ranges {
+            regex += &regex_char(start);
+            if start != end {
+                regex.push('-');
+                regex += &regex_char(end);
+            }
+        }
+        for &property in &class.properties {
+            regex += &self.property_escape(property);
+        }
+        regex += "]/";
+        // NOTE `\u{...}` and `\p{...}` need the `u` flag in JS.
+        if regex.contains(r"\u{") || regex.contains(r"\p{") {
+            regex.push('u');
+        }
+        regex
+    }
+
+    /// The `\p{...}` escape (for ANTLR and JS regexes) for `property`.
+    fn property_escape(&mut self, property: UnicodeProperty) -> String {
+        match property {
+            UnicodeProperty::Numeric => {
+                self.warn(
+                    "the `Numeric` property (i.e. `Numeric_Type != None`) isn't \
+                     supported by `\\p{...}`, so the `N` (Number) category is used instead"
+                        .to_string(),
+                );
+                r"\p{N}".to_string()
+            }
+            _ => format!(r"\p{{{}}}", property.name()),
+        }
+    }
+}
+
+/// The EBNF or ANTLR syntax for `X*` or `X+` (if `more`), with `X` being
+/// `elem`, and an optional separator, spelling out the repetition in full,
+/// e.g. `X+ % S` becomes `X (S X)*`.
+fn repeat(elem: String, sep: Option<(String, SepKind)>, more: bool) -> (String, u8) {
+    match sep {
+        None => (format!("{}{}", elem, if more { "+" } else { "*" }), POSTFIX),
+        Some((sep, kind)) => {
+            let mut text = format!("{} ({} {})*", elem, sep, elem);
+            if let SepKind::Trailing = kind {
+                text += &format!(" {}?", sep);
+            }
+            if more {
+                (text, SEQ)
+            } else {
+                (format!("({})?", text), POSTFIX)
+            }
+        }
+    }
+}
+
+fn antlr_rule_name(name: &str) -> String {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map(|c| c.to_lowercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+fn ebnf_char(c: char) -> String {
+    if c.is_ascii_alphanumeric() {
+        c.to_string()
+    } else {
+        format!("#x{:X}", c as u32)
+    }
+}
+
+/// `s` as an EBNF string, split up around characters which can't be in
+/// one (i.e. control characters and `"`), which W3C EBNF has no escapes for.
+fn ebnf_string(s: &str) -> (String, u8) {
+    let mut parts = vec![];
+    let mut plain = String::new();
+    for c in s.chars() {
+        if c != '"' && !c.is_control() {
+            plain.push(c);
+            continue;
+        }
+        if !plain.is_empty() {
+            parts.push(format!("\"{}\"", plain.split_off(0)));
+        }
+        parts.push(if c == '"' {
+            "'\"'".to_string()
+        } else {
+            format!("#x{:X}", c as u32)
+        });
+    }
+    if !plain.is_empty() {
+        parts.push(format!("\"{}\"", plain));
+    }
+    if parts.len() == 1 {
+        (parts.pop().unwrap(), ATOM)
+    } else {
+        (parts.join(" "), SEQ)
+    }
+}
+
+fn antlr_string(s: &str) -> String {
+    let mut out = "'".to_string();
+    for c in s.chars() {
+        match c {
+            '\'' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out += r"\n",
+            '\r' => out += r"\r",
+            '\t' => out += r"\t",
+            c if c.is_control() => out += &format!(r"\u{{{:X}}}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+    out
+}
+
+fn antlr_set_char(c: char) -> String {
+    match c {
+        '\\' | ']' | '-' => format!(r"\{}", c),
+        '\n' => r"\n".to_string(),
+        '\r' => r"\r".to_string(),
+        '\t' => r"\t".to_string(),
+        c if c.is_control() => format!(r"\u{{{:X}}}", c as u32),
+        c => c.to_string(),
+    }
+}
+
+fn js_string(s: &str) -> String {
+    let mut out = "'".to_string();
+    for c in s.chars() {
+        match c {
+            '\'' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\n' => out += r"\n",
+            '\r' => out += r"\r",
+            '\t' => out += r"\t",
+            c if c.is_control() => out += &format!(r"\u{:04X}", c as u32),
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+    out
+}
+
+fn regex_char(c: char) -> String {
+    match c {
+        '\\' | ']' | '[' | '-' | '^' | '/' => format!(r"\{}", c),
+        ' '..='~' => c.to_string(),
+        c if (c as u32) <= 0xffff => format!(r"\u{:04X}", c as u32),
+        c => format!(r"\u{{{:X}}}", c as u32),
+    }
+}
@@ -2,11 +2,14 @@ use crate::high::{type_lambda, ExistsL, PairL};
 use crate::input::{Input, Range};
 use crate::parse_node::ParseNodeShape;
 use indexing::{self, Container};
-use std::collections::{BTreeSet, HashMap, VecDeque};
+use indexmap::map::{Entry, IndexMap};
+use std::borrow::Cow;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::fmt;
 use std::hash::Hash;
 use std::io::{self, Write};
-use std::str;
+use std::ops::{Add, Mul};
+use std::{mem, slice, str};
 
 /// Objects capable of providing information about various parts of the grammar
 /// (mostly parse nodes and their substructure).
@@ -44,9 +47,94 @@ pub struct ParseForest<'i, G: GrammarReflector, I: Input> {
     pub grammar: G,
     // HACK(eddyb) `pub(crate)` only for `parser`.
     pub(crate) input: Container<'i, I::Container>,
-    pub(crate) possible_choices:
-        HashMap<ParseNode<'i, G::ParseNodeKind>, BTreeSet<G::ParseNodeKind>>,
-    pub(crate) possible_splits: HashMap<ParseNode<'i, G::ParseNodeKind>, BTreeSet<usize>>,
+    pub(crate) possible_choices: NodeMap<ParseNode<'i, G::ParseNodeKind>, G::ParseNodeKind>,
+    pub(crate) possible_splits: NodeMap<ParseNode<'i, G::ParseNodeKind>, usize>,
+}
+
+/// Compact map from parse nodes to sorted sets of values (e.g. choices or
+/// splits), filled in while parsing, acting as an arena for the nodes (their
+/// indices, in the order they were added, are dense ids), and keeping the
+/// common case of a node with only one value inline (i.e. not allocated).
+pub(crate) struct NodeMap<K, V> {
+    nodes: IndexMap<K, Values<V>>,
+}
+
+/// A sorted set of values, which only needs a `Vec` if there's more than one.
+enum Values<V> {
+    One(V),
+    Many(Vec<V>),
+}
+
+impl<V> Values<V> {
+    fn as_slice(&self) -> &[V] {
+        match self {
+            Values::One(value) => slice::from_ref(value),
+            Values::Many(values) => values,
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Default for NodeMap<K, V> {
+    fn default() -> Self {
+        NodeMap {
+            nodes: IndexMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V: Ord + Copy> NodeMap<K, V> {
+    pub(crate) fn insert(&mut self, key: K, value: V) {
+        match self.nodes.entry(key) {
+            Entry::Vacant(entry) => {
+                entry.insert(Values::One(value));
+            }
+            Entry::Occupied(mut entry) => {
+                let values = entry.get_mut();
+                if let Values::One(old) = *values {
+                    if old == value {
+                        return;
+                    }
+                    *values = Values::Many(vec![old]);
+                }
+                if let Values::Many(values) = values {
+                    if let Err(i) = values.binary_search(&value) {
+                        values.insert(i, value);
+                    }
+                }
+            }
+        }
+    }
+
+    pub(crate) fn get(&self, key: &K) -> Option<&[V]> {
+        self.nodes.get(key).map(Values::as_slice)
+    }
+
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &K> {
+        self.nodes.keys()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Remove the values for which `f(key, value)` returns `false`,
+    /// and the keys left without any values.
+    pub(crate) fn retain(&mut self, mut f: impl FnMut(&K, &V) -> bool) {
+        self.nodes.retain(|key, values| {
+            let mut kept: Vec<_> = values
+                .as_slice()
+                .iter()
+                .cloned()
+                .filter(|value| f(key, value))
+                .collect();
+            *values = match kept.len() {
+                0 => return false,
+                1 => Values::One(kept.pop().unwrap()),
+                _ => Values::Many(kept),
+            };
+            true
+        });
+    }
 }
 
 type_lambda! {
@@ -59,6 +147,63 @@ pub type OwnedParseForestAndNode<G, P, I> = ExistsL<PairL<ParseForestL<G, I>, Pa
 #[derive(Debug)]
 pub struct MoreThanOne;
 
+/// The number of distinct trees a node in a `ParseForest` encodes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TreeCount {
+    Finite(u64),
+    /// Too many trees to be counted in an `u64`.
+    Saturated,
+    /// Infinitely many trees, due to cycles (e.g. through nullable rules).
+    Infinite,
+}
+
+impl Add for TreeCount {
+    type Output = Self;
+    fn add(self, other: Self) -> Self {
+        match (self, other) {
+            (TreeCount::Finite(a), TreeCount::Finite(b)) => a
+                .checked_add(b)
+                .map_or(TreeCount::Saturated, TreeCount::Finite),
+            (a, b) => a.max(b),
+        }
+    }
+}
+
+impl Mul for TreeCount {
+    type Output = Self;
+    fn mul(self, other: Self) -> Self {
+        match (self, other) {
+            (TreeCount::Finite(0), _) | (_, TreeCount::Finite(0)) => TreeCount::Finite(0),
+            (TreeCount::Finite(a), TreeCount::Finite(b)) => a
+                .checked_mul(b)
+                .map_or(TreeCount::Saturated, TreeCount::Finite),
+            (a, b) => a.max(b),
+        }
+    }
+}
+
+/// A single tree, extracted out of a `ParseForest`.
+///
+/// The `children` follow the shape of `node`, i.e. there are none for
+/// `Opaque` (and empty `Opt`) nodes, one for `Alias`, `Choice` and `Opt`,
+/// and two (left and right) for `Split` nodes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseTree<'i, P> {
+    pub node: ParseNode<'i, P>,
+    pub children: Vec<ParseTree<'i, P>>,
+}
+
+// NOTE dropping trees recursively could overflow the stack,
+// for the deeply nested trees produced by long lists.
+impl<P> Drop for ParseTree<'_, P> {
+    fn drop(&mut self) {
+        let mut trees = mem::replace(&mut self.children, vec![]);
+        while let Some(mut tree) = trees.pop() {
+            trees.extend(tree.children.drain(..));
+        }
+    }
+}
+
 impl<'i, P, G, I: Input> ParseForest<'i, G, I>
 where
     // FIXME(eddyb) these shouldn't be needed, as they are bounds on
@@ -66,7 +211,7 @@ where
     P: fmt::Debug + Ord + Hash + Copy,
     G: GrammarReflector<ParseNodeKind = P>,
 {
-    pub fn input(&self, range: Range<'i>) -> &I::Slice {
+    pub fn input(&self, range: Range<'i>) -> Cow<'_, I::Slice> {
         I::slice(&self.input, range)
     }
 
@@ -77,7 +222,7 @@ where
     pub fn one_choice(&self, node: ParseNode<'i, P>) -> Result<ParseNode<'i, P>, MoreThanOne> {
         match self.grammar.parse_node_shape(node.kind) {
             ParseNodeShape::Choice => {
-                let choices = &self.possible_choices[&node];
+                let choices = self.possible_choices.get(&node).unwrap();
                 if choices.len() > 1 {
                     return Err(MoreThanOne);
                 }
@@ -119,7 +264,7 @@ where
     ) -> Result<(ParseNode<'i, P>, ParseNode<'i, P>), MoreThanOne> {
         match self.grammar.parse_node_shape(node.kind) {
             ParseNodeShape::Split(left_kind, right_kind) => {
-                let splits = &self.possible_splits[&node];
+                let splits = self.possible_splits.get(&node).unwrap();
                 if splits.len() > 1 {
                     return Err(MoreThanOne);
                 }
@@ -197,6 +342,372 @@ where
         }
     }
 
+    /// Count the distinct trees `node` encodes, without enumerating them.
+    pub fn count_trees(&self, node: ParseNode<'i, P>) -> TreeCount {
+        self.count_trees_cached(node, &mut HashMap::new())
+    }
+
+    // NOTE `None` in `cache` marks nodes still being counted, so
+    // reaching them again means we've found a cycle. An explicit stack
+    // is used instead of recursion, as long lists produce deeply nested
+    // splits, which could overflow the stack.
+    fn count_trees_cached(
+        &self,
+        node: ParseNode<'i, P>,
+        cache: &mut HashMap<ParseNode<'i, P>, Option<TreeCount>>,
+    ) -> TreeCount {
+        // Each node is on the stack first to have its children counted,
+        // and then again (with `true`), to add up the counts of its children.
+        let mut stack = vec![(node, false)];
+        while let Some((node, children_counted)) = stack.pop() {
+            if !children_counted {
+                if cache.contains_key(&node) {
+                    continue;
+                }
+                cache.insert(node, None);
+                stack.push((node, true));
+                stack.extend(
+                    self.children(node)
+                        .into_iter()
+                        .filter(|child| !cache.contains_key(child))
+                        .map(|child| (child, false)),
+                );
+                continue;
+            }
+
+            let count = |child: ParseNode<'i, P>| match cache.get(&child) {
+                Some(&Some(count)) => count,
+                _ => TreeCount::Infinite,
+            };
+            let count = match self.grammar.parse_node_shape(node.kind) {
+                ParseNodeShape::Opaque => TreeCount::Finite(1),
+                ParseNodeShape::Alias(_) => count(self.unpack_alias(node)),
+                ParseNodeShape::Opt(_) => self.unpack_opt(node).map_or(TreeCount::Finite(1), count),
+                ParseNodeShape::Choice => self
+                    .all_choices(node)
+                    .fold(TreeCount::Finite(0), |total, child| total + count(child)),
+                ParseNodeShape::Split(..) => self
+                    .all_splits(node)
+                    .fold(TreeCount::Finite(0), |total, (left, right)| {
+                        total + count(left) * count(right)
+                    }),
+            };
+            cache.insert(node, Some(count));
+        }
+        cache[&node].unwrap()
+    }
+
+    /// All the nodes `node` can have as children, in any of its derivations.
+    fn children(&self, node: ParseNode<'i, P>) -> Vec<ParseNode<'i, P>> {
+        match self.grammar.parse_node_shape(node.kind) {
+            ParseNodeShape::Opaque => vec![],
+            ParseNodeShape::Alias(_) => vec![self.unpack_alias(node)],
+            ParseNodeShape::Opt(_) => self.unpack_opt(node).into_iter().collect(),
+            ParseNodeShape::Choice => self.all_choices(node).collect(),
+            ParseNodeShape::Split(..) => self
+                .all_splits(node)
+                .flat_map(|(left, right)| vec![left, right])
+                .collect(),
+        }
+    }
+
+    /// Extract the `k`-th tree `node` encodes, in the order given by
+    /// `all_choices` and `all_splits` (with the left side of a split
+    /// varying slower than the right side).
+    ///
+    /// Returns `None` if `k` is out of bounds, or if `count_trees(node)`
+    /// isn't `TreeCount::Finite`.
+    pub fn kth_tree(&self, node: ParseNode<'i, P>, k: u64) -> Option<ParseTree<'i, P>> {
+        let mut cache = HashMap::new();
+        match self.count_trees_cached(node, &mut cache) {
+            TreeCount::Finite(count) if k < count => Some(self.kth_tree_cached(node, k, &cache)),
+            _ => None,
+        }
+    }
+
+    /// Extract an uniformly random tree out of those `node` encodes,
+    /// where `random_below(n)` must return an uniformly random `k < n`.
+    ///
+    /// Returns `None` if `count_trees(node)` isn't `TreeCount::Finite`,
+    /// or if `node` doesn't encode any trees at all.
+    pub fn random_tree(
+        &self,
+        node: ParseNode<'i, P>,
+        random_below: impl FnOnce(u64) -> u64,
+    ) -> Option<ParseTree<'i, P>> {
+        let mut cache = HashMap::new();
+        match self.count_trees_cached(node, &mut cache) {
+            TreeCount::Finite(count) if count > 0 => {
+                let k = random_below(count);
+                assert!(k < count);
+                Some(self.kth_tree_cached(node, k, &cache))
+            }
+            _ => None,
+        }
+    }
+
+    fn kth_tree_cached(
+        &self,
+        node: ParseNode<'i, P>,
+        k: u64,
+        cache: &HashMap<ParseNode<'i, P>, Option<TreeCount>>,
+    ) -> ParseTree<'i, P> {
+        // Like `count_trees_cached`, this uses an explicit stack, of trees
+        // still being built, each along with its children left to extract.
+        let new_tree = |node, k| {
+            let mut children = self.kth_children(node, k, cache);
+            children.reverse();
+            (
+                ParseTree {
+                    node,
+                    children: vec![],
+                },
+                children,
+            )
+        };
+        let mut stack = vec![new_tree(node, k)];
+        loop {
+            let (_, children_left) = stack.last_mut().unwrap();
+            if let Some((child, k)) = children_left.pop() {
+                stack.push(new_tree(child, k));
+                continue;
+            }
+            let (tree, _) = stack.pop().unwrap();
+            match stack.last_mut() {
+                Some((parent, _)) => parent.children.push(tree),
+                None => return tree,
+            }
+        }
+    }
+
+    /// The children of the `k`-th tree `node` encodes, each along with
+    /// the index of the tree it encodes, which is part of that tree.
+    fn kth_children(
+        &self,
+        node: ParseNode<'i, P>,
+        mut k: u64,
+        cache: &HashMap<ParseNode<'i, P>, Option<TreeCount>>,
+    ) -> Vec<(ParseNode<'i, P>, u64)> {
+        // NOTE only the counts of all the children of `node` are
+        // guaranteed to be cached, and finite (unless multiplied by `0`).
+        let count = |child: ParseNode<'i, P>| cache[&child].unwrap();
+        let finite = |n: TreeCount| match n {
+            TreeCount::Finite(n) => n,
+            n => unreachable!("kth_tree({:?}): non-finite count {:?}", node, n),
+        };
+        match self.grammar.parse_node_shape(node.kind) {
+            ParseNodeShape::Opaque => vec![],
+            ParseNodeShape::Alias(_) => vec![(self.unpack_alias(node), k)],
+            ParseNodeShape::Opt(_) => self
+                .unpack_opt(node)
+                .map(|child| (child, k))
+                .into_iter()
+                .collect(),
+            ParseNodeShape::Choice => {
+                let child = self
+                    .all_choices(node)
+                    .find(|&child| {
+                        let n = finite(count(child));
+                        if k < n {
+                            true
+                        } else {
+                            k -= n;
+                            false
+                        }
+                    })
+                    .unwrap();
+                vec![(child, k)]
+            }
+            ParseNodeShape::Split(..) => {
+                let (left, right) = self
+                    .all_splits(node)
+                    .find(|&(left, right)| {
+                        let n = finite(count(left) * count(right));
+                        if k < n {
+                            true
+                        } else {
+                            k -= n;
+                            false
+                        }
+                    })
+                    .unwrap();
+                let right_count = finite(count(right));
+                vec![(left, k / right_count), (right, k % right_count)]
+            }
+        }
+    }
+
+    /// Remove the choices of `Choice` nodes for which `f(forest, node, choice)`
+    /// returns `false` (all calls to `f` happen before any choice is removed).
+    ///
+    /// Nodes referring to nodes left without any derivations, or otherwise
+    /// unreachable, are only removed by `collect_garbage`, which should be
+    /// called afterwards.
+    pub fn retain_choices(
+        &mut self,
+        mut f: impl FnMut(&Self, ParseNode<'i, P>, ParseNode<'i, P>) -> bool,
+    ) {
+        let this = &*self;
+        let removed: HashSet<_> = this
+            .possible_choices
+            .keys()
+            .flat_map(|&node| this.all_choices(node).map(move |choice| (node, choice)))
+            .filter(|&(node, choice)| !f(this, node, choice))
+            .map(|(node, choice)| (node, choice.kind))
+            .collect();
+        self.possible_choices
+            .retain(|&node, &kind| !removed.contains(&(node, kind)));
+    }
+
+    /// Remove the splits of `Split` nodes for which `f(forest, node, (left, right))`
+    /// returns `false` (all calls to `f` happen before any split is removed).
+    ///
+    /// Nodes referring to nodes left without any derivations, or otherwise
+    /// unreachable, are only removed by `collect_garbage`, which should be
+    /// called afterwards.
+    pub fn retain_splits(
+        &mut self,
+        mut f: impl FnMut(&Self, ParseNode<'i, P>, (ParseNode<'i, P>, ParseNode<'i, P>)) -> bool,
+    ) {
+        let this = &*self;
+        let removed: HashSet<_> = this
+            .possible_splits
+            .keys()
+            .flat_map(|&node| this.all_splits(node).map(move |split| (node, split)))
+            .filter(|&(node, split)| !f(this, node, split))
+            .map(|(node, (left, _))| (node, left.range.len()))
+            .collect();
+        self.possible_splits
+            .retain(|&node, &split| !removed.contains(&(node, split)));
+    }
+
+    /// Remove all the choices and splits which refer to nodes left without any
+    /// derivations (e.g. by `retain_choices` or `retain_splits`), and then all
+    /// the nodes which are no longer reachable from `root`.
+    ///
+    /// Returns `false` if `root` itself was left without any derivations,
+    /// in which case the forest will be empty.
+    pub fn collect_garbage(&mut self, root: ParseNode<'i, P>) -> bool {
+        // Find all the `Choice` and `Split` nodes that have derivations left.
+        // As children can't be larger than their parents, going through nodes
+        // in increasing length means only nodes of the same length can depend
+        // on eachother (and that only happens with nullable rules), so it's
+        // enough to repeat each length until nothing changes.
+        let mut nodes: Vec<_> = self
+            .possible_choices
+            .keys()
+            .chain(self.possible_splits.keys())
+            .cloned()
+            .collect();
+        nodes.sort_by_key(|node| node.range.len());
+        let mut valid = HashSet::new();
+        let mut start = 0;
+        while start < nodes.len() {
+            let len = nodes[start].range.len();
+            let end = start
+                + nodes[start..]
+                    .iter()
+                    .take_while(|node| node.range.len() == len)
+                    .count();
+            loop {
+                let mut changed = false;
+                for &node in &nodes[start..end] {
+                    if !valid.contains(&node) && self.has_valid_derivation(node, &valid) {
+                        valid.insert(node);
+                        changed = true;
+                    }
+                }
+                if !changed {
+                    break;
+                }
+            }
+            start = end;
+        }
+
+        // Only keep the choices and splits which refer to valid nodes.
+        let this = &*self;
+        let invalid_choices: HashSet<_> = this
+            .possible_choices
+            .keys()
+            .flat_map(|&node| this.all_choices(node).map(move |choice| (node, choice)))
+            .filter(|&(_, choice)| !this.is_valid(choice, &valid))
+            .map(|(node, choice)| (node, choice.kind))
+            .collect();
+        let invalid_splits: HashSet<_> = this
+            .possible_splits
+            .keys()
+            .flat_map(|&node| this.all_splits(node).map(move |split| (node, split)))
+            .filter(|&(_, (left, right))| {
+                !(this.is_valid(left, &valid) && this.is_valid(right, &valid))
+            })
+            .map(|(node, (left, _))| (node, left.range.len()))
+            .collect();
+        self.possible_choices
+            .retain(|&node, &kind| !invalid_choices.contains(&(node, kind)));
+        self.possible_splits
+            .retain(|&node, &split| !invalid_splits.contains(&(node, split)));
+
+        // Only keep the nodes reachable from `root`.
+        let mut reachable = HashSet::new();
+        if self.is_valid(root, &valid) {
+            let mut queue = VecDeque::new();
+            queue.push_back(root);
+            while let Some(node) = queue.pop_front() {
+                if !reachable.insert(node) {
+                    continue;
+                }
+                match self.grammar.parse_node_shape(node.kind) {
+                    ParseNodeShape::Opaque => {}
+                    ParseNodeShape::Alias(_) => queue.push_back(self.unpack_alias(node)),
+                    ParseNodeShape::Opt(_) => queue.extend(self.unpack_opt(node)),
+                    ParseNodeShape::Choice => queue.extend(self.all_choices(node)),
+                    ParseNodeShape::Split(..) => {
+                        for (left, right) in self.all_splits(node) {
+                            queue.push_back(left);
+                            queue.push_back(right);
+                        }
+                    }
+                }
+            }
+        }
+        self.possible_choices
+            .retain(|node, _| reachable.contains(node));
+        self.possible_splits
+            .retain(|node, _| reachable.contains(node));
+
+        !reachable.is_empty()
+    }
+
+    fn has_valid_derivation(
+        &self,
+        node: ParseNode<'i, P>,
+        valid: &HashSet<ParseNode<'i, P>>,
+    ) -> bool {
+        match self.grammar.parse_node_shape(node.kind) {
+            ParseNodeShape::Choice => self
+                .all_choices(node)
+                .any(|choice| self.is_valid(choice, valid)),
+            ParseNodeShape::Split(..) => self
+                .all_splits(node)
+                .any(|(left, right)| self.is_valid(left, valid) && self.is_valid(right, valid)),
+            shape => unreachable!(
+                "has_valid_derivation({:?}): non-choice/split shape {:?}",
+                node, shape
+            ),
+        }
+    }
+
+    fn is_valid(&self, node: ParseNode<'i, P>, valid: &HashSet<ParseNode<'i, P>>) -> bool {
+        match self.grammar.parse_node_shape(node.kind) {
+            ParseNodeShape::Opaque => true,
+            ParseNodeShape::Alias(_) => self.is_valid(self.unpack_alias(node), valid),
+            ParseNodeShape::Opt(_) => self
+                .unpack_opt(node)
+                .map_or(true, |child| self.is_valid(child, valid)),
+            ParseNodeShape::Choice | ParseNodeShape::Split(..) => valid.contains(&node),
+        }
+    }
+
     pub fn dump_graphviz(&self, out: &mut dyn Write) -> io::Result<()> {
         writeln!(out, "digraph forest {{")?;
         let mut queue: VecDeque<_> = self
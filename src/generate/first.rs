@@ -0,0 +1,117 @@
+use grammer::context::{Context, IRule, IStr};
+use grammer::rule::{MatchesEmpty, MaybeKnown, Rule, RuleWithNamedFields};
+use indexmap::{IndexMap, IndexSet};
+use std::iter;
+
+/// What a rule can match first, i.e. at the start of the input it matches.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct First {
+    /// Whether the rule can match without consuming any input.
+    pub nullable: bool,
+
+    /// All the `Rule::Eat`s that can start a non-empty match, each paired
+    /// with the name of the rule it's found in (for error reporting).
+    pub eats: IndexSet<(IStr, IRule)>,
+}
+
+impl First {
+    fn extend(&mut self, other: First) {
+        self.nullable |= other.nullable;
+        self.eats.extend(other.eats);
+    }
+}
+
+/// The `First` of every named rule in a grammar.
+pub struct FirstSets {
+    named: IndexMap<IStr, First>,
+}
+
+impl FirstSets {
+    pub fn compute<Pat: MatchesEmpty>(
+        cx: &Context<Pat>,
+        named: &IndexMap<IStr, RuleWithNamedFields>,
+    ) -> Self {
+        let mut sets = FirstSets {
+            named: named.keys().map(|&name| (name, First::default())).collect(),
+        };
+
+        // Rules can be (mutually) recursive, so keep recomputing all of them
+        // until nothing changes anymore (which ends, as they can only grow).
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (&name, rule) in named {
+                let first = sets.first(cx, name, rule.rule);
+                if sets.named[&name] != first {
+                    sets.named.insert(name, first);
+                    changed = true;
+                }
+            }
+        }
+
+        sets
+    }
+
+    /// The `First` of `rule`, found in the named rule `enclosing`.
+    pub fn first<Pat: MatchesEmpty>(
+        &self,
+        cx: &Context<Pat>,
+        enclosing: IStr,
+        rule: IRule,
+    ) -> First {
+        match cx[rule] {
+            Rule::Empty => First {
+                nullable: true,
+                eats: IndexSet::new(),
+            },
+            Rule::Eat(ref pat) => First {
+                // NOTE patterns which might match the empty string can't
+                // be relied upon to consume anything, so they're treated as
+                // optional, which makes their `First` useless as a filter.
+                nullable: match pat.matches_empty() {
+                    MaybeKnown::Known(matches_empty) => matches_empty,
+                    MaybeKnown::Unknown => true,
+                },
+                eats: iter::once((enclosing, rule)).collect(),
+            },
+            // HACK undefined rules are reported by `Grammar::check`,
+            // so they only need to not cause any filtering here.
+            Rule::Call(r) => self.named.get(&r).cloned().unwrap_or(First {
+                nullable: true,
+                eats: IndexSet::new(),
+            }),
+            Rule::Concat([left, right]) => {
+                let mut first = self.first(cx, enclosing, left);
+                if first.nullable {
+                    let right = self.first(cx, enclosing, right);
+                    first.nullable = right.nullable;
+                    first.eats.extend(right.eats);
+                }
+                first
+            }
+            Rule::Or(ref cases) => {
+                let mut first = First::default();
+                for &rule in cases {
+                    first.extend(self.first(cx, enclosing, rule));
+                }
+                first
+            }
+            Rule::Opt(rule) => First {
+                nullable: true,
+                ..self.first(cx, enclosing, rule)
+            },
+            Rule::RepeatMany(elem, sep) | Rule::RepeatMore(elem, sep) => {
+                let mut first = self.first(cx, enclosing, elem);
+                if first.nullable {
+                    if let Some((sep, _)) = sep {
+                        first.eats.extend(self.first(cx, enclosing, sep).eats);
+                    }
+                }
+                if let Rule::RepeatMany(..) = cx[rule] {
+                    first.nullable = true;
+                }
+                first
+            }
+        }
+    }
+}
@@ -1,2 +1,3 @@
+pub mod first;
 pub mod rust;
 pub mod src;
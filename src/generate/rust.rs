@@ -1,10 +1,12 @@
+use crate::generate::first::FirstSets;
 use crate::generate::src::{quotable_to_src, quote, Src, ToSrc};
 use crate::parse_node::ParseNodeShape;
-use crate::scannerless::Pat as SPat;
+use crate::scannerless::{ClassItem, Pat as SPat};
 use grammer::context::{Context, IRule, IStr};
 use grammer::rule::{FieldPathset, MatchesEmpty, Rule, RuleWithNamedFields, SepKind};
 
 use indexmap::{map::Entry, IndexMap, IndexSet};
+use proc_quote::ToTokens;
 use std::borrow::Cow;
 use std::cell::RefCell;
 use std::fmt::Write as FmtWrite;
@@ -14,21 +16,126 @@ use std::rc::Rc;
 use std::{iter, mem};
 
 pub trait RustInputPat {
-    fn rust_slice_ty() -> Src;
+    /// The types of all the matchers, which inputs need to support.
+    fn rust_matcher_tys() -> Vec<Src>;
     fn rust_matcher(&self) -> Src;
+
+    /// The type of the `Trie`s from `rust_trie`, which inputs need to
+    /// support (through `InputRangeMatchAll`).
+    fn rust_trie_ty() -> Src;
+
+    /// A `Trie` matching all of `pats` at once, if they're all literals.
+    fn rust_trie(pats: &[&Self]) -> Option<Src>;
+}
+
+/// Generate a `gll::input::Trie`, from the elements of each pattern
+/// (e.g. the bytes of a string), and its own matcher (for error reporting).
+pub fn generate_trie<T: PartialEq + ToTokens>(pats: Vec<(Vec<T>, Src)>) -> Src {
+    // Each node has the (indices of) patterns ending at it, and its edges.
+    let mut nodes: Vec<(Vec<usize>, Vec<(T, usize)>)> = vec![(vec![], vec![])];
+    let mut matchers = vec![];
+    for (i, (elems, matcher)) in pats.into_iter().enumerate() {
+        let mut node = 0;
+        for elem in elems {
+            let existing = nodes[node]
+                .1
+                .iter()
+                .find(|(x, _)| *x == elem)
+                .map(|&(_, next)| next);
+            node = match existing {
+                Some(next) => next,
+                None => {
+                    let next = nodes.len();
+                    nodes.push((vec![], vec![]));
+                    nodes[node].1.push((elem, next));
+                    next
+                }
+            };
+        }
+        nodes[node].0.push(i);
+        matchers.push(matcher);
+    }
+
+    // HACK workaround `quote!(#i)` producing `0usize`.
+    let nodes = nodes.into_iter().map(|(accepts, edges)| {
+        let accepts = accepts
+            .into_iter()
+            .map(::proc_macro2::Literal::usize_unsuffixed);
+        let edges = edges.into_iter().map(|(elem, next)| {
+            let next = ::proc_macro2::Literal::usize_unsuffixed(next);
+            quote!((#elem, #next))
+        });
+        quote!(gll::input::TrieNode {
+            accepts: &[#(#accepts),*],
+            edges: &[#(#edges),*],
+        })
+    });
+    quote!(gll::input::Trie {
+        pats: &[#(#matchers),*],
+        nodes: &[#(#nodes),*],
+    })
 }
 
 impl<S: AsRef<str>> RustInputPat for SPat<S> {
-    fn rust_slice_ty() -> Src {
-        quote!(str)
+    fn rust_matcher_tys() -> Vec<Src> {
+        vec![
+            quote!(&'static str),
+            quote!(gll::scannerless::CaseInsensitive<&'static str>),
+            quote!(::std::ops::RangeInclusive<char>),
+            quote!(gll::scannerless::CharClass<&'static [gll::scannerless::ClassItem]>),
+        ]
     }
     fn rust_matcher(&self) -> Src {
         match self {
             SPat::String(s) => Src::new(s.as_ref()),
+            SPat::CaseInsensitive(s) => {
+                let s = s.as_ref();
+                quote!(gll::scannerless::CaseInsensitive(#s))
+            }
             SPat::Range(start, end) => quote!(#start..=#end),
+            SPat::Class(class) => {
+                let negated = class.negated;
+                let items = class.items.iter();
+                quote!(gll::scannerless::CharClass {
+                    negated: #negated,
+                    items: &[#(#items),*],
+                })
+            }
+        }
+    }
+    fn rust_trie_ty() -> Src {
+        quote!(gll::input::Trie<u8, &'static str>)
+    }
+    fn rust_trie(pats: &[&Self]) -> Option<Src> {
+        let pats = pats
+            .iter()
+            .map(|pat| match pat {
+                SPat::String(s) if !s.as_ref().is_empty() => {
+                    Some((s.as_ref().bytes().collect(), pat.rust_matcher()))
+                }
+                _ => None,
+            })
+            .collect::<Option<_>>()?;
+        Some(generate_trie::<u8>(pats))
+    }
+}
+
+impl ToSrc for ClassItem {
+    fn to_src(&self) -> Src {
+        match *self {
+            ClassItem::Range(start, end) => {
+                quote!(gll::scannerless::ClassItem::Range(#start, #end))
+            }
+            ClassItem::Property(property) => {
+                let property = Src::ident(format!("{:?}", property));
+                quote!(gll::scannerless::ClassItem::Property(
+                    gll::scannerless::UnicodeProperty::#property
+                ))
+            }
         }
     }
 }
+quotable_to_src!(ClassItem);
 
 struct RuleMap<'a> {
     named: &'a IndexMap<IStr, RuleWithNamedFields>,
@@ -338,6 +445,13 @@ impl CodeLabel {
     fn flattened_ident(&self) -> Src {
         Src::ident(self.flattened_name())
     }
+
+    fn rule_name(&self) -> &str {
+        match self {
+            CodeLabel::NamedRule(r) => r,
+            CodeLabel::Nested { parent, .. } => parent.rule_name(),
+        }
+    }
 }
 
 impl ToSrc for CodeLabel {
@@ -348,22 +462,84 @@ impl ToSrc for CodeLabel {
 }
 quotable_to_src!(CodeLabel);
 
+/// How the parsing code for each code label (`_C`) is generated.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Backend {
+    /// Specialized Rust code, in one `match` arm per code label.
+    Code,
+
+    /// Static tables of `gll::runtime::Op`s, one per code label, which are
+    /// run by `Runtime::interpret`, producing the same forests as `Code`,
+    /// but with a lot less generated code (and so faster to compile).
+    Tables,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::Code
+    }
+}
+
+/// Settings for generating a parser (see `generate_with_options`).
+#[derive(Copy, Clone, Debug)]
+pub struct Options {
+    pub backend: Backend,
+
+    /// Whether to skip calls (and alternatives) which can't match the next
+    /// input, using FIRST sets (see `check_first`). Turning it off doesn't
+    /// change the parse forests, only the work done (see `ParseStats`),
+    /// so it's only useful for measuring how much the checks help.
+    pub first_sets: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            backend: Backend::default(),
+            first_sets: true,
+        }
+    }
+}
+
 // FIXME(eddyb) this is a bit pointless, as it's exported as a free function.
 trait GrammarGenerateMethods<Pat> {
-    fn generate_rust(&self, cx: &mut Context<Pat>) -> Src;
+    fn generate_rust(&self, cx: &mut Context<Pat>, options: Options) -> Src;
 }
 
 pub fn generate<Pat: Eq + Hash + MatchesEmpty + RustInputPat>(
     cx: &mut Context<Pat>,
     g: &grammer::Grammar,
 ) -> Src {
-    g.generate_rust(cx)
+    generate_with_options(cx, g, Options::default())
+}
+
+pub fn generate_with_backend<Pat: Eq + Hash + MatchesEmpty + RustInputPat>(
+    cx: &mut Context<Pat>,
+    g: &grammer::Grammar,
+    backend: Backend,
+) -> Src {
+    generate_with_options(
+        cx,
+        g,
+        Options {
+            backend,
+            ..Options::default()
+        },
+    )
+}
+
+pub fn generate_with_options<Pat: Eq + Hash + MatchesEmpty + RustInputPat>(
+    cx: &mut Context<Pat>,
+    g: &grammer::Grammar,
+    options: Options,
+) -> Src {
+    g.generate_rust(cx, options)
 }
 
 impl<Pat: Eq + Hash + MatchesEmpty + RustInputPat> GrammarGenerateMethods<Pat>
     for grammer::Grammar
 {
-    fn generate_rust(&self, cx: &mut Context<Pat>) -> Src {
+    fn generate_rust(&self, cx: &mut Context<Pat>, options: Options) -> Src {
         self.check(cx);
 
         let rules = &RuleMap {
@@ -380,12 +556,22 @@ impl<Pat: Eq + Hash + MatchesEmpty + RustInputPat> GrammarGenerateMethods<Pat>
         .parse::<Src>()
         .unwrap();
 
+        // HACK this relies on `gll` being built with the same features
+        // both when generating the parser and when compiling the result.
+        if cfg!(feature = "syn") {
+            out += include_str!("templates/syn.rs").parse::<Src>().unwrap();
+        }
+
         for (&name, rule) in rules.named {
-            out += declare_rule(name, rule, cx, rules) + impl_parse_with(cx, name);
+            out += declare_rule(name, rule, cx, rules);
         }
 
         let mut code_labels = IndexMap::new();
-        out += define_parse_fn(cx, rules, &mut code_labels);
+        let (parse_fn, matches_all) = define_parse_fn(cx, rules, &mut code_labels, options);
+        for &name in rules.named.keys() {
+            out += impl_parse_with(cx, name, matches_all);
+        }
+        out += parse_fn;
 
         for rule in rules.named.values() {
             if !rule.fields.is_empty() {
@@ -438,6 +624,7 @@ impl<Pat: Eq + Hash + MatchesEmpty + RustInputPat> GrammarGenerateMethods<Pat>
 
         out + declare_parse_node_kind(&all_parse_nodes)
             + impl_debug_for_handle_any(&all_parse_nodes)
+            + impl_derivations_for_handle_any(&all_parse_nodes)
             + code_label_decl_and_impls(cx, rules, &code_labels)
     }
 }
@@ -448,17 +635,189 @@ struct Continuation<'a, Pat> {
     rules: Option<&'a RuleMap<'a>>,
     code_labels: &'a mut IndexMap<Rc<CodeLabel>, usize>,
     fn_code_label: &'a mut Rc<CodeLabel>,
-    code_label_arms: &'a mut Vec<Src>,
+    code_label_arms: &'a mut Vec<(Rc<CodeLabel>, Block)>,
+    /// Only `None` if FIRST set checks are turned off (see `Options`).
+    first_sets: Option<&'a FirstSets>,
     code: Code,
     nested_frames: Vec<Option<(Rc<CodeLabel>, Rc<CodeLabel>)>>,
 }
 
 #[derive(Clone)]
 enum Code {
-    Inline(Src),
+    Inline(Block),
     Label(Rc<CodeLabel>),
 }
 
+/// Parsing code, built up by `Thunk`s, to be turned into either Rust code,
+/// or `gll::runtime::Op`s (see `Backend`), once all of it is generated.
+#[derive(Clone, Default)]
+struct Block(Vec<Stmt>);
+
+/// A parsing step, each corresponding to a `gll::runtime::Runtime` method.
+#[derive(Clone)]
+enum Stmt {
+    Spawn(Rc<CodeLabel>),
+    Call {
+        callee: Rc<CodeLabel>,
+        next: Rc<CodeLabel>,
+    },
+    Ret,
+    Save(ParseNodeKind),
+    ForestAddChoice(ParseNodeKind, ParseNodeKind),
+    ForestAddSplit(ParseNodeKind),
+
+    /// Only run the `Block` if the pattern (of a `Rule::Eat`) matches,
+    /// with it consumed.
+    ConsumeLeft(IRule, Block),
+
+    /// Only run the `Block` if any of the patterns (of `Rule::Eat`s, each
+    /// in a named rule) matches, without consuming it (see `check_first`).
+    PeekLeft(Vec<(IStr, IRule)>, Block),
+
+    /// Match all the patterns (of `Rule::Eat`s) at once, and run the
+    /// `Block` for each of them which matched (see `dispatch_cases`).
+    MatchAllLeft(Vec<IRule>, Vec<Block>),
+}
+
+impl Block {
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether any `Stmt::MatchAllLeft` is used, which requires
+    /// the input to support `InputRangeMatchAll`.
+    fn matches_all(&self) -> bool {
+        self.0.iter().any(|stmt| match stmt {
+            Stmt::ConsumeLeft(_, then) | Stmt::PeekLeft(_, then) => then.matches_all(),
+            Stmt::MatchAllLeft(..) => true,
+            _ => false,
+        })
+    }
+
+    fn to_rust<Pat: Eq + Hash + RustInputPat>(&self, cx: &Context<Pat>) -> Src {
+        let matcher = |eat: IRule| match cx[eat] {
+            Rule::Eat(ref pat) => pat.rust_matcher(),
+            _ => unreachable!(),
+        };
+        let mut code = quote!();
+        for stmt in &self.0 {
+            code += match stmt {
+                Stmt::Spawn(next) => quote!(rt.spawn(#next);),
+                Stmt::Call { callee, next } => quote!(rt.call(#callee, #next);),
+                Stmt::Ret => quote!(rt.ret();),
+                Stmt::Save(kind) => quote!(rt.save(#kind);),
+                Stmt::ForestAddChoice(kind, choice) => {
+                    quote!(rt.forest_add_choice(#kind, #choice);)
+                }
+                Stmt::ForestAddSplit(kind) => quote!(
+                    let saved = rt.take_saved();
+                    rt.forest_add_split(
+                        #kind,
+                        saved,
+                    );
+                ),
+                &Stmt::ConsumeLeft(eat, ref then) => {
+                    let pat = matcher(eat);
+                    let then = then.to_rust(cx);
+                    quote!(
+                        if let Some(mut rt) = rt.input_consume_left(&(#pat)) {
+                            #then
+                        }
+                    )
+                }
+                Stmt::PeekLeft(eats, then) => {
+                    let condition = eats
+                        .iter()
+                        .map(|&(name, eat)| {
+                            let rule_kind = ParseNodeKind::NamedRule(cx[name].to_string());
+                            let pat = matcher(eat);
+                            quote!(rt.input_peek_left(#rule_kind, &(#pat)))
+                        })
+                        .fold(None, |condition, peek| match condition {
+                            Some(condition) => Some(quote!(#condition || #peek)),
+                            None => Some(peek),
+                        })
+                        // NOTE this can only happen if the rule can never match.
+                        .unwrap_or_else(|| quote!(false));
+                    let then = then.to_rust(cx);
+                    quote!(
+                        if #condition {
+                            #then
+                        }
+                    )
+                }
+                Stmt::MatchAllLeft(eats, cases) => {
+                    let trie = rust_trie(cx, eats).unwrap();
+                    let cases = cases.iter().enumerate().map(|(i, case)| {
+                        // HACK workaround `quote!(#i)` producing `0usize`.
+                        let i = ::proc_macro2::Literal::usize_unsuffixed(i);
+                        let case = case.to_rust(cx);
+                        quote!(#i => { #case })
+                    });
+                    quote!(
+                        for case in rt.input_match_all_left(&(#trie)) {
+                            match case {
+                                #(#cases)*
+                                _ => unreachable!(),
+                            }
+                        }
+                    )
+                }
+            };
+        }
+        code
+    }
+
+    /// Generate a slice of `gll::runtime::Op`s (see `Backend::Tables`), with
+    /// patterns (and sets of them, for `Trie`s) replaced by indices in `pats`.
+    fn to_ops<Pat: Eq + Hash>(&self, cx: &Context<Pat>, pats: &mut OpPats) -> Src {
+        // HACK workaround `quote!(#i)` producing `0usize`.
+        let index = ::proc_macro2::Literal::usize_unsuffixed;
+        let ops = self.0.iter().map(|stmt| match stmt {
+            Stmt::Spawn(next) => quote!(_O::Spawn(#next)),
+            Stmt::Call { callee, next } => quote!(_O::Call(#callee, #next)),
+            Stmt::Ret => quote!(_O::Ret),
+            Stmt::Save(kind) => quote!(_O::Save(#kind)),
+            Stmt::ForestAddChoice(kind, choice) => quote!(_O::ForestAddChoice(#kind, #choice)),
+            Stmt::ForestAddSplit(kind) => quote!(_O::ForestAddSplit(#kind)),
+            &Stmt::ConsumeLeft(eat, ref then) => {
+                let pat = index(pats.eats.insert_full(eat).0);
+                let then = then.to_ops(cx, pats);
+                quote!(_O::ConsumeLeft(#pat, #then))
+            }
+            Stmt::PeekLeft(eats, then) => {
+                let eats = eats
+                    .iter()
+                    .map(|&(name, eat)| {
+                        let rule_kind = ParseNodeKind::NamedRule(cx[name].to_string());
+                        let pat = index(pats.eats.insert_full(eat).0);
+                        quote!((#rule_kind, #pat))
+                    })
+                    .collect::<Vec<_>>();
+                let then = then.to_ops(cx, pats);
+                quote!(_O::PeekLeft(&[#(#eats),*], #then))
+            }
+            Stmt::MatchAllLeft(eats, cases) => {
+                let trie = index(pats.tries.insert_full(eats.clone()).0);
+                let cases = cases
+                    .iter()
+                    .map(|case| case.to_ops(cx, pats))
+                    .collect::<Vec<_>>();
+                quote!(_O::MatchAllLeft(#trie, &[#(#cases),*]))
+            }
+        });
+        quote!(&[#(#ops),*])
+    }
+}
+
+/// The patterns (of `Rule::Eat`s), and sets of them (for `Trie`s), that
+/// `gll::runtime::Op`s refer to, by their index (see `Block::to_ops`).
+#[derive(Default)]
+struct OpPats {
+    eats: IndexSet<IRule>,
+    tries: IndexSet<Vec<IRule>>,
+}
+
 impl<Pat> Continuation<'_, Pat> {
     fn next_code_label(&mut self) -> Rc<CodeLabel> {
         let counter = self
@@ -480,16 +839,15 @@ impl<Pat> Continuation<'_, Pat> {
             code_labels: self.code_labels,
             fn_code_label: self.fn_code_label,
             code_label_arms: self.code_label_arms,
+            first_sets: self.first_sets,
             code: self.code.clone(),
             nested_frames: self.nested_frames.clone(),
         }
     }
 
-    fn to_inline(&mut self) -> &mut Src {
+    fn to_inline(&mut self) -> &mut Block {
         if let Code::Label(ref label) = self.code {
-            self.code = Code::Inline(quote!(
-                rt.spawn(#label);
-            ));
+            self.code = Code::Inline(Block(vec![Stmt::Spawn(label.clone())]));
         }
 
         match self.code {
@@ -510,9 +868,8 @@ impl<Pat> Continuation<'_, Pat> {
     }
 
     fn reify_as(&mut self, label: Rc<CodeLabel>) {
-        let code = self.to_inline();
-        let code = quote!(#label => {#code});
-        self.code_label_arms.push(code);
+        let code = mem::replace(self.to_inline(), Block::default());
+        self.code_label_arms.push((label.clone(), code));
         self.code = Code::Label(label);
     }
 }
@@ -589,27 +946,26 @@ impl<Pat, F: ContFn<Pat>> ContFn<Pat> for Thunk<F> {
 }
 
 macro_rules! thunk {
-    ($($t:tt)*) => {{
-        let prefix = quote!($($t)*);
+    ($($stmt:expr),*) => {{
+        let prefix = vec![$($stmt),*];
         Thunk::new(move |mut cont| {
             let code = cont.to_inline();
-            let suffix = mem::replace(code, prefix);
-            *code += suffix;
+            let suffix = mem::replace(&mut code.0, prefix);
+            code.0.extend(suffix);
             cont
         })
     }}
 }
 
-fn pop_saved<Pat, F: ContFn<Pat>>(f: impl FnOnce(Src) -> Thunk<F>) -> Thunk<impl ContFn<Pat>> {
-    thunk!(let saved = rt.take_saved();)
-        + f(quote!(saved))
+fn pop_saved<Pat>(parse_node_kind: ParseNodeKind) -> Thunk<impl ContFn<Pat>> {
+    thunk!(Stmt::ForestAddSplit(parse_node_kind))
         + Thunk::new(|mut cont| {
             if let Some(&None) = cont.nested_frames.last() {
                 *cont.nested_frames.last_mut().unwrap() =
                     Some((cont.to_label().clone(), cont.fn_code_label.clone()));
                 *cont.fn_code_label = cont.next_code_label();
                 cont.code_labels.insert(cont.fn_code_label.clone(), 0);
-                cont.code = Code::Inline(quote!());
+                cont.code = Code::Inline(Block::default());
                 cont = ret().apply(cont);
             }
             cont.nested_frames.push(None);
@@ -618,7 +974,7 @@ fn pop_saved<Pat, F: ContFn<Pat>>(f: impl FnOnce(Src) -> Thunk<F>) -> Thunk<impl
 }
 
 fn push_saved<Pat>(parse_node_kind: ParseNodeKind) -> Thunk<impl ContFn<Pat>> {
-    thunk!(rt.save(#parse_node_kind);)
+    thunk!(Stmt::Save(parse_node_kind))
         + Thunk::new(move |mut cont| {
             if let Some((ret_label, outer_fn_label)) = cont.nested_frames.pop().unwrap() {
                 let inner_fn_label = mem::replace(cont.fn_code_label, outer_fn_label);
@@ -629,30 +985,50 @@ fn push_saved<Pat>(parse_node_kind: ParseNodeKind) -> Thunk<impl ContFn<Pat>> {
         })
 }
 
-fn check<Pat>(condition: Src) -> Thunk<impl ContFn<Pat>> {
+/// Only continue in the `Block` nested in the `Stmt` built by `wrap`.
+fn check<Pat>(wrap: impl FnOnce(Block) -> Stmt) -> Thunk<impl ContFn<Pat>> {
     Thunk::new(move |mut cont| {
         let code = cont.to_inline();
-        *code = quote!(
-            if #condition {
-                #code
-            }
-        );
+        *code = Block(vec![wrap(mem::replace(code, Block::default()))]);
         cont
     })
 }
 
+/// Only continue if the remaining input starts with something `rule` could
+/// match first, to avoid calls (and alternatives) which are bound to fail.
+/// All the patterns checked are recorded as expected if none of them match,
+/// just like they would've been by `rule` itself.
+fn check_first<Pat: Eq + Hash + MatchesEmpty + RustInputPat>(
+    rule: IRule,
+) -> Thunk<impl ContFn<Pat>> {
+    Thunk::new(move |mut cont| {
+        let first_sets = match cont.first_sets {
+            Some(first_sets) => first_sets,
+            None => return cont,
+        };
+        let enclosing = cont.cx.intern(cont.fn_code_label.rule_name());
+        let first = first_sets.first(cont.cx, enclosing, rule);
+        if first.nullable {
+            return cont;
+        }
+        let eats = first.eats.into_iter().collect();
+        check(|then| Stmt::PeekLeft(eats, then)).apply(cont)
+    })
+}
+
 fn call<Pat>(callee: Rc<CodeLabel>) -> Thunk<impl ContFn<Pat>> {
     Thunk::new(move |mut cont| {
         let label = cont.to_label().clone();
-        cont.code = Code::Inline(quote!(
-            rt.call(#callee, #label);
-        ));
+        cont.code = Code::Inline(Block(vec![Stmt::Call {
+            callee,
+            next: label,
+        }]));
         cont
     })
 }
 
 fn ret<Pat>() -> Thunk<impl ContFn<Pat>> {
-    thunk!(rt.ret();)
+    thunk!(Stmt::Ret)
         + Thunk::new(|mut cont| {
             assert!(cont.to_inline().is_empty());
             cont
@@ -710,9 +1086,22 @@ where
 }
 
 fn parallel<Pat>(thunks: impl ForEachThunk<Pat>) -> Thunk<impl ContFn<Pat>> {
+    parallel_dispatch(None, thunks)
+}
+
+/// Like `parallel`, but if `dispatch` is provided, the `thunks` for which
+/// it has a literal are only tried when that literal matches (see
+/// `dispatch_cases`), after matching all of those literals at once.
+fn parallel_dispatch<Pat>(
+    dispatch: Option<Vec<Option<IRule>>>,
+    thunks: impl ForEachThunk<Pat>,
+) -> Thunk<impl ContFn<Pat>> {
     Thunk::new(|mut cont| {
         cont.to_label();
-        let mut code = quote!();
+        let mut code = Block::default();
+        let mut dispatched_eats = vec![];
+        let mut dispatched_cases = vec![];
+        let mut i = 0;
         let mut child_nested_frames = None;
         let nested_frames = cont.nested_frames.clone();
         thunks.for_each_thunk(&mut cont, |mut child_cont| {
@@ -736,8 +1125,20 @@ fn parallel<Pat>(thunks: impl ForEachThunk<Pat>) -> Thunk<impl ContFn<Pat>> {
                 child_cont.nested_frames[..],
                 nested_frames[..child_cont.nested_frames.len()]
             );
-            code += child_cont.to_inline().clone();
+            let child_code = child_cont.to_inline().clone();
+            match dispatch.as_ref().and_then(|leading| leading[i]) {
+                Some(eat) => {
+                    dispatched_eats.push(eat);
+                    dispatched_cases.push(child_code);
+                }
+                None => code.0.extend(child_code.0),
+            }
+            i += 1;
         });
+        if !dispatched_cases.is_empty() {
+            code.0
+                .push(Stmt::MatchAllLeft(dispatched_eats, dispatched_cases));
+        }
         cont.code = Code::Inline(code);
         if let Some(child_nested_frames) = child_nested_frames {
             while cont.nested_frames.len() > child_nested_frames {
@@ -748,6 +1149,49 @@ fn parallel<Pat>(thunks: impl ForEachThunk<Pat>) -> Thunk<impl ContFn<Pat>> {
     })
 }
 
+/// The `Rule::Eat` `rule` always starts with, if any (ignoring `Empty`).
+fn leading_eat<Pat: Eq + Hash>(cx: &Context<Pat>, rule: IRule) -> Option<IRule> {
+    match cx[rule] {
+        Rule::Eat(_) => Some(rule),
+        Rule::Concat([left, right]) => match cx[left] {
+            Rule::Empty => leading_eat(cx, right),
+            _ => leading_eat(cx, left),
+        },
+        _ => None,
+    }
+}
+
+/// Find which `cases` (of a `Rule::Or`) start by matching a literal, if
+/// there are enough of them, and they can all be matched by one `Trie`.
+fn dispatch_cases<Pat: Eq + Hash + RustInputPat>(
+    cx: &Context<Pat>,
+    cases: &[IRule],
+) -> Option<Vec<Option<IRule>>> {
+    let leading = cases
+        .iter()
+        .map(|&rule| leading_eat(cx, rule))
+        .collect::<Vec<_>>();
+    let eats = leading.iter().filter_map(|&eat| eat).collect::<Vec<_>>();
+    if eats.len() < 2 {
+        return None;
+    }
+    rust_trie(cx, &eats)?;
+    Some(leading)
+}
+
+/// A `Trie` for the patterns of all the `Rule::Eat`s in `eats`, if possible
+/// (see `RustInputPat::rust_trie`).
+fn rust_trie<Pat: Eq + Hash + RustInputPat>(cx: &Context<Pat>, eats: &[IRule]) -> Option<Src> {
+    let pats = eats
+        .iter()
+        .map(|&eat| match cx[eat] {
+            Rule::Eat(ref pat) => pat,
+            _ => unreachable!(),
+        })
+        .collect::<Vec<_>>();
+    Pat::rust_trie(&pats)
+}
+
 fn opt<Pat>(thunk: Thunk<impl ContFn<Pat>>) -> Thunk<impl ContFn<Pat>> {
     parallel((thunk, thunk!()))
 }
@@ -756,7 +1200,7 @@ fn fix<Pat, F: ContFn<Pat>>(f: impl FnOnce(Rc<CodeLabel>) -> Thunk<F>) -> Thunk<
     Thunk::new(|mut cont| {
         let nested_frames = mem::replace(&mut cont.nested_frames, vec![]);
         let ret_label = cont.to_label().clone();
-        cont.code = Code::Inline(quote!());
+        cont.code = Code::Inline(Block::default());
         let label = cont.next_code_label();
         let outer_fn_label = mem::replace(cont.fn_code_label, label.clone());
         cont.code_labels.insert(label.clone(), 0);
@@ -781,7 +1225,7 @@ fn forest_add_choice<Pat>(
     parse_node_kind: &ParseNodeKind,
     choice: ParseNodeKind,
 ) -> Thunk<impl ContFn<Pat>> {
-    thunk!(rt.forest_add_choice(#parse_node_kind, #choice);)
+    thunk!(Stmt::ForestAddChoice(parse_node_kind.clone(), choice))
 }
 
 fn concat_and_forest_add<Pat>(
@@ -790,19 +1234,16 @@ fn concat_and_forest_add<Pat>(
     right: Thunk<impl ContFn<Pat>>,
     parse_node_kind: ParseNodeKind,
 ) -> Thunk<impl ContFn<Pat>> {
-    left + push_saved(left_parse_node_kind)
-        + right
-        + pop_saved(move |saved| {
-            thunk!(rt.forest_add_split(
-                #parse_node_kind,
-                #saved,
-            );)
-        })
+    left + push_saved(left_parse_node_kind) + right + pop_saved(parse_node_kind)
 }
 
 trait RuleGenerateMethods<Pat> {
     fn generate_parse(self) -> Thunk<Box<dyn ContFn<Pat>>>;
 
+    /// Like `generate_parse`, but also using `check_first` before trying to
+    /// parse `self`, unless `self` starts by checking the input anyway.
+    fn generate_parse_checked(self) -> Thunk<Box<dyn ContFn<Pat>>>;
+
     fn generate_traverse_shape(
         self,
         refutable: bool,
@@ -811,18 +1252,30 @@ trait RuleGenerateMethods<Pat> {
     ) -> Src;
 }
 
-impl<Pat: Eq + Hash + RustInputPat> RuleGenerateMethods<Pat> for IRule {
+impl<Pat: Eq + Hash + MatchesEmpty + RustInputPat> RuleGenerateMethods<Pat> for IRule {
+    fn generate_parse_checked(self) -> Thunk<Box<dyn ContFn<Pat>>> {
+        Thunk::new(move |cont: Continuation<'_, Pat>| {
+            let checks_input = match cont.cx[self] {
+                Rule::Eat(_) | Rule::Call(_) => true,
+                _ => false,
+            };
+            if checks_input {
+                self.generate_parse().apply(cont)
+            } else {
+                (check_first(self) + self.generate_parse()).apply(cont)
+            }
+        })
+        .boxed()
+    }
+
     fn generate_parse(self) -> Thunk<Box<dyn ContFn<Pat>>> {
         Thunk::new(
             move |cont: Continuation<'_, Pat>| match (&cont.cx[self], cont.rules) {
                 (Rule::Empty, _) => cont,
-                (Rule::Eat(pat), _) => {
-                    let pat = pat.rust_matcher();
-                    check(quote!(let Some(mut rt) = rt.input_consume_left(&(#pat)))).apply(cont)
-                }
-                (&Rule::Call(r), _) => {
-                    call(Rc::new(CodeLabel::NamedRule(cont.cx[r].to_string()))).apply(cont)
-                }
+                (Rule::Eat(_), _) => check(|then| Stmt::ConsumeLeft(self, then)).apply(cont),
+                (&Rule::Call(r), _) => (check_first(self)
+                    + call(Rc::new(CodeLabel::NamedRule(cont.cx[r].to_string()))))
+                .apply(cont),
                 (&Rule::Concat([left, right]), None) => {
                     (left.generate_parse() + right.generate_parse()).apply(cont)
                 }
@@ -836,24 +1289,52 @@ impl<Pat: Eq + Hash + RustInputPat> RuleGenerateMethods<Pat> for IRule {
                 (Rule::Or(cases), None) => {
                     // HACK(eddyb) only clones a `Vec` to avoid `cx` borrow conflicts.
                     let cases = cases.clone();
-                    parallel(ThunkIter(cases.iter().map(|rule| rule.generate_parse()))).apply(cont)
+                    let dispatch = dispatch_cases(cont.cx, &cases);
+                    let dispatched = dispatch.clone();
+                    parallel_dispatch(
+                        dispatch,
+                        ThunkIter(cases.iter().enumerate().map(move |(i, rule)| {
+                            if dispatched
+                                .as_ref()
+                                .map_or(false, |leading| leading[i].is_some())
+                            {
+                                rule.generate_parse()
+                            } else {
+                                rule.generate_parse_checked()
+                            }
+                        })),
+                    )
+                    .apply(cont)
                 }
                 (Rule::Or(cases), Some(rules)) => {
                     // HACK(eddyb) only clones a `Vec` to avoid `cx` borrow conflicts.
                     let cases = cases.clone();
-                    parallel(ThunkIter(cases.iter().map(|rule| {
-                        Thunk::new(move |cont| {
-                            (rule.generate_parse()
-                                + forest_add_choice(
-                                    &self.parse_node_kind(cont.cx, rules),
-                                    rule.parse_node_kind(cont.cx, rules),
-                                ))
-                            .apply(cont)
-                        })
-                    })))
+                    let dispatch = dispatch_cases(cont.cx, &cases);
+                    let dispatched = dispatch.clone();
+                    parallel_dispatch(
+                        dispatch,
+                        ThunkIter(cases.iter().enumerate().map(move |(i, rule)| {
+                            let parse = if dispatched
+                                .as_ref()
+                                .map_or(false, |leading| leading[i].is_some())
+                            {
+                                rule.generate_parse()
+                            } else {
+                                rule.generate_parse_checked()
+                            };
+                            Thunk::new(move |cont| {
+                                (parse
+                                    + forest_add_choice(
+                                        &self.parse_node_kind(cont.cx, rules),
+                                        rule.parse_node_kind(cont.cx, rules),
+                                    ))
+                                .apply(cont)
+                            })
+                        })),
+                    )
                     .apply(cont)
                 }
-                (&Rule::Opt(rule), _) => opt(rule.generate_parse()).apply(cont),
+                (&Rule::Opt(rule), _) => opt(rule.generate_parse_checked()).apply(cont),
                 (&Rule::RepeatMany(elem, None), None) => {
                     fix(|label| opt(elem.generate_parse() + call(label))).apply(cont)
                 }
@@ -1009,17 +1490,46 @@ impl<Pat: Eq + Hash + RustInputPat> RuleGenerateMethods<Pat> for IRule {
     }
 }
 
-fn impl_parse_with<Pat>(cx: &mut Context<Pat>, name: IStr) -> Src
+fn impl_parse_with<Pat>(cx: &mut Context<Pat>, name: IStr, matches_all: bool) -> Src
 where
     Pat: RustInputPat,
 {
     let ident = Src::ident(&cx[name]);
     let code_label = Rc::new(CodeLabel::NamedRule(cx[name].to_string()));
     let parse_node_kind = ParseNodeKind::NamedRule(cx[name].to_string());
-    let rust_slice_ty = Pat::rust_slice_ty();
+    let input_bounds = input_bounds::<Pat>(matches_all);
+
+    // HACK like with the `syn` feature (see `generate_with_options`),
+    // this relies on `gll` being built with the same features both when
+    // generating the parser and when compiling the result.
+    let parse_parallel = if cfg!(feature = "parallel") {
+        quote!(
+            pub fn parse_parallel(input: I, workers: usize)
+                -> Result<
+                    OwnedHandle<I, Self>,
+                    gll::parser::ParseError<I::SourceInfoPoint>,
+                >
+                where I::Container: Sync,
+            {
+                gll::runtime::Runtime::parse_parallel(
+                    _G,
+                    input,
+                    #code_label,
+                    #parse_node_kind,
+                    workers,
+                ).map(|forest_and_node| OwnedHandle {
+                    forest_and_node,
+                    _marker: PhantomData,
+                })
+            }
+        )
+    } else {
+        quote!()
+    };
+
     quote!(
         impl<I> #ident<'_, '_, I>
-            where I: gll::input::Input<Slice = #rust_slice_ty>,
+            where #input_bounds,
         {
             pub fn parse(input: I)
                 -> Result<
@@ -1037,6 +1547,93 @@ where
                     _marker: PhantomData,
                 })
             }
+
+            pub fn parse_with_stats(input: I)
+                -> (
+                    Result<
+                        OwnedHandle<I, Self>,
+                        gll::parser::ParseError<I::SourceInfoPoint>,
+                    >,
+                    gll::runtime::ParseStats,
+                )
+            {
+                let (result, stats) = gll::runtime::Runtime::parse_with_stats(
+                    _G,
+                    input,
+                    #code_label,
+                    #parse_node_kind,
+                );
+                let result = result.map(|forest_and_node| OwnedHandle {
+                    forest_and_node,
+                    _marker: PhantomData,
+                });
+                (result, stats)
+            }
+
+            pub fn parse_prefix(input: I)
+                -> gll::parser::PrefixParse<
+                    I::SourceInfoPoint,
+                    OwnedHandle<I, Self>,
+                >
+            {
+                gll::runtime::Runtime::parse_prefix(
+                    _G,
+                    input,
+                    #code_label,
+                    #parse_node_kind,
+                ).map(|forest_and_node| OwnedHandle {
+                    forest_and_node,
+                    _marker: PhantomData,
+                })
+            }
+
+            /// Parse the longest suffix of `input` matching this rule,
+            /// returning it along with its length.
+            pub fn parse_suffix(input: I)
+                -> Result<
+                    (OwnedHandle<I, Self>, usize),
+                    gll::parser::ParseError<I::SourceInfoPoint>,
+                >
+            {
+                gll::runtime::Runtime::parse_suffix(
+                    _G,
+                    input,
+                    #code_label,
+                    #parse_node_kind,
+                ).map(|(forest_and_node, len)| (OwnedHandle {
+                    forest_and_node,
+                    _marker: PhantomData,
+                }, len))
+            }
+
+            #parse_parallel
+
+            /// Parse each of `inputs` (e.g. from `gll::analysis::sentences`),
+            /// stopping at the first one with an ambiguity (see `find_ambiguity`
+            /// on `Handle`), which is returned along with all its derivations.
+            pub fn find_ambiguity(inputs: impl IntoIterator<Item = I>)
+                -> Option<gll::analysis::AmbiguityWitness<I>>
+                where I: Clone,
+            {
+                inputs.into_iter().find_map(|input| {
+                    let (rule, source_info, derivations) = Self::parse(input.clone())
+                        .ok()?
+                        .with(|handle| {
+                            let Ambiguity(handle) = handle.find_ambiguity()?;
+                            Some((
+                                handle.forest.grammar.parse_node_desc(handle.node.kind),
+                                format!("{:?}", handle.source_info()),
+                                handle.named_rule_derivations().unwrap(),
+                            ))
+                        })?;
+                    Some(gll::analysis::AmbiguityWitness {
+                        input,
+                        rule,
+                        source_info,
+                        derivations,
+                    })
+                })
+            }
         }
 
         impl<I: gll::input::Input> OwnedHandle<I, #ident<'_, '_, I>> {
@@ -1061,7 +1658,7 @@ fn declare_rule<Pat>(
     rules: &RuleMap<'_>,
 ) -> Src
 where
-    Pat: Eq + Hash + RustInputPat,
+    Pat: Eq + Hash + MatchesEmpty + RustInputPat,
 {
     let ident = Src::ident(&cx[name]);
     let variants = rule.find_variant_fields(cx);
@@ -1133,7 +1730,7 @@ fn impl_rule_from_forest<Pat>(
     rules: &RuleMap<'_>,
 ) -> Src
 where
-    Pat: Eq + Hash + RustInputPat,
+    Pat: Eq + Hash + MatchesEmpty + RustInputPat,
 {
     let ident = Src::ident(&cx[name]);
     let field_handle_expr = |cx: &Context<Pat>, rule: IRule, paths: &FieldPathset| {
@@ -1239,7 +1836,7 @@ fn impl_rule_one_and_all<Pat>(
     rules: &RuleMap<'_>,
 ) -> Src
 where
-    Pat: Eq + Hash + RustInputPat,
+    Pat: Eq + Hash + MatchesEmpty + RustInputPat,
 {
     let ident = Src::ident(&cx[name]);
     let (one, all) = if let Some(variants) = variants {
@@ -1459,10 +2056,16 @@ fn define_parse_fn<Pat>(
     cx: &mut Context<Pat>,
     rules: &RuleMap<'_>,
     code_labels: &mut IndexMap<Rc<CodeLabel>, usize>,
-) -> Src
+    options: Options,
+) -> (Src, bool)
 where
-    Pat: Eq + Hash + RustInputPat,
+    Pat: Eq + Hash + MatchesEmpty + RustInputPat,
 {
+    let first_sets = if options.first_sets {
+        Some(FirstSets::compute(cx, rules.named))
+    } else {
+        None
+    };
     let mut code_label_arms = vec![];
     for (&name, rule) in rules.named {
         let code_label = Rc::new(CodeLabel::NamedRule(cx[name].to_string()));
@@ -1478,22 +2081,120 @@ where
                 code_labels,
                 fn_code_label: &mut code_label.clone(),
                 code_label_arms: &mut code_label_arms,
-                code: Code::Inline(quote!()),
+                first_sets: first_sets.as_ref(),
+                code: Code::Inline(Block::default()),
                 nested_frames: vec![],
             })
             .reify_as(code_label);
     }
 
-    let rust_slice_ty = Pat::rust_slice_ty();
-    quote!(impl<I> gll::runtime::CodeStep<I> for _C
-        where I: gll::input::Input<Slice = #rust_slice_ty>,
-    {
-        fn step<'i>(self, mut rt: gll::runtime::Runtime<'_, 'i, _C, I>) {
-            match self {
-                #(#code_label_arms)*
-            }
+    let matches_all = code_label_arms.iter().any(|(_, code)| code.matches_all());
+    let input_bounds = input_bounds::<Pat>(matches_all);
+    let where_clause = quote!(where #input_bounds,);
+
+    let code = match options.backend {
+        Backend::Code => {
+            let code_label_arms = code_label_arms.iter().map(|(label, code)| {
+                let code = code.to_rust(cx);
+                quote!(#label => {#code})
+            });
+            quote!(impl<I> gll::runtime::CodeStep<I> for _C #where_clause {
+                fn step<'i>(self, mut rt: gll::runtime::Runtime<'_, 'i, _C, I>) {
+                    match self {
+                        #(#code_label_arms)*
+                    }
+                }
+            })
         }
-    })
+        Backend::Tables => {
+            // NOTE the tables are indexed by `_C` discriminants, so they
+            // have to be in the same order as the `_C` variants are declared in.
+            let mut code_label_arms: IndexMap<_, _> = code_label_arms.into_iter().collect();
+            let mut pats = OpPats::default();
+            let ops = all_code_labels(cx, rules, code_labels)
+                .iter()
+                .map(|label| {
+                    code_label_arms
+                        .swap_remove(label)
+                        .unwrap()
+                        .to_ops(cx, &mut pats)
+                })
+                .collect::<Vec<_>>();
+
+            let matcher = |&eat: &IRule| match cx[eat] {
+                Rule::Eat(ref pat) => pat.rust_matcher(),
+                _ => unreachable!(),
+            };
+            // HACK workaround `quote!(#i)` producing `0usize`.
+            let pats_idx = (0..pats.eats.len())
+                .map(::proc_macro2::Literal::usize_unsuffixed)
+                .collect::<Vec<_>>();
+            let pats_matcher = pats.eats.iter().map(matcher).collect::<Vec<_>>();
+            let tries_idx = (0..pats.tries.len()).map(::proc_macro2::Literal::usize_unsuffixed);
+            let tries = pats.tries.iter().map(|eats| rust_trie(cx, eats).unwrap());
+
+            quote!(
+                type _O = gll::runtime::Op<_C, _P>;
+
+                static _OPS: &[&[_O]] = &[#(#ops),*];
+
+                impl<I> gll::runtime::OpPats<I> for _C #where_clause {
+                    fn input_consume_left<'a, 'i>(
+                        rt: &'a mut gll::runtime::Runtime<'_, 'i, _C, I>,
+                        pat: usize,
+                    ) -> Option<gll::runtime::Runtime<'a, 'i, _C, I>> {
+                        match pat {
+                            #(#pats_idx => rt.input_consume_left(&(#pats_matcher)),)*
+                            _ => unreachable!(),
+                        }
+                    }
+                    fn input_peek_left(
+                        rt: &mut gll::runtime::Runtime<'_, '_, _C, I>,
+                        rule: _P,
+                        pat: usize,
+                    ) -> bool {
+                        match pat {
+                            #(#pats_idx => rt.input_peek_left(rule, &(#pats_matcher)),)*
+                            _ => unreachable!(),
+                        }
+                    }
+                    fn input_match_all_left(
+                        rt: &mut gll::runtime::Runtime<'_, '_, _C, I>,
+                        trie: usize,
+                    ) -> Vec<usize> {
+                        match trie {
+                            #(#tries_idx => rt.input_match_all_left(&(#tries)),)*
+                            _ => unreachable!(),
+                        }
+                    }
+                }
+
+                impl<I> gll::runtime::CodeStep<I> for _C #where_clause {
+                    fn step<'i>(self, mut rt: gll::runtime::Runtime<'_, 'i, _C, I>) {
+                        rt.interpret(_OPS[self as usize]);
+                    }
+                }
+            )
+        }
+    };
+    (code, matches_all)
+}
+
+/// The bounds on the input type `I` of generated parsers, which only
+/// need `InputRangeMatchAll` if they use any `Trie`s (`matches_all`).
+fn input_bounds<Pat: RustInputPat>(matches_all: bool) -> Src {
+    let rust_matcher_tys = Pat::rust_matcher_tys();
+    let match_all = if matches_all {
+        let rust_trie_ty = Pat::rust_trie_ty();
+        quote!(+ gll::input::InputRangeMatchAll<#rust_trie_ty>)
+    } else {
+        quote!()
+    };
+    quote!(
+        I: gll::input::Input
+            #(+ gll::input::InputRangeMatch<#rust_matcher_tys>)*
+            #match_all
+    )
 }
 
 fn declare_parse_node_kind(all_parse_nodes: &[ParseNode]) -> Src {
@@ -1565,12 +2266,52 @@ fn impl_debug_for_handle_any(all_parse_nodes: &[ParseNode]) -> Src {
     })
 }
 
-fn code_label_decl_and_impls<Pat>(
-    cx: &mut Context<Pat>,
+fn impl_derivations_for_handle_any(all_parse_nodes: &[ParseNode]) -> Src {
+    let named_rules: Vec<_> = all_parse_nodes
+        .iter()
+        .filter(|node| match node.kind {
+            ParseNodeKind::NamedRule(_) => true,
+            ParseNodeKind::Anon(_) => false,
+        })
+        .collect();
+    let named_kind = named_rules.iter().map(|node| &node.kind);
+    let derivations_arms = named_rules.iter().map(|ParseNode { kind, ty, .. }| {
+        quote!(#kind => Some(
+            Handle::<_, #ty> {
+                node: self.node,
+                forest: self.forest,
+                _marker: PhantomData,
+            }
+            .all()
+            .map(|x| format!("{:?}", x))
+            .collect()
+        ),)
+    });
+    quote!(impl<I: gll::input::Input> Handle<'_, '_, I, Any> {
+        fn is_named_rule(self) -> bool {
+            match self.node.kind {
+                #(#named_kind)|* => true,
+                _ => false,
+            }
+        }
+
+        /// All the ways this named rule was parsed, through `Debug`.
+        fn named_rule_derivations(self) -> Option<Vec<String>> {
+            match self.node.kind {
+                #(#derivations_arms)*
+                _ => None,
+            }
+        }
+    })
+}
+
+/// All the code labels, in the order the `_C` variants are declared in.
+fn all_code_labels<Pat>(
+    cx: &Context<Pat>,
     rules: &RuleMap<'_>,
     code_labels: &IndexMap<Rc<CodeLabel>, usize>,
-) -> Src {
-    let all_labels = rules
+) -> Vec<Rc<CodeLabel>> {
+    rules
         .named
         .keys()
         .map(|&r| CodeLabel::NamedRule(cx[r].to_string()))
@@ -1580,12 +2321,23 @@ fn code_label_decl_and_impls<Pat>(
                 .map(|(parent, i)| CodeLabel::Nested { parent, i })
         }))
         .map(Rc::new)
-        .collect::<Vec<_>>();
+        .collect()
+}
+
+fn code_label_decl_and_impls<Pat>(
+    cx: &mut Context<Pat>,
+    rules: &RuleMap<'_>,
+    code_labels: &IndexMap<Rc<CodeLabel>, usize>,
+) -> Src {
+    let all_labels = all_code_labels(cx, rules, code_labels);
     let all_labels_ident = all_labels.iter().map(|label| label.flattened_ident());
     let all_labels_enclosing_fn = all_labels.iter().map(|label| match &**label {
         CodeLabel::Nested { parent, .. } if !code_labels.contains_key(label) => parent,
         _ => label,
     });
+    let all_labels_enclosing_rule = all_labels
+        .iter()
+        .map(|label| ParseNodeKind::NamedRule(label.rule_name().to_string()));
 
     quote!(
         #[allow(non_camel_case_types)]
@@ -1602,6 +2354,11 @@ fn code_label_decl_and_impls<Pat>(
                     #(#all_labels => #all_labels_enclosing_fn),*
                 }
             }
+            fn enclosing_rule(self) -> _P {
+                match self {
+                    #(#all_labels => #all_labels_enclosing_rule),*
+                }
+            }
         }
     )
 }
@@ -15,6 +15,28 @@ impl<I: gll::input::Input, T: ?Sized> OwnedHandle<I, T> {
             forest.source_info(node.range)
         })
     }
+
+    /// Provide mutable access to the parse forest and its root node,
+    /// e.g. for pruning it (see `ParseForest::retain_choices`).
+    pub fn with_forest_mut<R>(
+        &mut self,
+        f: impl for<'i> FnOnce(&mut gll::forest::ParseForest<'i, _G, I>, ParseNode<'i, _P>) -> R,
+    ) -> R {
+        self.forest_and_node.unpack_mut(|_, mut forest_and_node| {
+            let (ref mut forest, node) = *forest_and_node;
+            f(forest, node)
+        })
+    }
+}
+
+impl<T: ?Sized> OwnedHandle<gll::proc_macro::TokenStream, T> {
+    /// Like `Handle::to_token_stream`.
+    pub fn to_token_stream(&self) -> Option<gll::proc_macro::TokenStream> {
+        self.forest_and_node.unpack_ref(|_, forest_and_node| {
+            let (ref forest, node) = *forest_and_node;
+            gll::proc_macro::unflatten(&forest.input(node.range))
+        })
+    }
 }
 
 pub struct Handle<'a, 'i, I: gll::input::Input, T: ?Sized> {
@@ -32,7 +54,7 @@ impl<I: gll::input::Input, T: ?Sized> Clone for Handle<'_, '_, I, T> {
 }
 
 impl<'a, I: gll::input::Input, T: ?Sized> Handle<'a, '_, I, T> {
-    pub fn source(self) -> &'a I::Slice {
+    pub fn source(self) -> std::borrow::Cow<'a, I::Slice> {
         self.forest.input(self.node.range)
     }
     pub fn source_info(self) -> I::SourceInfo {
@@ -40,6 +62,69 @@ impl<'a, I: gll::input::Input, T: ?Sized> Handle<'a, '_, I, T> {
     }
 }
 
+impl<'a, 'i, I: gll::input::Input, T: ?Sized> Handle<'a, 'i, I, T> {
+    /// Find a named rule (this one, or one parsed as part of it) which was
+    /// parsed in more than one way, if any, picking the one matching the
+    /// shortest input, e.g. to show its derivations, through `Debug`.
+    pub fn find_ambiguity(self) -> Option<Ambiguity<Handle<'a, 'i, I, Any>>> {
+        let forest = self.forest;
+        let handle = |node| Handle::<_, Any> {
+            node,
+            forest,
+            _marker: PhantomData,
+        };
+        let mut found: Option<ParseNode<'i, _P>> = None;
+        let mut seen = HashSet::new();
+        let mut stack = vec![(self.node, None)];
+        while let Some((node, mut named_rule)) = stack.pop() {
+            if !seen.insert(node) {
+                continue;
+            }
+            if handle(node).is_named_rule() {
+                named_rule = Some(node);
+            }
+            let (children, ambiguous) = match forest.grammar.parse_node_shape(node.kind) {
+                ParseNodeShape::Opaque => (vec![], false),
+                ParseNodeShape::Alias(_) => (vec![forest.unpack_alias(node)], false),
+                ParseNodeShape::Opt(_) => (forest.unpack_opt(node).into_iter().collect(), false),
+                ParseNodeShape::Choice => {
+                    let choices: Vec<_> = forest.all_choices(node).collect();
+                    let ambiguous = choices.len() > 1;
+                    (choices, ambiguous)
+                }
+                ParseNodeShape::Split(..) => {
+                    let splits: Vec<_> = forest.all_splits(node).collect();
+                    let ambiguous = splits.len() > 1;
+                    (
+                        splits
+                            .into_iter()
+                            .flat_map(|(left, right)| vec![left, right])
+                            .collect(),
+                        ambiguous,
+                    )
+                }
+            };
+            if let (true, Some(named_rule)) = (ambiguous, named_rule) {
+                if found.map_or(true, |found| named_rule.range.len() < found.range.len()) {
+                    found = Some(named_rule);
+                }
+            }
+            stack.extend(children.into_iter().map(|child| (child, named_rule)));
+        }
+        found.map(|node| Ambiguity(handle(node)))
+    }
+}
+
+impl<T: ?Sized> Handle<'_, '_, gll::proc_macro::TokenStream, T> {
+    /// Rebuild the tokens this was parsed from, with their `Group`s
+    /// (see `gll::proc_macro::unflatten`), e.g. to splice into macro output,
+    /// or `None` if they aren't whole token trees (e.g. for a rule matching
+    /// only `"("`).
+    pub fn to_token_stream(self) -> Option<gll::proc_macro::TokenStream> {
+        gll::proc_macro::unflatten(&self.source())
+    }
+}
+
 impl<'a, 'i, I: gll::input::Input, T> From<Ambiguity<Handle<'a, 'i, I, T>>>
     for Ambiguity<Handle<'a, 'i, I, Any>>
 {
@@ -1,5 +1,6 @@
 use gll::forest::{nd::Arrow, traverse, GrammarReflector as _, ParseNode};
 use gll::parse_node::ParseNodeShape;
 use std::any;
+use std::collections::HashSet;
 use std::fmt;
 use std::marker::PhantomData;
@@ -0,0 +1,10 @@
+impl<T: ?Sized> Handle<'_, '_, gll::proc_macro::TokenStream, T> {
+    /// Parse the tokens this was parsed from with `syn`, e.g. into
+    /// a `syn::Expr`, for a handle obtained from `RUST_EXPR`.
+    pub fn to_syn<S: gll::syn::parse::Parse>(self) -> gll::syn::Result<S> {
+        let tokens = self.to_token_stream().ok_or_else(|| {
+            gll::syn::Error::new(self.source_info().join(), "unbalanced delimiters")
+        })?;
+        gll::syn::parse2(tokens)
+    }
+}
@@ -2,10 +2,12 @@
 // FIXME(eddyb) ensure `indexing::Range` can't break
 // `str`'s UTF-8 requirement, without overhead
 
-use indexing::container_traits::{Contiguous, Trustworthy};
-use indexing::{Container, Range};
+use indexing::container_traits::{Contiguous, GetUnchecked, Trustworthy};
+use indexing::{Container, Index, Range, Unknown};
 use std::ops::Deref;
 
+// NOTE `repr(transparent)` makes the casts from `str` (and `Box<str>`) sound.
+#[repr(transparent)]
 pub struct Str(str);
 
 impl<'a> From<&'a str> for &'a Str {
@@ -21,6 +23,20 @@ impl Deref for Str {
     }
 }
 
+impl From<String> for Box<Str> {
+    fn from(s: String) -> Self {
+        unsafe { Box::from_raw(Box::into_raw(s.into_boxed_str()) as *mut Str) }
+    }
+}
+
+// NOTE this allows `Cow<Str>`, e.g. for `RopeInput` slices spanning chunks.
+impl ToOwned for Str {
+    type Owned = Box<Str>;
+    fn to_owned(&self) -> Box<Str> {
+        self.0.to_string().into()
+    }
+}
+
 unsafe impl Trustworthy for Str {
     type Item = u8;
     fn base_len(&self) -> usize {
@@ -40,17 +56,17 @@ unsafe impl Contiguous for Str {
     }
 }
 
+// NOTE(eddyb) following code is copied from `str::is_char_boundary`:
+fn valid_utf8_start(bytes: &[u8]) -> bool {
+    match bytes.first() {
+        None => true,
+        // This is bit magic equivalent to: b < 128 || b >= 192
+        Some(&b) => (b as i8) >= -0x40,
+    }
+}
+
 impl Str {
     pub fn slice<'a, 'b, 'i>(input: &'b Container<'i, &'a Self>, range: Range<'i>) -> &'b Self {
-        // NOTE(eddyb) following code is copied from `str::is_char_boundary`:
-        let valid_utf8_start = |bytes: &[u8]| {
-            match bytes.first() {
-                None => true,
-                // This is bit magic equivalent to: b < 128 || b >= 192
-                Some(&b) => (b as i8) >= -0x40,
-            }
-        };
-
         let (_, after) = input.split_around(range);
         let (bytes, bytes_after) = (&input[range], &input[after]);
 
@@ -60,4 +76,88 @@ impl Str {
 
         unsafe { &*(bytes as *const [u8] as *const Str) }
     }
+
+    /// Whether `index` is at the start of a character (or at the end),
+    /// i.e. whether `slice` can start (or end) there.
+    pub fn is_char_boundary<'i>(input: &Container<'i, &Self>, index: Index<'i, Unknown>) -> bool {
+        valid_utf8_start(&input[input.split_at(index).1])
+    }
+}
+
+/// Text split into `&str` chunks (e.g. from a rope), as an `indexing` container
+/// of bytes, without concatenating the chunks.
+pub struct StrChunks<'a, R: ?Sized> {
+    pub rope: &'a R,
+    /// Each non-empty chunk, with its starting offset in the whole text.
+    chunks: Vec<(usize, &'a str)>,
+    len: usize,
+}
+
+// HACK the only way to get back at the `StrChunks` in a `Container`,
+// is to have it be its own "item", so that indexing by any `Index` returns it.
+unsafe impl<R: ?Sized> Trustworthy for StrChunks<'_, R> {
+    type Item = Self;
+    fn base_len(&self) -> usize {
+        self.len
+    }
+}
+
+unsafe impl<R: ?Sized> GetUnchecked for StrChunks<'_, R> {
+    unsafe fn xget_unchecked(&self, _: usize) -> &Self {
+        self
+    }
+}
+
+impl<'a, R: ?Sized> StrChunks<'a, R> {
+    pub fn new(rope: &'a R, chunks: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut len = 0;
+        let chunks = chunks
+            .into_iter()
+            .filter(|chunk| !chunk.is_empty())
+            .map(|chunk| {
+                let start = len;
+                len += chunk.len();
+                (start, chunk)
+            })
+            .collect();
+        StrChunks { rope, chunks, len }
+    }
+
+    /// Get the `StrChunks` out of its `Container`, unless it's empty.
+    pub fn get<'b, 'i>(input: &'b Container<'i, Self>) -> Option<&'b Self> {
+        input.range().nonempty().map(|range| &input[range.first()])
+    }
+
+    /// Whether `offset` is at the start of a character (or at the end).
+    pub fn is_char_boundary(&self, offset: usize) -> bool {
+        // Chunks are whole `str`s, so only the one containing `offset` matters.
+        match self.chunks.binary_search_by_key(&offset, |&(s, _)| s) {
+            Ok(_) | Err(0) => true,
+            Err(i) => {
+                let (s, chunk) = self.chunks[i - 1];
+                chunk.is_char_boundary(offset - s)
+            }
+        }
+    }
+
+    /// Iterate over the (non-empty) parts of chunks in `start..end`, in order.
+    pub fn pieces(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> impl DoubleEndedIterator<Item = &'a str> + '_ {
+        // The first chunk is the last one starting at or before `start`,
+        // and the chunks after the last one start at or after `end`.
+        let first = match self.chunks.binary_search_by_key(&start, |&(s, _)| s) {
+            Ok(i) => i,
+            Err(i) => i.saturating_sub(1),
+        };
+        let last = match self.chunks.binary_search_by_key(&end, |&(s, _)| s) {
+            Ok(i) | Err(i) => i,
+        };
+        self.chunks[first..last.max(first)]
+            .iter()
+            .map(move |&(s, chunk)| &chunk[start.max(s) - s..(end - s).min(chunk.len())])
+            .filter(|piece| !piece.is_empty())
+    }
 }
@@ -1,9 +1,12 @@
 use crate::indexing_str;
+use crate::scannerless::{CaseInsensitive, CharClass, ClassItem};
 use indexing::container_traits::Trustworthy;
 use indexing::{self, Container, Index, Unknown};
+use std::borrow::Cow;
 use std::cmp::Ordering;
 use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::io;
 use std::ops::{self, Deref, RangeInclusive};
 use std::str;
 
@@ -79,7 +82,9 @@ impl fmt::Debug for LineColumnRange {
 
 pub trait Input: Sized {
     type Container: Trustworthy;
-    type Slice: ?Sized;
+    /// What `slice` returns, borrowed from the input where possible
+    /// (`RopeInput` has to copy slices which span several chunks).
+    type Slice: ?Sized + ToOwned;
     type SourceInfo: fmt::Debug;
     // FIXME(eddyb) remove - replace with `SourceInfo` for the affected range
     type SourceInfoPoint: fmt::Debug;
@@ -87,7 +92,7 @@ pub trait Input: Sized {
     fn slice<'a, 'i>(
         input: &'a Container<'i, Self::Container>,
         range: Range<'i>,
-    ) -> &'a Self::Slice;
+    ) -> Cow<'a, Self::Slice>;
     fn source_info<'i>(
         input: &Container<'i, Self::Container>,
         range: Range<'i>,
@@ -96,9 +101,18 @@ pub trait Input: Sized {
         input: &Container<'i, Self::Container>,
         index: Index<'i, Unknown>,
     ) -> Self::SourceInfoPoint;
+
+    /// Whether a parse can start at `index`, e.g. not in the middle of
+    /// a character, for inputs of bytes (used by `Runtime::parse_suffix`).
+    fn is_boundary<'i>(
+        _input: &Container<'i, Self::Container>,
+        _index: Index<'i, Unknown>,
+    ) -> bool {
+        true
+    }
 }
 
-impl<T> Input for &[T] {
+impl<T: Clone> Input for &[T] {
     type Container = Self;
     type Slice = [T];
     type SourceInfo = ops::Range<usize>;
@@ -109,8 +123,8 @@ impl<T> Input for &[T] {
     fn slice<'b, 'i>(
         input: &'b Container<'i, Self::Container>,
         range: Range<'i>,
-    ) -> &'b Self::Slice {
-        &input[range.0]
+    ) -> Cow<'b, Self::Slice> {
+        Cow::Borrowed(&input[range.0])
     }
     fn source_info<'i>(_: &Container<'i, Self::Container>, range: Range<'i>) -> Self::SourceInfo {
         range.as_range()
@@ -134,8 +148,8 @@ impl<'a> Input for &'a str {
     fn slice<'b, 'i>(
         input: &'b Container<'i, Self::Container>,
         range: Range<'i>,
-    ) -> &'b Self::Slice {
-        indexing_str::Str::slice(input, range.0)
+    ) -> Cow<'b, Self::Slice> {
+        Cow::Borrowed(&**indexing_str::Str::slice(input, range.0))
     }
     fn source_info<'i>(
         input: &Container<'i, Self::Container>,
@@ -144,7 +158,7 @@ impl<'a> Input for &'a str {
         let start = Self::source_info_point(input, range.first());
         // HACK(eddyb) add up `LineColumn`s to avoid counting twice.
         // Ideally we'd cache around a line map, like rustc's `SourceMap`.
-        let mut end = LineColumn::count(Self::slice(input, range));
+        let mut end = LineColumn::count(&Self::slice(input, range));
         end.line += start.line;
         if end.line == start.line {
             end.column += start.column;
@@ -156,13 +170,22 @@ impl<'a> Input for &'a str {
         index: Index<'i, Unknown>,
     ) -> Self::SourceInfoPoint {
         let prefix_range = Range(input.split_at(index).0);
-        LineColumn::count(Self::slice(input, prefix_range))
+        LineColumn::count(&Self::slice(input, prefix_range))
+    }
+    fn is_boundary<'i>(input: &Container<'i, Self::Container>, index: Index<'i, Unknown>) -> bool {
+        indexing_str::Str::is_char_boundary(input, index)
     }
 }
 
 pub trait InputMatch<Pat> {
     fn match_left(&self, pat: &'static Pat) -> Option<usize>;
     fn match_right(&self, pat: &'static Pat) -> Option<usize>;
+
+    /// Whether `match_left` could succeed if more input was appended to
+    /// `self` (only needed for inputs which can be read incrementally).
+    fn match_left_needs_more(&self, _pat: &'static Pat) -> bool {
+        false
+    }
 }
 
 impl<T: PartialEq> InputMatch<&'static [T]> for [T] {
@@ -180,6 +203,9 @@ impl<T: PartialEq> InputMatch<&'static [T]> for [T] {
             None
         }
     }
+    fn match_left_needs_more(&self, pat: &&[T]) -> bool {
+        self.len() < pat.len() && pat.starts_with(self)
+    }
 }
 
 impl<T: PartialOrd> InputMatch<RangeInclusive<T>> for [T] {
@@ -199,6 +225,9 @@ impl<T: PartialOrd> InputMatch<RangeInclusive<T>> for [T] {
             None
         }
     }
+    fn match_left_needs_more(&self, _: &RangeInclusive<T>) -> bool {
+        self.is_empty()
+    }
 }
 
 impl InputMatch<&'static str> for str {
@@ -216,6 +245,39 @@ impl InputMatch<&'static str> for str {
             None
         }
     }
+    fn match_left_needs_more(&self, pat: &&str) -> bool {
+        self.len() < pat.len() && pat.starts_with(self)
+    }
+}
+
+/// Match `pat` against the start of `input` (both given as characters, in
+/// the direction of matching), returning the number of input bytes matched,
+/// or `Err(true)` if `input` ran out before a mismatch could be found.
+fn match_case_insensitive(
+    mut input: impl Iterator<Item = char>,
+    pat: impl Iterator<Item = char>,
+) -> Result<usize, bool> {
+    let mut len = 0;
+    for p in pat {
+        let c = input.next().ok_or(true)?;
+        if !CaseInsensitive::chars_eq(c, p) {
+            return Err(false);
+        }
+        len += c.len_utf8();
+    }
+    Ok(len)
+}
+
+impl InputMatch<CaseInsensitive<&'static str>> for str {
+    fn match_left(&self, pat: &CaseInsensitive<&str>) -> Option<usize> {
+        match_case_insensitive(self.chars(), pat.0.chars()).ok()
+    }
+    fn match_right(&self, pat: &CaseInsensitive<&str>) -> Option<usize> {
+        match_case_insensitive(self.chars().rev(), pat.0.chars().rev()).ok()
+    }
+    fn match_left_needs_more(&self, pat: &CaseInsensitive<&str>) -> bool {
+        match_case_insensitive(self.chars(), pat.0.chars()) == Err(true)
+    }
 }
 
 impl InputMatch<RangeInclusive<char>> for str {
@@ -235,4 +297,570 @@ impl InputMatch<RangeInclusive<char>> for str {
             None
         }
     }
+    fn match_left_needs_more(&self, _: &RangeInclusive<char>) -> bool {
+        self.is_empty()
+    }
+}
+
+impl InputMatch<CharClass<&'static [ClassItem]>> for str {
+    fn match_left(&self, pat: &CharClass<&[ClassItem]>) -> Option<usize> {
+        let c = self.chars().next()?;
+        if pat.contains(c) {
+            Some(c.len_utf8())
+        } else {
+            None
+        }
+    }
+    fn match_right(&self, pat: &CharClass<&[ClassItem]>) -> Option<usize> {
+        let c = self.chars().rev().next()?;
+        if pat.contains(c) {
+            Some(c.len_utf8())
+        } else {
+            None
+        }
+    }
+    fn match_left_needs_more(&self, _: &CharClass<&[ClassItem]>) -> bool {
+        self.is_empty()
+    }
+}
+
+/// Patterns which can be described in error messages, as expected input.
+// NOTE `Sync` is required so that `&'static dyn DescribePat` can be
+// sent between threads (see the `parallel` feature).
+pub trait DescribePat: fmt::Debug + Sync {
+    /// Human-readable description, e.g. "`,`" or "identifier".
+    fn describe(&self) -> String;
+}
+
+impl DescribePat for &'static str {
+    fn describe(&self) -> String {
+        format!("`{}`", self)
+    }
+}
+
+impl DescribePat for CaseInsensitive<&'static str> {
+    fn describe(&self) -> String {
+        format!("case-insensitive `{}`", self.0)
+    }
+}
+
+impl<T: fmt::Debug + Sync> DescribePat for RangeInclusive<T> {
+    fn describe(&self) -> String {
+        format!("`{:?}..={:?}`", self.start(), self.end())
+    }
+}
+
+impl DescribePat for CharClass<&'static [ClassItem]> {
+    fn describe(&self) -> String {
+        format!("`{:?}`", self)
+    }
+}
+
+/// Matching against the `range` of an `input` (i.e. of its `Container`).
+/// Usually implemented through `InputMatch` on the `Slice` of `range`,
+/// except for inputs which can't be sliced cheaply (e.g. `RopeInput`).
+pub trait InputRangeMatch<Pat>: Input {
+    fn match_left<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pat: &'static Pat,
+    ) -> Option<usize>;
+    fn match_right<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pat: &'static Pat,
+    ) -> Option<usize>;
+    fn match_left_needs_more<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pat: &'static Pat,
+    ) -> bool;
+}
+
+impl<I: Input, Pat> InputRangeMatch<Pat> for I
+where
+    I::Slice: InputMatch<Pat>,
+{
+    fn match_left<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pat: &'static Pat,
+    ) -> Option<usize> {
+        I::slice(input, range).match_left(pat)
+    }
+    fn match_right<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pat: &'static Pat,
+    ) -> Option<usize> {
+        I::slice(input, range).match_right(pat)
+    }
+    fn match_left_needs_more<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pat: &'static Pat,
+    ) -> bool {
+        I::slice(input, range).match_left_needs_more(pat)
+    }
+}
+
+/// Many literal patterns, matched all at once by walking a trie of their
+/// elements (e.g. bytes of strings, or tokens), instead of one at a time.
+pub struct Trie<T: 'static, Pat: 'static> {
+    /// The patterns, in the order given to `InputRangeMatchAll`'s callback.
+    pub pats: &'static [Pat],
+
+    /// All the nodes of the trie, starting with the root.
+    pub nodes: &'static [TrieNode<T>],
+}
+
+pub struct TrieNode<T: 'static> {
+    /// The (indices of) patterns ending at this node.
+    pub accepts: &'static [usize],
+
+    /// The nodes reached by matching each element.
+    pub edges: &'static [(T, usize)],
+}
+
+impl<T, Pat> Trie<T, Pat> {
+    /// Walk the trie along `input`, following all the edges which `matches`
+    /// the next element, and calling `f` with each pattern reached, returning
+    /// whether `input` ran out before the walk could end.
+    pub fn walk<E>(
+        &self,
+        input: impl IntoIterator<Item = E>,
+        matches: impl Fn(&T, &E) -> bool,
+        f: &mut dyn FnMut(usize),
+    ) -> bool {
+        let mut input = input.into_iter();
+        let mut nodes = vec![0];
+        loop {
+            for &node in &nodes {
+                for &i in self.nodes[node].accepts {
+                    f(i);
+                }
+            }
+            if nodes.iter().all(|&node| self.nodes[node].edges.is_empty()) {
+                return false;
+            }
+            let elem = match input.next() {
+                Some(elem) => elem,
+                None => return true,
+            };
+            nodes = nodes
+                .iter()
+                .flat_map(|&node| self.nodes[node].edges)
+                .filter(|(x, _)| matches(x, &elem))
+                .map(|&(_, next)| next)
+                .collect();
+        }
+    }
+}
+
+/// Matching all of the patterns in `Pats` (e.g. a `Trie`) at once, against
+/// the `range` of an `input`, e.g. to pick between alternatives quickly.
+pub trait InputRangeMatchAll<Pats>: Input {
+    /// Call `f` with the index of each pattern matching at the start of
+    /// `range`, and return whether more of them could match if more input
+    /// was appended (like `InputMatch::match_left_needs_more`).
+    fn match_all_left<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pats: &'static Pats,
+        f: &mut dyn FnMut(usize),
+    ) -> bool;
+}
+
+impl<I: Input, Pats> InputRangeMatchAll<Pats> for I
+where
+    I::Slice: SliceMatchAll<Pats>,
+{
+    fn match_all_left<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pats: &'static Pats,
+        f: &mut dyn FnMut(usize),
+    ) -> bool {
+        I::slice(input, range).match_all_left(pats, f)
+    }
+}
+
+/// Matching all of the patterns in `Pats` at once, against a slice of
+/// the input, used by the `InputRangeMatchAll` impl for most inputs.
+pub trait SliceMatchAll<Pats> {
+    /// Like `InputRangeMatchAll::match_all_left`, but for all of `self`.
+    fn match_all_left(&self, pats: &'static Pats, f: &mut dyn FnMut(usize)) -> bool;
+}
+
+impl SliceMatchAll<Trie<u8, &'static str>> for str {
+    fn match_all_left(
+        &self,
+        trie: &'static Trie<u8, &'static str>,
+        f: &mut dyn FnMut(usize),
+    ) -> bool {
+        trie.walk(self.bytes(), |a, b| a == b, f)
+    }
+}
+
+/// Text stored in chunks (e.g. by a rope data structure), which can
+/// be parsed (through `RopeInput`) without concatenating the chunks.
+pub trait Rope {
+    /// All of the chunks of text, in order.
+    fn chunks(&self) -> Box<dyn Iterator<Item = &str> + '_>;
+
+    /// The line and column of the character starting at `byte_offset`
+    /// (or of the end of the text), ideally using a line index.
+    fn line_column(&self, byte_offset: usize) -> LineColumn;
+}
+
+pub struct RopeInput<'a, R: ?Sized>(pub &'a R);
+
+impl<'a, R: ?Sized> RopeInput<'a, R> {
+    fn pieces<'b, 'i>(
+        input: &'b Container<'i, indexing_str::StrChunks<'a, R>>,
+        range: Range<'i>,
+    ) -> impl DoubleEndedIterator<Item = &'a str> + 'b {
+        let (start, end) = (range.start(), range.end());
+        indexing_str::StrChunks::get(input)
+            .into_iter()
+            .flat_map(move |chunks| chunks.pieces(start, end))
+    }
+}
+
+impl<'a, R: ?Sized + Rope> Input for RopeInput<'a, R> {
+    type Container = indexing_str::StrChunks<'a, R>;
+    // NOTE not `str`, to avoid the blanket `InputRangeMatch` impl, which
+    // would slice (and therefore copy) across chunks for every match.
+    type Slice = indexing_str::Str;
+    type SourceInfo = LineColumnRange;
+    type SourceInfoPoint = LineColumn;
+    fn to_container(self) -> Self::Container {
+        indexing_str::StrChunks::new(self.0, self.0.chunks())
+    }
+    fn slice<'b, 'i>(
+        input: &'b Container<'i, Self::Container>,
+        range: Range<'i>,
+    ) -> Cow<'b, Self::Slice> {
+        let mut pieces = Self::pieces(input, range);
+        match (pieces.next(), pieces.next()) {
+            (None, _) => Cow::Borrowed("".into()),
+            (Some(piece), None) => Cow::Borrowed(piece.into()),
+            (Some(_), Some(_)) => Cow::Owned(Self::pieces(input, range).collect::<String>().into()),
+        }
+    }
+    fn source_info<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+    ) -> Self::SourceInfo {
+        match indexing_str::StrChunks::get(input) {
+            Some(chunks) => LineColumnRange {
+                start: chunks.rope.line_column(range.start()),
+                end: chunks.rope.line_column(range.end()),
+            },
+            None => LineColumnRange {
+                start: LineColumn::default(),
+                end: LineColumn::default(),
+            },
+        }
+    }
+    fn source_info_point<'i>(
+        input: &Container<'i, Self::Container>,
+        index: Index<'i, Unknown>,
+    ) -> Self::SourceInfoPoint {
+        indexing_str::StrChunks::get(input).map_or(LineColumn::default(), |chunks| {
+            chunks.rope.line_column(index.integer())
+        })
+    }
+    fn is_boundary<'i>(input: &Container<'i, Self::Container>, index: Index<'i, Unknown>) -> bool {
+        indexing_str::StrChunks::get(input)
+            .map_or(true, |chunks| chunks.is_char_boundary(index.integer()))
+    }
+}
+
+impl<R: ?Sized + Rope> InputRangeMatch<&'static str> for RopeInput<'_, R> {
+    fn match_left<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pat: &&str,
+    ) -> Option<usize> {
+        let bytes = Self::pieces(input, range).flat_map(|piece| piece.bytes());
+        if bytes.zip(pat.bytes()).take_while(|(a, b)| a == b).count() == pat.len() {
+            Some(pat.len())
+        } else {
+            None
+        }
+    }
+    fn match_right<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pat: &&str,
+    ) -> Option<usize> {
+        let bytes = Self::pieces(input, range).flat_map(|piece| piece.bytes());
+        let pat_bytes = pat.bytes().rev();
+        if bytes
+            .rev()
+            .zip(pat_bytes)
+            .take_while(|(a, b)| a == b)
+            .count()
+            == pat.len()
+        {
+            Some(pat.len())
+        } else {
+            None
+        }
+    }
+    fn match_left_needs_more<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pat: &&str,
+    ) -> bool {
+        let bytes = Self::pieces(input, range).flat_map(|piece| piece.bytes());
+        range.len() < pat.len()
+            && bytes.zip(pat.bytes()).take_while(|(a, b)| a == b).count() == range.len()
+    }
+}
+
+impl<R: ?Sized + Rope> InputRangeMatch<CaseInsensitive<&'static str>> for RopeInput<'_, R> {
+    fn match_left<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pat: &CaseInsensitive<&str>,
+    ) -> Option<usize> {
+        let chars = Self::pieces(input, range).flat_map(|piece| piece.chars());
+        match_case_insensitive(chars, pat.0.chars()).ok()
+    }
+    fn match_right<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pat: &CaseInsensitive<&str>,
+    ) -> Option<usize> {
+        let chars = Self::pieces(input, range)
+            .rev()
+            .flat_map(|piece| piece.chars().rev());
+        match_case_insensitive(chars, pat.0.chars().rev()).ok()
+    }
+    fn match_left_needs_more<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pat: &CaseInsensitive<&str>,
+    ) -> bool {
+        let chars = Self::pieces(input, range).flat_map(|piece| piece.chars());
+        match_case_insensitive(chars, pat.0.chars()) == Err(true)
+    }
+}
+
+impl<R: ?Sized + Rope> InputRangeMatch<RangeInclusive<char>> for RopeInput<'_, R> {
+    fn match_left<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pat: &RangeInclusive<char>,
+    ) -> Option<usize> {
+        // NOTE chunks are `str`s, so characters can't span across them.
+        let c = Self::pieces(input, range).next()?.chars().next()?;
+        if *pat.start() <= c && c <= *pat.end() {
+            Some(c.len_utf8())
+        } else {
+            None
+        }
+    }
+    fn match_right<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pat: &RangeInclusive<char>,
+    ) -> Option<usize> {
+        let c = Self::pieces(input, range)
+            .next_back()?
+            .chars()
+            .next_back()?;
+        if *pat.start() <= c && c <= *pat.end() {
+            Some(c.len_utf8())
+        } else {
+            None
+        }
+    }
+    fn match_left_needs_more<'i>(
+        _: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        _: &RangeInclusive<char>,
+    ) -> bool {
+        range.len() == 0
+    }
+}
+
+impl<R: ?Sized + Rope> InputRangeMatch<CharClass<&'static [ClassItem]>> for RopeInput<'_, R> {
+    fn match_left<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pat: &CharClass<&[ClassItem]>,
+    ) -> Option<usize> {
+        // NOTE chunks are `str`s, so characters can't span across them.
+        let c = Self::pieces(input, range).next()?.chars().next()?;
+        if pat.contains(c) {
+            Some(c.len_utf8())
+        } else {
+            None
+        }
+    }
+    fn match_right<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        pat: &CharClass<&[ClassItem]>,
+    ) -> Option<usize> {
+        let c = Self::pieces(input, range)
+            .next_back()?
+            .chars()
+            .next_back()?;
+        if pat.contains(c) {
+            Some(c.len_utf8())
+        } else {
+            None
+        }
+    }
+    fn match_left_needs_more<'i>(
+        _: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        _: &CharClass<&[ClassItem]>,
+    ) -> bool {
+        range.len() == 0
+    }
+}
+
+impl<R: ?Sized + Rope> InputRangeMatchAll<Trie<u8, &'static str>> for RopeInput<'_, R> {
+    fn match_all_left<'i>(
+        input: &Container<'i, Self::Container>,
+        range: Range<'i>,
+        trie: &'static Trie<u8, &'static str>,
+        f: &mut dyn FnMut(usize),
+    ) -> bool {
+        let bytes = Self::pieces(input, range).flat_map(|piece| piece.bytes());
+        trie.walk(bytes, |a, b| a == b, f)
+    }
+}
+
+/// UTF-8 input read incrementally from an `io::Read`, which only buffers
+/// the input that hasn't been consumed yet (see `ReadInput::consume_with`).
+///
+/// This isn't an `Input` itself: instead, the input is consumed one prefix
+/// (e.g. a top-level list element) at a time, each parsed on its own (e.g.
+/// with `parse_prefix`), and parsed again from its start if it turns out
+/// to need more input, so only the prefix being parsed is kept in memory.
+/// That means a whole list can't be parsed (into one forest) incrementally,
+/// and that each element has to be parseable without the ones before it.
+//
+// FIXME support parsing a whole input while it's being read, which
+// needs `Input::Container`s that can grow (and forget their prefix) while
+// parsing, whereas `indexing` containers have a fixed length, for as long as
+// any `Index`/`Range` into them (e.g. in the GSS/memoizer/forest) is alive.
+pub struct ReadInput<R> {
+    reader: R,
+    buffer: String,
+    /// The length of the prefix of `buffer` that was already consumed,
+    /// which is only removed before reading more (to avoid quadratic costs).
+    start: usize,
+    /// Bytes read after `buffer`, which don't yet form a whole UTF-8 character.
+    incomplete: Vec<u8>,
+    eof: bool,
+    /// The number of bytes consumed so far (i.e. the offset of `buffer[start..]`).
+    consumed: usize,
+}
+
+impl<R: io::Read> ReadInput<R> {
+    pub fn new(reader: R) -> Self {
+        ReadInput {
+            reader,
+            buffer: String::new(),
+            start: 0,
+            incomplete: vec![],
+            eof: false,
+            consumed: 0,
+        }
+    }
+
+    /// The number of bytes consumed so far, which is also the offset
+    /// of the buffered input, relative to the start of the whole input.
+    pub fn consumed(&self) -> usize {
+        self.consumed
+    }
+
+    /// Repeatedly call `f` with the buffered input, and whether that's all
+    /// of the remaining input, until all of the input has been consumed.
+    /// `f` returns the length of the prefix it consumed (which is then
+    /// discarded from the buffer), or `None` to request more input first,
+    /// e.g. when a `parse_prefix` result has `reached_end` set (but only
+    /// if not at the end of the input, where an `UnexpectedEof` error is
+    /// returned instead, as is an `InvalidData` one for empty prefixes).
+    pub fn consume_with<E: From<io::Error>>(
+        &mut self,
+        mut f: impl FnMut(&str, bool) -> Result<Option<usize>, E>,
+    ) -> Result<(), E> {
+        loop {
+            if self.start == self.buffer.len() && !self.eof {
+                self.read_more()?;
+            }
+            if self.start == self.buffer.len() && self.eof {
+                return Ok(());
+            }
+            match f(&self.buffer[self.start..], self.eof)? {
+                Some(0) => {
+                    // NOTE an empty prefix would never make progress.
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "ReadInput::consume_with: empty prefix consumed",
+                    )
+                    .into());
+                }
+                Some(len) => {
+                    self.start += len;
+                    self.consumed += len;
+                }
+                None if self.eof => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "ReadInput::consume_with: more input needed after the end",
+                    )
+                    .into());
+                }
+                None => self.read_more()?,
+            }
+        }
+    }
+
+    /// Read (at least) one more character, unless EOF was reached.
+    /// Reads are sized after the buffer, so that large elements don't
+    /// get parsed again after every small read.
+    fn read_more(&mut self) -> io::Result<()> {
+        self.buffer.drain(..self.start);
+        self.start = 0;
+
+        let mut chunk = vec![0; self.buffer.len().max(8 * 1024)];
+        let old_len = self.buffer.len();
+        while !self.eof && self.buffer.len() == old_len {
+            let n = match self.reader.read(&mut chunk) {
+                Ok(n) => n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if n == 0 {
+                self.eof = true;
+                if !self.incomplete.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "stream did not end on a UTF-8 character boundary",
+                    ));
+                }
+                break;
+            }
+            self.incomplete.extend_from_slice(&chunk[..n]);
+            let valid = match str::from_utf8(&self.incomplete) {
+                Ok(s) => s.len(),
+                // Only an incomplete character at the end can become valid later.
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            };
+            self.buffer
+                .push_str(str::from_utf8(&self.incomplete[..valid]).unwrap());
+            self.incomplete.drain(..valid);
+        }
+        Ok(())
+    }
 }
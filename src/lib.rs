@@ -7,6 +7,10 @@ mod high;
 #[allow(unsafe_code)]
 mod indexing_str;
 
+#[forbid(unsafe_code)]
+pub mod analysis;
+#[forbid(unsafe_code)]
+pub mod export;
 #[forbid(unsafe_code)]
 pub mod forest;
 #[forbid(unsafe_code)]
@@ -32,4 +36,8 @@ mod proc_macro_input;
 #[forbid(unsafe_code)]
 mod parse_grammar;
 
-pub use parse_grammar::parse_grammar;
+pub use parse_grammar::{format_grammar, parse_grammar};
+
+// NOTE used by generated code, which might not depend on `syn` itself.
+#[cfg(feature = "syn")]
+pub use syn;
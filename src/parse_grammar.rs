@@ -8,109 +8,346 @@ use crate as gll;
 include!(concat!(env!("OUT_DIR"), "/parse_grammar.rs"));
 
 use crate::parser::ParseError;
-use crate::proc_macro::{FlatToken, Span, TokenStream};
-use crate::scannerless::Pat as SPat;
+use crate::proc_macro::{flatten_none_groups, FlatToken, Span, TokenStream};
+use crate::scannerless::{CharClass, ClassItem as SClassItem, Pat as SPat, UnicodeProperty};
 use grammer::context::Context;
 use grammer::rule;
+use std::convert::TryFrom;
+use std::fmt;
 use std::hash::Hash;
+use std::iter;
 use std::ops::Bound;
 use std::str::FromStr;
 
-pub fn parse_grammar<Pat: Eq + Hash + From<SPat>>(
+/// Parse a grammar, lowering its patterns to `Pat` (which can reject
+/// the patterns it doesn't support, with a description of those it does).
+/// Invalid grammars (e.g. with unknown Unicode properties) are reported
+/// as `ParseError`s, just like grammars that don't parse at all.
+pub fn parse_grammar<Pat: Eq + Hash + TryFrom<SPat>>(
     cx: &mut Context<Pat>,
     stream: TokenStream,
-) -> Result<grammer::Grammar, ParseError<Span>> {
+) -> Result<grammer::Grammar, ParseError<Span>>
+where
+    Pat::Error: fmt::Display,
+{
     let mut grammar = grammer::Grammar::new();
-    Grammar::parse(stream)?.with(|g| {
+    // Grammars can't contain `NONE_GROUP`s, but `macro_rules!` macros
+    // generating grammars may wrap substituted fragments in them.
+    Grammar::parse(flatten_none_groups(stream))?.with(|g| -> Result<_, ParseError<Span>> {
         for rule_def in g.one().unwrap().rules {
             let rule_def = rule_def.unwrap().one().unwrap();
-            let name = match rule_def.name.source() {
+            let name = match &*rule_def.name.source() {
                 [FlatToken::Ident(ident)] => ident.to_string(),
                 _ => unreachable!(),
             };
-            grammar.define(cx.intern(name), rule_def.rule.one().unwrap().lower(cx));
+            grammar.define(cx.intern(name), rule_def.rule.one().unwrap().lower(cx)?);
         }
-    });
+        Ok(())
+    })?;
     Ok(grammar)
 }
 
+/// An error in a grammar which parsed successfully, reported at `handle`,
+/// as if the input was `expected` to be something else, in `rule`.
+fn lowering_error<T>(
+    handle: Handle<'_, '_, TokenStream, T>,
+    rule: &str,
+    expected: impl IntoIterator<Item = String>,
+) -> ParseError<Span> {
+    ParseError {
+        at: handle.source_info().join(),
+        at_end: false,
+        expected: iter::once((rule.to_string(), expected.into_iter().collect())).collect(),
+    }
+}
+
+/// Convert `pat` (written at `handle`) to a `Pat`, if it supports it.
+fn lower_pat<Pat: TryFrom<SPat>, T>(
+    handle: Handle<'_, '_, TokenStream, T>,
+    pat: SPat,
+) -> Result<Pat, ParseError<Span>>
+where
+    Pat::Error: fmt::Display,
+{
+    Pat::try_from(pat)
+        .map_err(|supported| lowering_error(handle, "Pattern", iter::once(supported.to_string())))
+}
+
+/// Rules wider than this (when on one line) have their alternatives
+/// formatted on separate lines, by `format_grammar`.
+const FORMAT_WIDTH: usize = 80;
+
+/// Format a grammar, written in the syntax `parse_grammar` accepts, canonically,
+/// i.e. with one rule per line (or, for rules with alternatives, which don't
+/// fit on one line, one alternative per line), and the same spacing everywhere.
+/// The result parses (through `parse_grammar`) to the same grammar, though it
+/// lacks any comments, as those aren't kept in `TokenStream`s to begin with.
+pub fn format_grammar(stream: TokenStream) -> Result<String, ParseError<Span>> {
+    let mut out = String::new();
+    Grammar::parse(flatten_none_groups(stream))?.with(|g| {
+        for rule_def in g.one().unwrap().rules {
+            let rule_def = rule_def.unwrap().one().unwrap();
+            let name = token_text(rule_def.name);
+            let cases = rule_def.rule.one().unwrap().format_cases();
+            let line = format!("{} = {};", name, cases.join(" | "));
+            if cases.len() == 1 || line.chars().count() <= FORMAT_WIDTH {
+                out += &line;
+                out.push('\n');
+            } else {
+                out += &format!("{} =\n", name);
+                for case in cases {
+                    out += &format!("    | {}\n", case);
+                }
+                out += "    ;\n";
+            }
+        }
+    });
+    Ok(out)
+}
+
 impl Or<'_, '_, TokenStream> {
-    fn lower<Pat: Eq + Hash + From<SPat>>(
+    fn format_cases(self) -> Vec<String> {
+        self.rules
+            .map(|rule| rule.unwrap().one().unwrap().format())
+            .collect()
+    }
+
+    fn lower<Pat: Eq + Hash + TryFrom<SPat>>(
         self,
         cx: &mut Context<Pat>,
-    ) -> rule::RuleWithNamedFields {
+    ) -> Result<rule::RuleWithNamedFields, ParseError<Span>>
+    where
+        Pat::Error: fmt::Display,
+    {
         let mut rules = self.rules.map(|rule| rule.unwrap().one().unwrap());
-        let first = rules.next().unwrap().lower(cx);
-        rules.fold(first, |a, b| (a | b.lower(cx)).finish(cx))
+        let mut or = rules.next().unwrap().lower(cx)?;
+        for rule in rules {
+            or = (or | rule.lower(cx)?).finish(cx);
+        }
+        Ok(or)
     }
 }
 
 impl Concat<'_, '_, TokenStream> {
-    fn lower<Pat: Eq + Hash + From<SPat>>(
+    fn format(self) -> String {
+        let mut rules = self
+            .rules
+            .map(|rule| rule.unwrap().one().unwrap())
+            .peekable();
+        let mut formatted = vec![];
+        while let Some(rule) = rules.next() {
+            // HACK keep `i"..."` together (see also `lower` below).
+            let rule = match rules
+                .peek()
+                .and_then(|next| rule.case_insensitive_str(next))
+            {
+                Some(_) => {
+                    let next = rules.next().unwrap();
+                    format!("{}i{}", rule.format_field(), next.format())
+                }
+                None => rule.format(),
+            };
+            formatted.push(rule);
+        }
+        formatted.join(" ")
+    }
+
+    fn lower<Pat: Eq + Hash + TryFrom<SPat>>(
         self,
         cx: &mut Context<Pat>,
-    ) -> rule::RuleWithNamedFields {
-        self.rules
+    ) -> Result<rule::RuleWithNamedFields, ParseError<Span>>
+    where
+        Pat::Error: fmt::Display,
+    {
+        let mut rules = self
+            .rules
             .map(|rule| rule.unwrap().one().unwrap())
-            .fold(rule::empty().finish(cx), |a, b| {
-                (a + b.lower(cx)).finish(cx)
-            })
+            .peekable();
+        let mut concat = rule::empty().finish(cx);
+        while let Some(rule) = rules.next() {
+            // HACK `i"..."` is tokenized as the identifier `i`, followed
+            // by a string literal, so it parses as a call to a rule named `i`,
+            // and the two have to be recombined into a case-insensitive string.
+            let rule = match rules
+                .peek()
+                .and_then(|next| rule.case_insensitive_str(next))
+            {
+                Some(s) => {
+                    let next = rules.next().unwrap();
+                    let pat = lower_pat(next.rule, SPat::CaseInsensitive(s))?;
+                    let eat = rule::eat(pat).finish(cx);
+                    rule.lower_field(cx, next.lower_modifier(cx, eat)?)
+                }
+                None => rule.lower(cx)?,
+            };
+            concat = (concat + rule).finish(cx);
+        }
+        Ok(concat)
     }
 }
 
 impl Rule<'_, '_, TokenStream> {
-    fn lower<Pat: Eq + Hash + From<SPat>>(
+    fn format(&self) -> String {
+        let mut out = self.format_field();
+        out += &self.rule.one().unwrap().format();
+        if let Some(modifier) = self.modifier {
+            out += &modifier.one().unwrap().format();
+        }
+        out
+    }
+
+    fn format_field(&self) -> String {
+        match self.field {
+            Some(field) => format!("{}:", token_text(field)),
+            None => String::new(),
+        }
+    }
+
+    fn lower<Pat: Eq + Hash + TryFrom<SPat>>(
         self,
         cx: &mut Context<Pat>,
+    ) -> Result<rule::RuleWithNamedFields, ParseError<Span>>
+    where
+        Pat::Error: fmt::Display,
+    {
+        let rule = self.rule.one().unwrap().lower(cx)?;
+        let rule = self.lower_modifier(cx, rule)?;
+        Ok(self.lower_field(cx, rule))
+    }
+
+    fn lower_modifier<Pat: Eq + Hash + TryFrom<SPat>>(
+        &self,
+        cx: &mut Context<Pat>,
+        rule: rule::RuleWithNamedFields,
+    ) -> Result<rule::RuleWithNamedFields, ParseError<Span>>
+    where
+        Pat::Error: fmt::Display,
+    {
+        match self.modifier {
+            Some(modifier) => modifier.one().unwrap().lower(cx, rule),
+            None => Ok(rule),
+        }
+    }
+
+    fn lower_field<Pat: Eq + Hash>(
+        &self,
+        cx: &mut Context<Pat>,
+        rule: rule::RuleWithNamedFields,
     ) -> rule::RuleWithNamedFields {
-        let mut rule = self.rule.one().unwrap().lower(cx);
-        if let Some(modifier) = self.modifier {
-            rule = modifier.one().unwrap().lower(cx, rule);
+        match self.field {
+            Some(field) => {
+                let field = match &*field.source() {
+                    [FlatToken::Ident(ident)] => ident.to_string(),
+                    _ => unreachable!(),
+                };
+                rule.field(&field).finish(cx)
+            }
+            None => rule,
         }
-        if let Some(field) = self.field {
-            let field = match field.source() {
-                [FlatToken::Ident(ident)] => ident.to_string(),
-                _ => unreachable!(),
-            };
-            rule = rule.field(&field).finish(cx);
+    }
+
+    /// The (unescaped) string `next` matches, if `self` is a bare `i` (i.e.
+    /// a call to a rule named `i`) and `next` a string without a field,
+    /// written right after the `i` (unlike e.g. `i "..."`, see `adjacent`).
+    fn case_insensitive_str(&self, next: &Self) -> Option<String> {
+        let i = match self.rule.one().unwrap() {
+            Primary::Call(name) if self.modifier.is_none() => match &*name.source() {
+                [FlatToken::Ident(ident)] if ident == "i" => name.source_info(),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        if next.field.is_some() {
+            return None;
+        }
+        match next.rule.one().unwrap() {
+            Primary::Eat(pat) => match pat.one().unwrap() {
+                Pattern::Str(s) if adjacent(i.last, s.source_info().first) => Some(unescape(s)),
+                _ => None,
+            },
+            _ => None,
         }
-        rule
     }
 }
 
 impl Primary<'_, '_, TokenStream> {
-    fn lower<Pat: Eq + Hash + From<SPat>>(
+    fn format(self) -> String {
+        match self {
+            Primary::Eat(pat) => pat.one().unwrap().format(),
+            Primary::Call(name) => token_text(name),
+            Primary::Group { or } => match or {
+                Some(or) => format!("{{ {} }}", or.one().unwrap().format_cases().join(" | ")),
+                None => "{}".to_string(),
+            },
+        }
+    }
+
+    fn lower<Pat: Eq + Hash + TryFrom<SPat>>(
         self,
         cx: &mut Context<Pat>,
-    ) -> rule::RuleWithNamedFields {
-        match self {
-            Primary::Eat(pat) => rule::eat(pat.one().unwrap().lower(cx)).finish(cx),
+    ) -> Result<rule::RuleWithNamedFields, ParseError<Span>>
+    where
+        Pat::Error: fmt::Display,
+    {
+        Ok(match self {
+            Primary::Eat(pat) => {
+                let pat = lower_pat(pat, pat.one().unwrap().lower()?)?;
+                rule::eat(pat).finish(cx)
+            }
             Primary::Call(name) => {
-                let name = match name.source() {
+                let name = match &*name.source() {
                     [FlatToken::Ident(ident)] => ident.to_string(),
                     _ => unreachable!(),
                 };
                 rule::call(&name).finish(cx)
             }
-            Primary::Group { or } => or
-                .map(|or| or.one().unwrap().lower(cx))
-                .unwrap_or_else(|| rule::empty().finish(cx)),
-        }
+            Primary::Group { or } => match or {
+                Some(or) => or.one().unwrap().lower(cx)?,
+                None => rule::empty().finish(cx),
+            },
+        })
     }
 }
 
 impl Modifier<'_, '_, TokenStream> {
-    fn lower<Pat: Eq + Hash + From<SPat>>(
+    fn format(self) -> String {
+        match self {
+            Modifier::Opt(_) => "?".to_string(),
+            Modifier::Repeat { repeat, sep, kind } => {
+                let mut out = match repeat.one().unwrap() {
+                    Repeat::Many(_) => "*".to_string(),
+                    Repeat::More(_) => "+".to_string(),
+                };
+                if let Some(sep) = sep {
+                    let kind = match kind.unwrap().one().unwrap() {
+                        SepKind::Simple(_) => "%",
+                        SepKind::Trailing(_) => "%%",
+                    };
+                    out += &format!(" {} {}", kind, sep.one().unwrap().format());
+                }
+                out
+            }
+            Modifier::Count { min, max } => match max {
+                Some(max) => format!("{{{},{}}}", token_text(min), token_text(max)),
+                None => format!("{{{}}}", token_text(min)),
+            },
+        }
+    }
+
+    fn lower<Pat: Eq + Hash + TryFrom<SPat>>(
         self,
         cx: &mut Context<Pat>,
         rule: rule::RuleWithNamedFields,
-    ) -> rule::RuleWithNamedFields {
-        match self {
+    ) -> Result<rule::RuleWithNamedFields, ParseError<Span>>
+    where
+        Pat::Error: fmt::Display,
+    {
+        Ok(match self {
             Modifier::Opt(_) => rule.opt().finish(cx),
             Modifier::Repeat { repeat, sep, kind } => {
                 let repeat = repeat.one().unwrap();
                 if let Some(sep) = sep {
-                    let sep = sep.one().unwrap().lower(cx);
+                    let sep = sep.one().unwrap().lower(cx)?;
                     let kind = kind.unwrap().one().unwrap().lower(cx);
                     match repeat {
                         Repeat::Many(_) => rule.repeat_many_sep(sep, kind).finish(cx),
@@ -123,7 +360,32 @@ impl Modifier<'_, '_, TokenStream> {
                     }
                 }
             }
-        }
+            Modifier::Count { min, max } => {
+                let min_count = parse_count(min)?;
+                let max_count = match max {
+                    Some(max) => {
+                        let max_count = parse_count(max)?;
+                        if max_count < min_count {
+                            return Err(lowering_error(
+                                max,
+                                "Modifier",
+                                iter::once(format!("a maximum count of at least {}", min_count)),
+                            ));
+                        }
+                        max_count
+                    }
+                    None => min_count,
+                };
+
+                // `X{min,max}` is unrolled to `min` copies of `X`, followed by
+                // `max - min` nested optional ones, i.e. `X X {X {X}?}?`.
+                let mut optional = rule::empty().finish(cx);
+                for _ in min_count..max_count {
+                    optional = (rule.clone() + optional).opt().finish(cx);
+                }
+                (0..min_count).fold(optional, |acc, _| (rule.clone() + acc).finish(cx))
+            }
+        })
     }
 }
 
@@ -136,31 +398,100 @@ impl SepKind<'_, '_, TokenStream> {
     }
 }
 
+/// Whether the token at `a` is immediately followed by the one at `b`, e.g.
+/// `i` and `"..."` in `i"..."`. This needs the `span-locations` feature,
+/// without which (or without locations, e.g. in a procedural macro on stable)
+/// all tokens are assumed to be adjacent, i.e. `i "..."` is also `i"..."`.
+fn adjacent(a: Span, b: Span) -> bool {
+    #[cfg(feature = "span-locations")]
+    {
+        // NOTE unknown locations are all the same, so they're "adjacent".
+        a.end() == b.start()
+    }
+    #[cfg(not(feature = "span-locations"))]
+    {
+        let _ = (a, b);
+        true
+    }
+}
+
+/// The source text of a single token (e.g. an identifier or a literal),
+/// as written (i.e. literals are kept escaped).
+fn token_text<T>(handle: Handle<'_, '_, TokenStream, T>) -> String {
+    match &*handle.source() {
+        [FlatToken::Ident(ident)] => ident.to_string(),
+        [FlatToken::Literal(lit)] => lit.to_string(),
+        _ => unreachable!(),
+    }
+}
+
+fn unescape<T>(handle: Handle<'_, '_, TokenStream, T>) -> String {
+    let mut out = String::new();
+    let s = match &*handle.source() {
+        [FlatToken::Literal(lit)] => lit.to_string(),
+        _ => unreachable!(),
+    };
+    let mut chars = s[1..s.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next().unwrap() {
+                't' => '\t',
+                'n' => '\n',
+                'r' => '\r',
+                c => c,
+            },
+            _ => c,
+        };
+        out.push(c);
+    }
+    out
+}
+
+fn parse_count<T>(handle: Handle<'_, '_, TokenStream, T>) -> Result<usize, ParseError<Span>> {
+    let s = match &*handle.source() {
+        [FlatToken::Literal(lit)] => lit.to_string(),
+        _ => unreachable!(),
+    };
+    s.parse().map_err(|_| {
+        lowering_error(
+            handle,
+            "Modifier",
+            iter::once("a repetition count (fitting in `usize`)".to_string()),
+        )
+    })
+}
+
 impl Pattern<'_, '_, TokenStream> {
-    fn lower<Pat: Eq + Hash>(self, cx: &mut Context<Pat>) -> SPat {
-        fn unescape<T>(handle: Handle<'_, '_, TokenStream, T>) -> String {
-            let mut out = String::new();
-            let s = match handle.source() {
-                [FlatToken::Literal(lit)] => lit.to_string(),
-                _ => unreachable!(),
-            };
-            let mut chars = s[1..s.len() - 1].chars();
-            while let Some(c) = chars.next() {
-                let c = match c {
-                    '\\' => match chars.next().unwrap() {
-                        't' => '\t',
-                        'n' => '\n',
-                        'r' => '\r',
-                        c => c,
-                    },
-                    _ => c,
-                };
-                out.push(c);
+    fn format(self) -> String {
+        let opt_text = |c: Option<_>| c.map_or(String::new(), token_text);
+        match self {
+            Pattern::Str(s) => token_text(s),
+            Pattern::CharRange { start, end } => {
+                format!("{}..{}", opt_text(start), opt_text(end))
+            }
+            Pattern::CharRangeInclusive { start, end } => {
+                format!("{}..={}", opt_text(start), token_text(end))
             }
-            out
+            Pattern::Class { negated, items } => {
+                let items: Vec<_> = items
+                    .map(|item| match item.unwrap().one().unwrap() {
+                        ClassItem::Range { start, end } => {
+                            format!("{}..={}", token_text(start), token_text(end))
+                        }
+                        ClassItem::Char(c) => token_text(c),
+                        ClassItem::Property(name) => token_text(name),
+                    })
+                    .collect();
+                let negated = if negated.is_some() { "!" } else { "" };
+                format!("{}[{}]", negated, items.join(" "))
+            }
+            Pattern::NegatedChar { ch } => format!("!{}", token_text(ch)),
         }
+    }
+
+    fn lower(self) -> Result<SPat, ParseError<Span>> {
         let unescape_char = |c| unescape(c).parse::<char>().unwrap();
-        match self {
+        Ok(match self {
             Pattern::Str(s) => SPat::from(unescape(s)),
             Pattern::CharRange { start, end } => SPat::from((
                 start
@@ -175,6 +506,46 @@ impl Pattern<'_, '_, TokenStream> {
                     .map_or(Bound::Unbounded, Bound::Included),
                 Bound::Included(unescape_char(end)),
             )),
-        }
+            Pattern::Class { negated, items } => SPat::Class(CharClass {
+                negated: negated.is_some(),
+                items: items
+                    .map(|item| match item.unwrap().one().unwrap() {
+                        ClassItem::Range { start, end } => {
+                            Ok(SClassItem::Range(unescape_char(start), unescape_char(end)))
+                        }
+                        ClassItem::Char(c) => {
+                            let c = unescape_char(c);
+                            Ok(SClassItem::Range(c, c))
+                        }
+                        // NOTE Unicode properties are only recognized here,
+                        // so they can't clash with the names of rules.
+                        ClassItem::Property(name) => {
+                            let property = match &*name.source() {
+                                [FlatToken::Ident(ident)] => {
+                                    UnicodeProperty::from_name(&ident.to_string())
+                                }
+                                _ => unreachable!(),
+                            };
+                            property.map(SClassItem::Property).ok_or_else(|| {
+                                lowering_error(
+                                    name,
+                                    "ClassItem",
+                                    UnicodeProperty::ALL
+                                        .iter()
+                                        .map(|property| format!("`{}`", property.name())),
+                                )
+                            })
+                        }
+                    })
+                    .collect::<Result<_, _>>()?,
+            }),
+            Pattern::NegatedChar { ch } => {
+                let c = unescape_char(ch);
+                SPat::Class(CharClass {
+                    negated: true,
+                    items: vec![SClassItem::Range(c, c)],
+                })
+            }
+        })
     }
 }
@@ -1,31 +1,210 @@
-use crate::forest::{GrammarReflector, OwnedParseForestAndNode, ParseForest, ParseNode};
+use crate::forest::{GrammarReflector, NodeMap, OwnedParseForestAndNode, ParseForest, ParseNode};
 use crate::high::ErasableL;
-use crate::input::{Input, InputMatch, Range};
+use crate::input::{DescribePat, Input, InputRangeMatch, InputRangeMatchAll, Range, Trie};
 use indexing::{self, Index, Unknown};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
 use std::fmt;
 use std::hash::Hash;
 
 pub struct Parser<'a, 'i, G: GrammarReflector, I: Input> {
-    state: &'a mut ParserState<'i, G, I>,
+    forest: &'a ParseForest<'i, G, I>,
+    state: &'a mut ParserState<'i, G::ParseNodeKind>,
     result: Range<'i>,
     remaining: Range<'i>,
 }
 
-struct ParserState<'i, G: GrammarReflector, I: Input> {
-    forest: ParseForest<'i, G, I>,
-    last_input_pos: Index<'i, Unknown>,
-    expected_pats: Vec<&'static dyn fmt::Debug>,
+/// Everything a `Parser` records while parsing, kept separate from the
+/// (read-only) `ParseForest`, so that a parallel parse can have one for
+/// each thread, to be `merge`d together at the end.
+pub(crate) struct ParserState<'i, P> {
+    // NOTE these are moved into the forest once parsing is done.
+    possible_choices: NodeMap<ParseNode<'i, P>, P>,
+    possible_splits: NodeMap<ParseNode<'i, P>, usize>,
+    /// Furthest failures to match, left-to-right and right-to-left.
+    expected_left: Expected<'i, P>,
+    expected_right: Expected<'i, P>,
+    reached_end: bool,
+}
+
+impl<'i, P: Ord + Hash + Copy> ParserState<'i, P> {
+    /// An empty state, for parsing (all of) the input `range`.
+    pub(crate) fn new(range: Range<'i>) -> Self {
+        let (start, end) = range.frontiers();
+        ParserState {
+            possible_choices: NodeMap::default(),
+            possible_splits: NodeMap::default(),
+            expected_left: Expected {
+                pos: start.first(),
+                pats: vec![],
+            },
+            expected_right: Expected {
+                pos: end.first(),
+                pats: vec![],
+            },
+            reached_end: false,
+        }
+    }
+
+    /// Add everything recorded in `other` to `self`, such that the result
+    /// doesn't depend on which of the two recorded what (or in which order).
+    #[cfg(feature = "parallel")]
+    pub(crate) fn merge(&mut self, other: Self) {
+        for &node in other.possible_choices.keys() {
+            for &choice in other.possible_choices.get(&node).unwrap() {
+                self.possible_choices.insert(node, choice);
+            }
+        }
+        for &node in other.possible_splits.keys() {
+            for &split in other.possible_splits.get(&node).unwrap() {
+                self.possible_splits.insert(node, split);
+            }
+        }
+        self.expected_left
+            .merge(other.expected_left, |new, old| new > old);
+        self.expected_right
+            .merge(other.expected_right, |new, old| new < old);
+        self.reached_end |= other.reached_end;
+    }
+}
+
+/// Patterns which failed to match at `pos`, and the rules
+/// (their parse node kinds) they were in, for error reporting.
+struct Expected<'i, P> {
+    pos: Index<'i, Unknown>,
+    pats: Vec<(P, &'static dyn DescribePat)>,
+}
+
+impl<'i, P> Expected<'i, P> {
+    /// Move `pos` to `new_pos`, discarding all the patterns so far,
+    /// if `further(new_pos, pos)` (i.e. more input was consumed).
+    fn advance(
+        &mut self,
+        new_pos: Index<'i, Unknown>,
+        further: impl FnOnce(Index<'i, Unknown>, Index<'i, Unknown>) -> bool,
+    ) {
+        if further(new_pos, self.pos) {
+            self.pos = new_pos;
+            self.pats.clear();
+        }
+    }
+
+    /// Keep the patterns from whichever of `self` and `other` got further,
+    /// or from both of them, if they're at the same position.
+    #[cfg(feature = "parallel")]
+    fn merge(
+        &mut self,
+        other: Self,
+        further: impl Fn(Index<'i, Unknown>, Index<'i, Unknown>) -> bool,
+    ) {
+        self.advance(other.pos, &further);
+        if other.pos == self.pos {
+            self.pats.extend(other.pats);
+        }
+    }
+}
+
+/// Which ends of the input a successful parse has to reach.
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Anchor {
+    Whole,
+    Start,
+    End,
 }
 
 #[derive(Debug)]
 pub struct ParseError<A> {
     pub at: A,
-    pub expected: Vec<&'static dyn fmt::Debug>,
+    /// Whether `at` is the end of the input, i.e. it ended too early
+    /// (or its start, if the furthest failure was matching right-to-left).
+    pub at_end: bool,
+    /// Descriptions (see `DescribePat`) of the patterns expected at `at`,
+    /// grouped by the rule (see `GrammarReflector::parse_node_desc`) they're in.
+    pub expected: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl<A> ParseError<A> {
+    /// Describe the expected patterns, e.g. "`,` or `]` (in List)",
+    /// or "nothing" if there aren't any.
+    pub fn describe_expected(&self) -> String {
+        let groups: Vec<_> = self
+            .expected
+            .iter()
+            .map(|(rule, pats)| {
+                let pats: Vec<_> = pats.iter().map(|pat| &pat[..]).collect();
+                format!("{} (in {})", or_list(&pats), rule)
+            })
+            .collect();
+        match groups.split_last() {
+            None => "nothing".to_string(),
+            Some((last, [])) => last.clone(),
+            Some((last, rest)) => format!("{}, or {}", rest.join(", "), last),
+        }
+    }
+
+    /// The error message, without the location (`at`).
+    pub fn message(&self) -> String {
+        let unexpected = if self.at_end {
+            "unexpected end of input"
+        } else {
+            "unexpected input"
+        };
+        if self.expected.is_empty() {
+            unexpected.to_string()
+        } else if self.at_end {
+            format!("{}, expected {}", unexpected, self.describe_expected())
+        } else {
+            format!("expected {}", self.describe_expected())
+        }
+    }
 }
 
+/// Join `items` as "a", "a or b", "a, b or c", etc.
+fn or_list(items: &[&str]) -> String {
+    match items.split_last() {
+        None => String::new(),
+        Some((last, [])) => last.to_string(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+    }
+}
+
+impl<A: fmt::Debug> fmt::Display for ParseError<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.at, self.message())
+    }
+}
+
+impl<A: fmt::Debug> Error for ParseError<A> {}
+
 pub type ParseResult<A, T> = Result<T, ParseError<A>>;
 
+/// The result of parsing the longest matching prefix of the input,
+/// along with the length of that prefix, if any prefix matched.
+#[derive(Debug)]
+pub struct PrefixParse<A, T> {
+    pub result: ParseResult<A, (T, usize)>,
+    /// Whether matching was attempted against the end of the input, such that
+    /// the `result` could change if more input was appended (and parsed again).
+    pub reached_end: bool,
+}
+
+impl<A, T> PrefixParse<A, T> {
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> PrefixParse<A, U> {
+        PrefixParse {
+            result: self.result.map(|(x, len)| (f(x), len)),
+            reached_end: self.reached_end,
+        }
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<'a, 'i, G: GrammarReflector, I: Input> Parser<'a, 'i, G, I> {
+    /// The forest being parsed into (see also `with_state`).
+    pub(crate) fn forest(&self) -> &'a ParseForest<'i, G, I> {
+        self.forest
+    }
+}
+
 impl<'i, P, G, I: Input> Parser<'_, 'i, G, I>
 where
     // FIXME(eddyb) these shouldn't be needed, as they are bounds on
@@ -38,42 +217,121 @@ where
         input: I,
         f: impl for<'i2> FnOnce(Parser<'_, 'i2, G, I>) -> Option<ParseNode<'i2, P>>,
     ) -> ParseResult<I::SourceInfoPoint, OwnedParseForestAndNode<G, P, I>> {
+        Self::parse_anchored_with(grammar, input, Anchor::Whole, false, f)
+            .result
+            .map(|(forest_and_node, _)| forest_and_node)
+    }
+
+    /// Like `parse_with`, but the result of `f` doesn't have to cover the
+    /// whole input, only a prefix of it (which should be the longest match).
+    pub fn parse_prefix_with(
+        grammar: G,
+        input: I,
+        f: impl for<'i2> FnOnce(Parser<'_, 'i2, G, I>) -> Option<ParseNode<'i2, P>>,
+    ) -> PrefixParse<I::SourceInfoPoint, OwnedParseForestAndNode<G, P, I>> {
+        Self::parse_anchored_with(grammar, input, Anchor::Start, false, f)
+    }
+
+    /// Like `parse_with`, but matching right-to-left (i.e. `f` starts with
+    /// an empty result at the end of the input, and should only consume
+    /// with `input_consume_right`), with the result of `f` only having to
+    /// cover a suffix of the input (which should be the longest match).
+    pub fn parse_suffix_with(
+        grammar: G,
+        input: I,
+        f: impl for<'i2> FnOnce(Parser<'_, 'i2, G, I>) -> Option<ParseNode<'i2, P>>,
+    ) -> ParseResult<I::SourceInfoPoint, (OwnedParseForestAndNode<G, P, I>, usize)> {
+        Self::parse_anchored_with(grammar, input, Anchor::End, true, f).result
+    }
+
+    /// Like `parse_suffix_with`, but `f` matches left-to-right (like in
+    /// `parse_with`), so it has to try every position the suffix could
+    /// start at (see `Runtime::parse_suffix`), and return the longest match.
+    pub fn parse_suffix_left_to_right_with(
+        grammar: G,
+        input: I,
+        f: impl for<'i2> FnOnce(Parser<'_, 'i2, G, I>) -> Option<ParseNode<'i2, P>>,
+    ) -> ParseResult<I::SourceInfoPoint, (OwnedParseForestAndNode<G, P, I>, usize)> {
+        Self::parse_anchored_with(grammar, input, Anchor::End, false, f).result
+    }
+
+    fn parse_anchored_with(
+        grammar: G,
+        input: I,
+        anchor: Anchor,
+        right_to_left: bool,
+        f: impl for<'i2> FnOnce(Parser<'_, 'i2, G, I>) -> Option<ParseNode<'i2, P>>,
+    ) -> PrefixParse<I::SourceInfoPoint, OwnedParseForestAndNode<G, P, I>> {
         ErasableL::indexing_scope(input.to_container(), |lifetime, input| {
             let range = Range(input.range());
-            let mut state = ParserState {
-                forest: ParseForest {
-                    grammar,
-                    input,
-                    possible_choices: HashMap::new(),
-                    possible_splits: HashMap::new(),
-                },
-                last_input_pos: range.first(),
-                expected_pats: vec![],
+            let (start, end) = range.frontiers();
+            let mut forest = ParseForest {
+                grammar,
+                input,
+                possible_choices: NodeMap::default(),
+                possible_splits: NodeMap::default(),
             };
+            let mut state = ParserState::new(range);
 
             let result = f(Parser {
+                forest: &forest,
                 state: &mut state,
-                result: Range(range.frontiers().0),
+                result: Range(if right_to_left { end } else { start }),
                 remaining: range,
             });
 
+            // Report the failures from whichever direction got further.
+            let left_progress = state.expected_left.pos.integer() - range.start();
+            let right_progress = range.end() - state.expected_right.pos.integer();
+            let right_to_left = !state.expected_right.pats.is_empty()
+                && (state.expected_left.pats.is_empty() || right_progress > left_progress);
+            let (furthest, at_end) = if right_to_left {
+                let at_start = state.expected_right.pos.integer() == range.start();
+                (state.expected_right, at_start)
+            } else {
+                let at_end = state.expected_left.pos.integer() == range.end();
+                (state.expected_left, at_end)
+            };
+            let mut expected = BTreeMap::new();
+            for (rule, pat) in furthest.pats {
+                expected
+                    .entry(forest.grammar.parse_node_desc(rule))
+                    .or_insert_with(BTreeSet::new)
+                    .insert(pat.describe());
+            }
             let error = ParseError {
-                at: I::source_info_point(&state.forest.input, state.last_input_pos),
-                expected: state.expected_pats,
+                at: I::source_info_point(&forest.input, furthest.pos),
+                at_end,
+                expected,
             };
-            match result {
+            let reached_end = state.reached_end;
+            let result = match result {
                 None => Err(error),
                 Some(node) => {
-                    // The result is only a successful parse if it's as long as the input.
-                    if node.range == range {
-                        Ok(OwnedParseForestAndNode::pack(
-                            lifetime,
-                            (state.forest, node),
+                    // The result is only a successful parse if it
+                    // reaches the end(s) of the input it's anchored at.
+                    let reaches_start = node.range.start() == range.start();
+                    let reaches_end = node.range.end() == range.end();
+                    let anchored = match anchor {
+                        Anchor::Whole => reaches_start && reaches_end,
+                        Anchor::Start => reaches_start,
+                        Anchor::End => reaches_end,
+                    };
+                    if anchored {
+                        forest.possible_choices = state.possible_choices;
+                        forest.possible_splits = state.possible_splits;
+                        Ok((
+                            OwnedParseForestAndNode::pack(lifetime, (forest, node)),
+                            node.range.len(),
                         ))
                     } else {
                         Err(error)
                     }
                 }
+            };
+            PrefixParse {
+                result,
+                reached_end,
             }
         })
     }
@@ -107,90 +365,203 @@ where
         assert_eq!(self.remaining.end(), full_new_range.end());
 
         Parser {
+            forest: self.forest,
             state: self.state,
             result,
             remaining,
         }
     }
 
-    pub fn input_consume_left<'a, Pat: fmt::Debug>(
+    /// A `Parser` for (all of) the `remaining` input, like the one passed to
+    /// the closure of `parse_with`, but recording into `state` instead, which
+    /// then has to be merged back in (see `merge_state`), e.g. for parsing
+    /// on other threads, as `forest` can be shared between them.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn with_state<'b>(
+        forest: &'b ParseForest<'i, G, I>,
+        state: &'b mut ParserState<'i, P>,
+        remaining: Range<'i>,
+    ) -> Parser<'b, 'i, G, I> {
+        Parser {
+            forest,
+            state,
+            result: Range(remaining.frontiers().0),
+            remaining,
+        }
+    }
+
+    /// Whether a parse can start at `index` (see `Input::is_boundary`).
+    pub(crate) fn input_is_boundary(&self, index: Index<'i, Unknown>) -> bool {
+        I::is_boundary(&self.forest.input, index)
+    }
+
+    /// How many parse nodes have had choices and splits (respectively)
+    /// recorded so far, i.e. how many there will be in the forest.
+    pub(crate) fn forest_node_counts(&self) -> (usize, usize) {
+        (
+            self.state.possible_choices.len(),
+            self.state.possible_splits.len(),
+        )
+    }
+
+    #[cfg(feature = "parallel")]
+    pub(crate) fn merge_state(&mut self, state: ParserState<'i, P>) {
+        self.state.merge(state);
+    }
+
+    /// Match `pat` at the start of the remaining input, or record it as
+    /// expected there (by `rule`, for error reporting), if it doesn't match.
+    pub fn input_consume_left<'a, Pat: DescribePat>(
         &'a mut self,
+        rule: P,
         pat: &'static Pat,
     ) -> Option<Parser<'a, 'i, G, I>>
     where
-        I::Slice: InputMatch<Pat>,
+        I: InputRangeMatch<Pat>,
     {
         let start = self.remaining.first();
-        if start > self.state.last_input_pos {
-            self.state.last_input_pos = start;
-            self.state.expected_pats.clear();
-        }
-        match self.state.forest.input(self.remaining).match_left(pat) {
+        self.state
+            .expected_left
+            .advance(start, |new, old| new > old);
+        match I::match_left(&self.forest.input, self.remaining, pat) {
             Some(n) => {
                 let (matching, after, _) = self.remaining.split_at(n);
                 if n > 0 {
-                    self.state.last_input_pos = after.first();
-                    self.state.expected_pats.clear();
+                    // NOTE this only moves forward, so that the furthest
+                    // failures don't depend on the order threads run in.
+                    self.state
+                        .expected_left
+                        .advance(after.first(), |new, old| new > old);
                 }
                 Some(Parser {
+                    forest: self.forest,
                     state: self.state,
                     result: Range(self.result.join(matching).unwrap()),
                     remaining: Range(after),
                 })
             }
             None => {
-                if start == self.state.last_input_pos {
-                    self.state.expected_pats.push(pat);
+                if I::match_left_needs_more(&self.forest.input, self.remaining, pat) {
+                    self.state.reached_end = true;
+                }
+                if start == self.state.expected_left.pos {
+                    self.state.expected_left.pats.push((rule, pat));
                 }
                 None
             }
         }
     }
 
-    pub fn input_consume_right<'a, Pat>(
+    /// Check whether `pat` matches at the start of the remaining input,
+    /// without consuming it, but otherwise just like `input_consume_left`
+    /// (i.e. `pat` is recorded as expected there, if it doesn't match).
+    pub fn input_peek_left<Pat: DescribePat>(&mut self, rule: P, pat: &'static Pat) -> bool
+    where
+        I: InputRangeMatch<Pat>,
+    {
+        let start = self.remaining.first();
+        self.state
+            .expected_left
+            .advance(start, |new, old| new > old);
+        if I::match_left(&self.forest.input, self.remaining, pat).is_some() {
+            return true;
+        }
+        if I::match_left_needs_more(&self.forest.input, self.remaining, pat) {
+            self.state.reached_end = true;
+        }
+        if start == self.state.expected_left.pos {
+            self.state.expected_left.pats.push((rule, pat));
+        }
+        false
+    }
+
+    /// Match all the patterns of `trie` at the start of the remaining input,
+    /// without consuming it, returning the indices of the matching ones, and
+    /// recording the others as expected, just like `input_consume_left`.
+    pub fn input_match_all_left<T, Pat: DescribePat>(
+        &mut self,
+        rule: P,
+        trie: &'static Trie<T, Pat>,
+    ) -> Vec<usize>
+    where
+        I: InputRangeMatchAll<Trie<T, Pat>>,
+    {
+        let start = self.remaining.first();
+        self.state
+            .expected_left
+            .advance(start, |new, old| new > old);
+        let mut matches = vec![];
+        let needs_more = I::match_all_left(&self.forest.input, self.remaining, trie, &mut |i| {
+            matches.push(i)
+        });
+        if needs_more {
+            self.state.reached_end = true;
+        }
+        if start == self.state.expected_left.pos {
+            for (i, pat) in trie.pats.iter().enumerate() {
+                if !matches.contains(&i) {
+                    self.state.expected_left.pats.push((rule, pat));
+                }
+            }
+        }
+        matches
+    }
+
+    /// Match `pat` at the end of the remaining input, or record it as
+    /// expected there (by `rule`, for error reporting), if it doesn't match.
+    pub fn input_consume_right<'a, Pat: DescribePat>(
         &'a mut self,
+        rule: P,
         pat: &'static Pat,
     ) -> Option<Parser<'a, 'i, G, I>>
     where
-        I::Slice: InputMatch<Pat>,
+        I: InputRangeMatch<Pat>,
     {
-        // FIXME(eddyb) implement error reporting support like in `input_consume_left`
-        match self.state.forest.input(self.remaining).match_right(pat) {
+        let end = self.remaining.frontiers().1.first();
+        self.state.expected_right.advance(end, |new, old| new < old);
+        match I::match_right(&self.forest.input, self.remaining, pat) {
             Some(n) => {
                 let (before, matching, _) = self.remaining.split_at(self.remaining.len() - n);
+                if n > 0 {
+                    self.state
+                        .expected_right
+                        .advance(before.frontiers().1.first(), |new, old| new < old);
+                }
                 Some(Parser {
+                    forest: self.forest,
                     state: self.state,
                     result: Range(matching.join(self.result.0).unwrap()),
                     remaining: Range(before),
                 })
             }
-            None => None,
+            None => {
+                if end == self.state.expected_right.pos {
+                    self.state.expected_right.pats.push((rule, pat));
+                }
+                None
+            }
         }
     }
 
     pub fn forest_add_choice(&mut self, kind: P, choice: P) {
-        self.state
-            .forest
-            .possible_choices
-            .entry(ParseNode {
+        self.state.possible_choices.insert(
+            ParseNode {
                 kind,
                 range: self.result,
-            })
-            .or_default()
-            .insert(choice);
+            },
+            choice,
+        );
     }
 
     // FIXME(eddyb) safeguard this against misuse.
     pub fn forest_add_split(&mut self, kind: P, left: ParseNode<'i, P>) {
         self.result = Range(left.range.join(self.result.0).unwrap());
-        self.state
-            .forest
-            .possible_splits
-            .entry(ParseNode {
+        self.state.possible_splits.insert(
+            ParseNode {
                 kind,
                 range: self.result,
-            })
-            .or_default()
-            .insert(left.range.len());
+            },
+            left.range.len(),
+        );
     }
 }
@@ -1,10 +1,16 @@
-use crate::generate::rust::RustInputPat;
+use crate::analysis::OverlapsFirst;
+use crate::generate::rust::{generate_trie, RustInputPat};
 use crate::generate::src::{quotable_to_src, quote, Src, ToSrc};
 use crate::scannerless::Pat as SPat;
 use grammer::rule::{call, eat, MatchesEmpty, MaybeKnown};
+#[cfg(feature = "span-locations")]
+pub use proc_macro2::LineColumn;
 pub use proc_macro2::{
-    Delimiter, Ident, LexError, Literal, Punct, Spacing, Span, TokenStream, TokenTree,
+    Delimiter, Group, Ident, LexError, Literal, Punct, Spacing, Span, TokenStream, TokenTree,
 };
+use std::convert::TryFrom;
+use std::fmt;
+use std::mem;
 use std::str::FromStr;
 
 pub type Context = grammer::context::Context<Pat>;
@@ -34,14 +40,65 @@ pub fn builtin(cx: &mut Context) -> grammer::Grammar {
     .finish(cx);
     g.define(cx.intern("PUNCT"), punct.clone());
 
-    let literal = eat(Pat(vec![FlatTokenPat::Literal])).finish(cx);
+    let literal = eat(Pat(vec![FlatTokenPat::Literal {
+        kind: None,
+        value: None,
+    }]))
+    .finish(cx);
     g.define(cx.intern("LITERAL"), literal.clone());
 
+    for &(name, kind) in &[
+        ("STR_LIT", LiteralKind::Str),
+        ("BYTE_STR_LIT", LiteralKind::ByteStr),
+        ("CHAR_LIT", LiteralKind::Char),
+        ("BYTE_LIT", LiteralKind::Byte),
+        ("INT_LIT", LiteralKind::Int),
+        ("FLOAT_LIT", LiteralKind::Float),
+    ] {
+        g.define(
+            cx.intern(name),
+            eat(Pat(vec![FlatTokenPat::Literal {
+                kind: Some(kind),
+                value: None,
+            }]))
+            .finish(cx),
+        );
+    }
+
+    // NOTE `true` and `false` are identifiers, not literals, in tokens.
+    let keyword = |name: &str| eat(Pat(vec![FlatTokenPat::Ident(Some(name.to_string()))]));
+    g.define(
+        cx.intern("BOOL"),
+        (keyword("true") | keyword("false")).finish(cx),
+    );
+
+    let none_group = eat(Pat(vec![FlatTokenPat::NoneGroup])).finish(cx);
+    g.define(cx.intern("NONE_GROUP"), none_group.clone());
+
+    #[cfg(feature = "syn")]
+    for &(name, syntax) in &[
+        ("RUST_EXPR", RustSyntax::Expr),
+        ("RUST_TYPE", RustSyntax::Type),
+        ("RUST_PATH", RustSyntax::Path),
+    ] {
+        g.define(
+            cx.intern(name),
+            eat(Pat(vec![FlatTokenPat::Rust(syntax)])).finish(cx),
+        );
+    }
+
     let delim = |c| eat(FlatTokenPat::Delim(c));
     let group = |open, close| delim(open) + call("TOKEN_TREE").repeat_many() + delim(close);
     g.define(
         cx.intern("TOKEN_TREE"),
-        (ident | punct | literal | group('(', ')') | group('[', ']') | group('{', '}')).finish(cx),
+        (ident
+            | punct
+            | literal
+            | none_group
+            | group('(', ')')
+            | group('[', ']')
+            | group('{', '}'))
+        .finish(cx),
     );
 
     g
@@ -80,11 +137,16 @@ impl From<FlatTokenPat<String>> for Pat {
     }
 }
 
-impl From<SPat> for Pat {
-    fn from(pat: SPat) -> Self {
+/// Only strings (of tokens) can be used in proc macro grammars, so any other
+/// (character-based) pattern is rejected, with a description of the former.
+impl TryFrom<SPat> for Pat {
+    type Error = &'static str;
+    fn try_from(pat: SPat) -> Result<Self, Self::Error> {
         match pat {
-            SPat::String(s) => s[..].into(),
-            SPat::Range(..) => unimplemented!("char ranges are unsupported"),
+            SPat::String(s) => Ok(s[..].into()),
+            SPat::CaseInsensitive(_) | SPat::Range(..) | SPat::Class(_) => {
+                Err("a string literal (character patterns are only supported in scannerless mode)")
+            }
         }
     }
 }
@@ -95,21 +157,98 @@ impl MatchesEmpty for Pat {
     }
 }
 
+impl OverlapsFirst for Pat {
+    fn overlaps_first(&self, other: &Self) -> bool {
+        match (self.0.first(), other.0.first()) {
+            (Some(a), Some(b)) => a.overlaps(b),
+            _ => true,
+        }
+    }
+}
+
 impl RustInputPat for Pat {
-    fn rust_slice_ty() -> Src {
-        quote!([gll::proc_macro::FlatToken])
+    fn rust_matcher_tys() -> Vec<Src> {
+        vec![quote!(
+            &'static [gll::proc_macro::FlatTokenPat<&'static str>]
+        )]
     }
     fn rust_matcher(&self) -> Src {
         let pats = self.0.iter();
         quote!(&[#(#pats),*] as &[_])
     }
+    fn rust_trie_ty() -> Src {
+        quote!(
+            gll::input::Trie<
+                gll::proc_macro::FlatTokenPat<&'static str>,
+                &'static [gll::proc_macro::FlatTokenPat<&'static str>],
+            >
+        )
+    }
+    fn rust_trie(pats: &[&Self]) -> Option<Src> {
+        let pats = pats
+            .iter()
+            .map(|pat| {
+                // NOTE Rust syntax can't be matched token by token.
+                let literal = !pat.0.is_empty()
+                    && pat.0.iter().all(|pat| match pat {
+                        FlatTokenPat::Rust(_) => false,
+                        _ => true,
+                    });
+                if literal {
+                    Some((pat.0.clone(), pat.rust_matcher()))
+                } else {
+                    None
+                }
+            })
+            .collect::<Option<_>>()?;
+        Some(generate_trie(pats))
+    }
 }
 
+/// The location of a range of tokens, given by the spans of its first and
+/// last tokens (which are the same span for ranges of one token, and the
+/// span of the token right after the range, or before it, if it's empty).
+#[derive(Copy, Clone)]
+pub struct Spans {
+    pub first: Span,
+    pub last: Span,
+}
+
+impl fmt::Debug for Spans {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}..{:?}", self.first, self.last)
+    }
+}
+
+impl Spans {
+    /// Get a single span covering all the tokens, if `proc_macro2` supports
+    /// joining spans (i.e. on nightly, or outside of a procedural macro with
+    /// the `span-locations` feature), or only the first token's span otherwise.
+    pub fn join(&self) -> Span {
+        self.first.join(self.last).unwrap_or(self.first)
+    }
+
+    #[cfg(feature = "span-locations")]
+    pub fn start(&self) -> LineColumn {
+        self.first.start()
+    }
+
+    #[cfg(feature = "span-locations")]
+    pub fn end(&self) -> LineColumn {
+        self.last.end()
+    }
+}
+
+#[derive(Clone)]
 pub enum FlatToken {
     Delim(char, Span),
     Ident(Ident),
     Punct(Punct),
     Literal(Literal),
+    /// A group with invisible delimiters (e.g. from a `macro_rules!` `$e:expr`),
+    /// kept opaque so that its contents can't mix with the surrounding tokens.
+    /// See `flatten_none_groups` for turning these into their contents instead.
+    NoneGroup(Group),
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -120,7 +259,59 @@ pub enum FlatTokenPat<S: AsRef<str>> {
         ch: Option<char>,
         joint: Option<bool>,
     },
-    Literal,
+    Literal {
+        kind: Option<LiteralKind>,
+        value: Option<S>,
+    },
+    NoneGroup,
+    /// The longest sequence of tokens `syn` can parse as `RustSyntax`
+    /// (only usable on its own, and with the `syn` feature enabled).
+    Rust(RustSyntax),
+}
+
+/// Rust syntax that can be matched (see `FlatTokenPat::Rust`) by having `syn`
+/// parse it, e.g. the `RUST_EXPR` builtin rule, for `syn::Expr`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RustSyntax {
+    Expr,
+    Type,
+    Path,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LiteralKind {
+    Str,
+    ByteStr,
+    CStr,
+    Char,
+    Byte,
+    Int,
+    Float,
+}
+
+impl LiteralKind {
+    pub fn of(literal: &Literal) -> Self {
+        let s = literal.to_string();
+        match s.as_bytes()[0] {
+            b'"' | b'r' => LiteralKind::Str,
+            b'b' if s.as_bytes()[1] == b'\'' => LiteralKind::Byte,
+            b'b' => LiteralKind::ByteStr,
+            b'c' => LiteralKind::CStr,
+            b'\'' => LiteralKind::Char,
+            _ => {
+                // HACK look past any (unsuffixed) integer prefix, and
+                // the sign (which literals created by `Literal` methods can have).
+                let digits = s.trim_start_matches('-');
+                let rest = digits.trim_start_matches(|c: char| c.is_ascii_digit() || c == '_');
+                let is_radix_prefixed = ["0x", "0o", "0b"].iter().any(|p| digits.starts_with(p));
+                if !is_radix_prefixed && rest.starts_with(|c: char| ".eEf".contains(c)) {
+                    LiteralKind::Float
+                } else {
+                    LiteralKind::Int
+                }
+            }
+        }
+    }
 }
 
 impl ToSrc for FlatTokenPat<String> {
@@ -138,13 +329,71 @@ impl ToSrc for FlatTokenPat<String> {
                 let joint = joint.map_or_else(|| quote!(None), |x| quote!(Some(#x)));
                 quote!(Punct { ch: #ch, joint: #joint })
             }
-            FlatTokenPat::Literal => quote!(Literal),
+            FlatTokenPat::Literal { kind, value } => {
+                let kind = kind.map_or_else(
+                    || quote!(None),
+                    |x| {
+                        let x = Src::ident(format!("{:?}", x));
+                        quote!(Some(gll::proc_macro::LiteralKind::#x))
+                    },
+                );
+                let value = value
+                    .as_ref()
+                    .map_or_else(|| quote!(None), |x| quote!(Some(#x)));
+                quote!(Literal { kind: #kind, value: #value })
+            }
+            FlatTokenPat::NoneGroup => quote!(NoneGroup),
+            FlatTokenPat::Rust(syntax) => {
+                let syntax = Src::ident(format!("{:?}", syntax));
+                quote!(Rust(gll::proc_macro::RustSyntax::#syntax))
+            }
         };
         quote!(gll::proc_macro::FlatTokenPat::#variant)
     }
 }
 quotable_to_src!(FlatTokenPat<String>);
 
+impl<S: AsRef<str>> FlatTokenPat<S> {
+    /// Whether any token can match both `self` and `other` (assuming so for
+    /// `FlatTokenPat::Rust`, and for literals with a kind but no value, and
+    /// a value but no kind, respectively, without checking their kinds).
+    fn overlaps(&self, other: &Self) -> bool {
+        fn either_eq<T: PartialEq>(a: Option<T>, b: Option<T>) -> bool {
+            a.is_none() || b.is_none() || a == b
+        }
+        match (self, other) {
+            (FlatTokenPat::Rust(_), _) | (_, FlatTokenPat::Rust(_)) => true,
+            (FlatTokenPat::Delim(a), FlatTokenPat::Delim(b)) => a == b,
+            (FlatTokenPat::Ident(a), FlatTokenPat::Ident(b)) => either_eq(
+                a.as_ref().map(|a| a.as_ref()),
+                b.as_ref().map(|b| b.as_ref()),
+            ),
+            (
+                FlatTokenPat::Punct { ch, joint },
+                FlatTokenPat::Punct {
+                    ch: other_ch,
+                    joint: other_joint,
+                },
+            ) => either_eq(*ch, *other_ch) && either_eq(*joint, *other_joint),
+            (
+                FlatTokenPat::Literal { kind, value },
+                FlatTokenPat::Literal {
+                    kind: other_kind,
+                    value: other_value,
+                },
+            ) => {
+                either_eq(*kind, *other_kind)
+                    && either_eq(
+                        value.as_ref().map(|a| a.as_ref()),
+                        other_value.as_ref().map(|b| b.as_ref()),
+                    )
+            }
+            (FlatTokenPat::NoneGroup, FlatTokenPat::NoneGroup) => true,
+            _ => false,
+        }
+    }
+}
+
 impl FlatToken {
     pub fn span(&self) -> Span {
         match self {
@@ -152,6 +401,7 @@ impl FlatToken {
             FlatToken::Ident(tt) => tt.span(),
             FlatToken::Punct(tt) => tt.span(),
             FlatToken::Literal(tt) => tt.span(),
+            FlatToken::NoneGroup(tt) => tt.span(),
         }
     }
 
@@ -167,13 +417,11 @@ impl FlatToken {
                     None
                 },
             },
-            FlatToken::Literal(tt) => {
-                unimplemented!(
-                    "matching specific literals is not supported, \
-                     use `LITERAL` instead of `{}`",
-                    tt.to_string(),
-                );
-            }
+            FlatToken::NoneGroup(_) => FlatTokenPat::NoneGroup,
+            FlatToken::Literal(tt) => FlatTokenPat::Literal {
+                kind: None,
+                value: Some(tt.to_string()),
+            },
         }
     }
 
@@ -186,35 +434,39 @@ impl FlatToken {
                 ch.map_or(true, |b| a.as_char() == b)
                     && joint.map_or(true, |b| (a.spacing() == Spacing::Joint) == b)
             }
-            (FlatToken::Literal(_), FlatTokenPat::Literal) => true,
+            (FlatToken::Literal(a), FlatTokenPat::Literal { kind, value }) => {
+                kind.map_or(true, |b| LiteralKind::of(a) == b)
+                    && value.as_ref().map_or(true, |b| a.to_string() == b.as_ref())
+            }
+            (FlatToken::NoneGroup(_), FlatTokenPat::NoneGroup) => true,
+            // NOTE this includes `FlatTokenPat::Rust`, which can span any
+            // number of tokens, so it's only matched by the `InputMatch` impl
+            // (see `proc_macro_input`).
             _ => false,
         }
     }
 }
 
-pub(crate) fn flatten(stream: TokenStream, out: &mut Vec<FlatToken>) {
+/// Append the tokens of `stream` to `out`, with each group replaced by its
+/// delimiters around its own (flattened) tokens, except for `NoneGroup`s.
+pub fn flatten(stream: TokenStream, out: &mut Vec<FlatToken>) {
     for tt in stream {
         let flat = match tt {
             TokenTree::Group(tt) => {
                 let delim = tt.delimiter();
-                // FIXME(eddyb) use proper delim span here.
-                let span = tt.span();
-                let stream = tt.stream();
+                let (open_span, close_span) = (tt.span_open(), tt.span_close());
                 let (open, close) = match delim {
                     Delimiter::Parenthesis => ('(', ')'),
                     Delimiter::Brace => ('{', '}'),
                     Delimiter::Bracket => ('[', ']'),
                     Delimiter::None => {
-                        // FIXME(eddyb) maybe encode implicit delimiters somehow?
-                        // One way could be to have an opaque `FlatToken` variant,
-                        // containing the entire group, instead of exposing its contents.
-                        flatten(stream, out);
+                        out.push(FlatToken::NoneGroup(tt));
                         continue;
                     }
                 };
-                out.push(FlatToken::Delim(open, span));
-                flatten(stream, out);
-                FlatToken::Delim(close, span)
+                out.push(FlatToken::Delim(open, open_span));
+                flatten(tt.stream(), out);
+                FlatToken::Delim(close, close_span)
             }
             TokenTree::Ident(tt) => FlatToken::Ident(tt),
             TokenTree::Punct(tt) => FlatToken::Punct(tt),
@@ -223,3 +475,88 @@ pub(crate) fn flatten(stream: TokenStream, out: &mut Vec<FlatToken>) {
         out.push(flat);
     }
 }
+
+/// Rebuild a `TokenStream` out of flattened tokens (e.g. from `Handle::source`),
+/// turning pairs of `FlatToken::Delim` back into `Group`s. Each `Group` gets
+/// the joined spans of its delimiters, when `proc_macro2` can join spans.
+///
+/// Returns `None` if the delimiters aren't balanced, i.e. `tokens` doesn't
+/// contain only whole token trees (which is the case for e.g. `TOKEN_TREE+`,
+/// but not for e.g. a rule only matching `"("`).
+pub fn unflatten(tokens: &[FlatToken]) -> Option<TokenStream> {
+    // The groups still open (innermost last), and the tokens in each so far.
+    let mut open_groups = vec![];
+    let mut tokens_so_far = vec![];
+    for token in tokens {
+        let tt: TokenTree = match *token {
+            FlatToken::Delim(open, span) if "([{".contains(open) => {
+                open_groups.push((open, span, mem::replace(&mut tokens_so_far, vec![])));
+                continue;
+            }
+            FlatToken::Delim(close, close_span) => {
+                let (open, open_span, outer_tokens) = open_groups.pop()?;
+                let delimiter = match (open, close) {
+                    ('(', ')') => Delimiter::Parenthesis,
+                    ('[', ']') => Delimiter::Bracket,
+                    ('{', '}') => Delimiter::Brace,
+                    _ => return None,
+                };
+                let inner_tokens = mem::replace(&mut tokens_so_far, outer_tokens);
+                let mut group = Group::new(delimiter, inner_tokens.into_iter().collect());
+                group.set_span(open_span.join(close_span).unwrap_or(open_span));
+                group.into()
+            }
+            FlatToken::Ident(ref tt) => tt.clone().into(),
+            FlatToken::Punct(ref tt) => tt.clone().into(),
+            FlatToken::Literal(ref tt) => tt.clone().into(),
+            FlatToken::NoneGroup(ref tt) => tt.clone().into(),
+        };
+        tokens_so_far.push(tt);
+    }
+    if !open_groups.is_empty() {
+        return None;
+    }
+    Some(tokens_so_far.into_iter().collect())
+}
+
+/// Replace groups with invisible delimiters (`Delimiter::None`) with their
+/// contents, recursively, so that grammars can match the tokens inside them
+/// (instead of matching whole groups with `NONE_GROUP` or `TOKEN_TREE`).
+pub fn flatten_none_groups(stream: TokenStream) -> TokenStream {
+    stream
+        .into_iter()
+        .flat_map(|tt| match tt {
+            TokenTree::Group(g) => {
+                let stream = flatten_none_groups(g.stream());
+                if g.delimiter() == Delimiter::None {
+                    stream
+                } else {
+                    let mut flat = Group::new(g.delimiter(), stream);
+                    flat.set_span(g.span());
+                    TokenTree::from(flat).into()
+                }
+            }
+            tt => tt.into(),
+        })
+        .collect()
+}
+
+/// A `compile_error!("...");` invocation reporting `message` at `span`,
+/// e.g. to return from a procedural macro (see also `ParseError::to_compile_error`).
+pub fn compile_error(message: &str, span: Span) -> TokenStream {
+    let mut bang = Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut message = Literal::string(message);
+    message.set_span(span);
+    let mut args = Group::new(Delimiter::Parenthesis, TokenTree::from(message).into());
+    args.set_span(span);
+    let mut semi = Punct::new(';', Spacing::Alone);
+    semi.set_span(span);
+    let tokens: Vec<TokenTree> = vec![
+        Ident::new("compile_error", span).into(),
+        bang.into(),
+        args.into(),
+        semi.into(),
+    ];
+    tokens.into_iter().collect()
+}
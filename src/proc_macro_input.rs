@@ -1,12 +1,18 @@
-use crate::input::{Input, InputMatch, Range};
-use crate::proc_macro::{flatten, FlatToken, FlatTokenPat, Span, TokenStream};
-use indexing::{proof::Provable, Container, Index, Unknown};
-use std::ops;
+use crate::input::{DescribePat, Input, InputMatch, Range, SliceMatchAll, Trie};
+use crate::parser::ParseError;
+#[cfg(feature = "syn")]
+use crate::proc_macro::unflatten;
+use crate::proc_macro::{
+    compile_error, flatten, FlatToken, FlatTokenPat, LiteralKind, RustSyntax, Span, Spans,
+    TokenStream,
+};
+use indexing::{Container, Index, Unknown};
+use std::borrow::Cow;
 
 impl Input for TokenStream {
     type Container = Vec<FlatToken>;
     type Slice = [FlatToken];
-    type SourceInfo = ops::Range<Span>;
+    type SourceInfo = Spans;
     type SourceInfoPoint = Span;
     fn to_container(self) -> Self::Container {
         let mut out = vec![];
@@ -16,20 +22,26 @@ impl Input for TokenStream {
     fn slice<'b, 'i>(
         input: &'b Container<'i, Self::Container>,
         range: Range<'i>,
-    ) -> &'b Self::Slice {
-        &input[range.0]
+    ) -> Cow<'b, Self::Slice> {
+        Cow::Borrowed(&input[range.0])
     }
     fn source_info<'i>(
         input: &Container<'i, Self::Container>,
         range: Range<'i>,
     ) -> Self::SourceInfo {
-        // FIXME(eddyb) should be joining up spans, but the API
-        // for that is still "semver-exempt" in `proc-macro2`.
-        let last = range
-            .nonempty()
-            .map(|r| r.last().no_proof())
-            .unwrap_or(range.past_the_end());
-        Self::source_info_point(input, range.first())..Self::source_info_point(input, last)
+        match range.nonempty() {
+            Some(range) => Spans {
+                first: input[range.first()].span(),
+                last: input[range.last()].span(),
+            },
+            None => {
+                let span = Self::source_info_point(input, range.first());
+                Spans {
+                    first: span,
+                    last: span,
+                }
+            }
+        }
     }
     fn source_info_point<'i>(
         input: &Container<'i, Self::Container>,
@@ -54,6 +66,13 @@ impl Input for TokenStream {
 
 impl InputMatch<&'static [FlatTokenPat<&'static str>]> for [FlatToken] {
     fn match_left(&self, &pat: &&[FlatTokenPat<&str>]) -> Option<usize> {
+        #[cfg(feature = "syn")]
+        {
+            if let [FlatTokenPat::Rust(syntax)] = *pat {
+                return match_rust_syntax_left(syntax, self);
+            }
+        }
+
         if self
             .iter()
             .zip(pat)
@@ -67,10 +86,17 @@ impl InputMatch<&'static [FlatTokenPat<&'static str>]> for [FlatToken] {
         }
     }
     fn match_right(&self, &pat: &&[FlatTokenPat<&str>]) -> Option<usize> {
+        #[cfg(feature = "syn")]
+        {
+            if let [FlatTokenPat::Rust(syntax)] = *pat {
+                return match_rust_syntax_right(syntax, self);
+            }
+        }
+
         if self
             .iter()
-            .zip(pat)
             .rev()
+            .zip(pat.iter().rev())
             .take_while(|(t, p)| t.matches_pat(p))
             .count()
             == pat.len()
@@ -81,3 +107,168 @@ impl InputMatch<&'static [FlatTokenPat<&'static str>]> for [FlatToken] {
         }
     }
 }
+
+impl SliceMatchAll<Trie<FlatTokenPat<&'static str>, &'static [FlatTokenPat<&'static str>]>>
+    for [FlatToken]
+{
+    fn match_all_left(
+        &self,
+        trie: &'static Trie<FlatTokenPat<&'static str>, &'static [FlatTokenPat<&'static str>]>,
+        f: &mut dyn FnMut(usize),
+    ) -> bool {
+        trie.walk(self, |pat, token| token.matches_pat(pat), f)
+    }
+}
+
+impl DescribePat for &'static [FlatTokenPat<&'static str>] {
+    fn describe(&self) -> String {
+        // Consecutive specific tokens (e.g. "`::`") are described together.
+        let mut descs = vec![];
+        let mut tokens = String::new();
+        let mut joint = false;
+        for pat in self.iter() {
+            let token = match *pat {
+                FlatTokenPat::Delim(c) | FlatTokenPat::Punct { ch: Some(c), .. } => {
+                    Some(c.to_string())
+                }
+                FlatTokenPat::Ident(Some(s)) | FlatTokenPat::Literal { value: Some(s), .. } => {
+                    Some(s.to_string())
+                }
+                _ => None,
+            };
+            match token {
+                Some(token) => {
+                    if !tokens.is_empty() && !joint {
+                        tokens.push(' ');
+                    }
+                    tokens += &token;
+                }
+                None => {
+                    if !tokens.is_empty() {
+                        descs.push(format!("`{}`", tokens));
+                        tokens.clear();
+                    }
+                    descs.push(
+                        match *pat {
+                            FlatTokenPat::Ident(_) => "identifier",
+                            FlatTokenPat::Punct { .. } => "punctuation",
+                            FlatTokenPat::Literal { kind, .. } => match kind {
+                                None => "literal",
+                                Some(LiteralKind::Str) => "string literal",
+                                Some(LiteralKind::ByteStr) => "byte string literal",
+                                Some(LiteralKind::CStr) => "C string literal",
+                                Some(LiteralKind::Char) => "character literal",
+                                Some(LiteralKind::Byte) => "byte literal",
+                                Some(LiteralKind::Int) => "integer literal",
+                                Some(LiteralKind::Float) => "float literal",
+                            },
+                            FlatTokenPat::NoneGroup => "invisible group",
+                            FlatTokenPat::Rust(syntax) => match syntax {
+                                RustSyntax::Expr => "Rust expression",
+                                RustSyntax::Type => "Rust type",
+                                RustSyntax::Path => "Rust path",
+                            },
+                            FlatTokenPat::Delim(_) => unreachable!(),
+                        }
+                        .to_string(),
+                    );
+                }
+            }
+            joint = match *pat {
+                FlatTokenPat::Punct { joint, .. } => joint == Some(true),
+                _ => false,
+            };
+        }
+        if !tokens.is_empty() {
+            descs.push(format!("`{}`", tokens));
+        }
+        if descs.is_empty() {
+            "nothing".to_string()
+        } else {
+            descs.join(" ")
+        }
+    }
+}
+
+impl ParseError<Span> {
+    /// Turn this error into a `compile_error!("...");` invocation (see `message`),
+    /// with the span of the error, e.g. to return from a procedural macro.
+    pub fn to_compile_error(&self) -> TokenStream {
+        compile_error(&self.message(), self.at)
+    }
+}
+
+#[cfg(feature = "syn")]
+fn match_rust_syntax_left(syntax: RustSyntax, tokens: &[FlatToken]) -> Option<usize> {
+    use syn::parse::{ParseStream, Parser};
+
+    // Only whole token trees can be given to `syn`, so stop
+    // before the first closing delimiter that wasn't opened.
+    let mut depth = 0;
+    let len = tokens
+        .iter()
+        .position(|token| match *token {
+            FlatToken::Delim(open, _) if "([{".contains(open) => {
+                depth += 1;
+                false
+            }
+            FlatToken::Delim(..) if depth == 0 => true,
+            FlatToken::Delim(..) => {
+                depth -= 1;
+                false
+            }
+            _ => false,
+        })
+        .unwrap_or(tokens.len());
+
+    // FIXME avoid rebuilding all the remaining tokens on every match.
+    let parser = |input: ParseStream<'_>| {
+        match syntax {
+            RustSyntax::Expr => drop(input.parse::<syn::Expr>()?),
+            RustSyntax::Type => drop(input.parse::<syn::Type>()?),
+            RustSyntax::Path => drop(input.parse::<syn::Path>()?),
+        }
+        // Count (without parsing) the tokens left after the longest match.
+        let mut rest = vec![];
+        flatten(input.parse()?, &mut rest);
+        Ok(rest.len())
+    };
+    let rest = parser.parse2(unflatten(&tokens[..len])?).ok()?;
+    Some(len - rest)
+}
+
+#[cfg(feature = "syn")]
+fn match_rust_syntax_right(syntax: RustSyntax, tokens: &[FlatToken]) -> Option<usize> {
+    use syn::parse::{ParseStream, Parser};
+
+    // Only whole token trees can be given to `syn`, so the match can only
+    // start at the beginning of one, after the last unclosed opening delimiter.
+    let mut depth = 0;
+    let mut starts = vec![];
+    for (i, token) in tokens.iter().enumerate().rev() {
+        match *token {
+            FlatToken::Delim(close, _) if ")]}".contains(close) => depth += 1,
+            FlatToken::Delim(..) if depth == 0 => break,
+            FlatToken::Delim(..) => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            starts.push(i);
+        }
+    }
+
+    // Try the longest suffix first, keeping the first one `syn` fully accepts.
+    // FIXME avoid reparsing the same tokens for every possible start.
+    let parser = |input: ParseStream<'_>| match syntax {
+        RustSyntax::Expr => input.parse::<syn::Expr>().map(drop),
+        RustSyntax::Type => input.parse::<syn::Type>().map(drop),
+        RustSyntax::Path => input.parse::<syn::Path>().map(drop),
+    };
+    starts
+        .into_iter()
+        .rev()
+        .find(|&start| {
+            unflatten(&tokens[start..]).map_or(false, |tokens| parser.parse2(tokens).is_ok())
+        })
+        .map(|start| tokens.len() - start)
+}
@@ -1,23 +1,76 @@
 use crate::forest::{GrammarReflector, OwnedParseForestAndNode, ParseNode};
-use crate::input::{Input, InputMatch, Range};
-use crate::parser::{ParseResult, Parser};
+use crate::input::{DescribePat, Input, InputRangeMatch, InputRangeMatchAll, Range, Trie};
+use crate::parser::{ParseResult, Parser, PrefixParse};
 use std::cmp::{Ordering, Reverse};
-use std::collections::{BTreeSet, BinaryHeap, HashMap};
+use std::collections::{BTreeSet, BinaryHeap, HashMap, HashSet};
 use std::fmt;
 use std::hash::Hash;
 use std::io::{self, Write};
+use std::iter;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "parallel")]
+mod parallel;
 
 pub struct Runtime<'a, 'i, C: CodeLabel, I: Input> {
     parser: Parser<'a, 'i, C::GrammarReflector, I>,
-    state: &'a mut RuntimeState<'i, C>,
+    state: StateRef<'a, 'i, C>,
     current: C,
     saved: Option<ParseNode<'i, C::ParseNodeKind>>,
 }
 
+/// Access to the threads, GSS and memoizer of a parse, which are either
+/// all owned by the `Runtime::run` loop, or shared between the threads
+/// of a parallel parse (see `Runtime::parse_parallel`).
+enum StateRef<'a, 'i, C: CodeLabel> {
+    Exclusive(&'a mut RuntimeState<'i, C>),
+    #[cfg(feature = "parallel")]
+    Shared(&'a parallel::SharedState<'i, C>),
+}
+
 struct RuntimeState<'i, C: CodeLabel> {
     threads: Threads<'i, C>,
     gss: GraphStack<'i, C>,
     memoizer: Memoizer<'i, C>,
+    /// Combined size of `gss` and `memoizer` that triggers the next
+    /// `collect_garbage` (twice the size left by the previous one).
+    gc_threshold: usize,
+    gc_runs: usize,
+    gc_time: Duration,
+    max_live_calls: usize,
+}
+
+/// Statistics about a single parse (see `Runtime::parse_with_stats`), e.g.
+/// for tracking performance regressions. Counts are of everything added
+/// over the whole parse, regardless of what garbage collection removed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ParseStats {
+    /// Threads spawned, including those discarded as duplicates (i.e. ones
+    /// identical to a thread already seen), which are also counted below.
+    pub threads_spawned: usize,
+    pub threads_deduplicated: usize,
+    /// The most threads ever waiting to run at the same time.
+    pub max_queue_len: usize,
+    /// Calls (nodes) and the continuations they return to (edges) in the GSS.
+    pub gss_nodes: usize,
+    pub gss_edges: usize,
+    /// Results (i.e. lengths of matches) memoized, across all calls.
+    pub memoizer_entries: usize,
+    /// The most calls (in the GSS and the memoizer, combined) kept at once,
+    /// which garbage collection keeps from growing with the input, except
+    /// for calls which can still return (e.g. one for each list element).
+    pub max_live_calls: usize,
+    /// Parse nodes in the forest with (any) possible choices/splits.
+    pub forest_choice_nodes: usize,
+    pub forest_split_nodes: usize,
+    /// Time spent running threads, not including garbage collection.
+    pub run_time: Duration,
+    /// Time spent collecting garbage, over `gc_runs` separate runs.
+    pub gc_runs: usize,
+    pub gc_time: Duration,
+    /// Time spent on the whole parse, also including building the forest
+    /// (or the error) from everything recorded while running threads.
+    pub total_time: Duration,
 }
 
 impl<'i, P, G, C, I: Input> Runtime<'_, 'i, C, I>
@@ -34,74 +87,182 @@ where
         callee: C,
         kind: P,
     ) -> ParseResult<I::SourceInfoPoint, OwnedParseForestAndNode<G, P, I>> {
-        Parser::parse_with(grammar, input, |mut parser| {
-            let call = Call {
-                callee,
-                range: parser.remaining(),
-            };
-            let mut state = RuntimeState {
-                threads: Threads {
-                    queue: BinaryHeap::new(),
-                    seen: BTreeSet::new(),
-                },
-                gss: GraphStack {
-                    returns: HashMap::new(),
-                },
-                memoizer: Memoizer {
-                    lengths: HashMap::new(),
-                },
-            };
+        Parser::parse_with(grammar, input, |parser| {
+            Self::run(parser, callee, kind, false).0
+        })
+    }
 
-            // Start with one thread, at the provided entry-point.
+    /// Like `parse`, but also returning `ParseStats` about the parse.
+    pub fn parse_with_stats(
+        grammar: G,
+        input: I,
+        callee: C,
+        kind: P,
+    ) -> (
+        ParseResult<I::SourceInfoPoint, OwnedParseForestAndNode<G, P, I>>,
+        ParseStats,
+    ) {
+        let start = Instant::now();
+        let mut stats = ParseStats::default();
+        let result = Parser::parse_with(grammar, input, |parser| {
+            let (result, run_stats) = Self::run(parser, callee, kind, false);
+            stats = run_stats;
+            result
+        });
+        stats.total_time = start.elapsed();
+        (result, stats)
+    }
+
+    /// Like `parse`, but only the longest matching prefix of `input` is parsed.
+    pub fn parse_prefix(
+        grammar: G,
+        input: I,
+        callee: C,
+        kind: P,
+    ) -> PrefixParse<I::SourceInfoPoint, OwnedParseForestAndNode<G, P, I>> {
+        Parser::parse_prefix_with(grammar, input, |parser| {
+            Self::run(parser, callee, kind, false).0
+        })
+    }
+
+    /// Like `parse`, but only the longest matching suffix of `input` is parsed,
+    /// returned along with its length. This still matches left-to-right,
+    /// by starting at every position of `input` (sharing the GSS/memoizer).
+    pub fn parse_suffix(
+        grammar: G,
+        input: I,
+        callee: C,
+        kind: P,
+    ) -> ParseResult<I::SourceInfoPoint, (OwnedParseForestAndNode<G, P, I>, usize)> {
+        Parser::parse_suffix_left_to_right_with(grammar, input, |parser| {
+            Self::run(parser, callee, kind, true).0
+        })
+    }
+
+    /// Run all the threads started by calling `callee` at the start of the
+    /// remaining input (or, if `suffix` is set, at every position in it).
+    fn run<'i2>(
+        mut parser: Parser<'_, 'i2, G, I>,
+        callee: C,
+        kind: P,
+        suffix: bool,
+    ) -> (Option<ParseNode<'i2, P>>, ParseStats) {
+        let start = Instant::now();
+        let call = Call {
+            callee,
+            range: parser.remaining(),
+        };
+        let mut state = RuntimeState {
+            threads: Threads::new(),
+            gss: GraphStack::new(),
+            memoizer: Memoizer::new(),
+            gc_threshold: 0,
+            gc_runs: 0,
+            gc_time: Duration::default(),
+            max_live_calls: 0,
+        };
+
+        // Start with one thread, at the provided entry-point
+        // (or one for each suffix of the input, in `suffix` mode,
+        // skipping those which don't start at e.g. a character boundary).
+        let roots: Vec<_> = if suffix {
+            (0..=call.range.len())
+                .map(|start| Range(call.range.split_at(start).1))
+                .filter(|range| parser.input_is_boundary(range.first()))
+                .map(|range| Call {
+                    callee: call.callee,
+                    range,
+                })
+                .collect()
+        } else {
+            vec![call]
+        };
+        for &root in &roots {
             state.threads.spawn(
                 Continuation {
-                    code: call.callee,
+                    code: root.callee,
                     saved: None,
-                    result: Range(call.range.frontiers().0),
+                    result: Range(root.range.frontiers().0),
                 },
-                call.range,
+                root.range,
             );
+        }
 
-            // Run all threads to completion.
-            while let Some(next) = state.threads.steal() {
-                let Call {
-                    callee:
-                        Continuation {
-                            code,
-                            saved,
-                            result,
-                        },
-                    range,
-                } = next;
-                code.step(Runtime {
-                    parser: parser.with_result_and_remaining(result, range),
-                    state: &mut state,
-                    current: code,
-                    saved,
-                });
+        // Run all threads to completion.
+        let mut pos = call.range.start();
+        while let Some(next) = state.threads.steal() {
+            if next.range.start() > pos {
+                pos = next.range.start();
+                state.collect_garbage(call, suffix, next);
             }
+            let Call {
+                callee:
+                    Continuation {
+                        code,
+                        saved,
+                        result,
+                    },
+                range,
+            } = next;
+            code.step(Runtime {
+                parser: parser.with_result_and_remaining(result, range),
+                state: StateRef::Exclusive(&mut state),
+                current: code,
+                saved,
+            });
+        }
 
-            // If the function call we started with ever returned,
-            // we will find an entry for it in the memoizer, from
-            // which we pick the longest match.
-            state
-                .memoizer
-                .longest_result(call)
-                .map(|range| ParseNode { kind, range })
-        })
+        // If the function call we started with ever returned,
+        // we will find an entry for it in the memoizer, from
+        // which we pick the longest match.
+        // In `suffix` mode, that's the first call (i.e. the one starting
+        // the earliest) to have returned with all of its remaining input.
+        let result = if suffix {
+            roots.iter().find_map(|&root| {
+                state
+                    .memoizer
+                    .longest_result(root)
+                    .filter(|range| range.end() == root.range.end())
+            })
+        } else {
+            state.memoizer.longest_result(call)
+        };
+        let result = result.map(|range| ParseNode { kind, range });
+
+        state.max_live_calls = state.max_live_calls.max(state.live_calls());
+        let (forest_choice_nodes, forest_split_nodes) = parser.forest_node_counts();
+        let stats = ParseStats {
+            threads_spawned: state.threads.spawned,
+            threads_deduplicated: state.threads.deduplicated,
+            max_queue_len: state.threads.max_queue_len,
+            gss_nodes: state.gss.nodes,
+            gss_edges: state.gss.edges,
+            memoizer_entries: state.memoizer.entries,
+            max_live_calls: state.max_live_calls,
+            forest_choice_nodes,
+            forest_split_nodes,
+            run_time: start.elapsed() - state.gc_time,
+            gc_runs: state.gc_runs,
+            gc_time: state.gc_time,
+            total_time: Duration::default(),
+        };
+        (result, stats)
     }
 
-    pub fn input_consume_left<'a, Pat: fmt::Debug>(
+    pub fn input_consume_left<'a, Pat: DescribePat>(
         &'a mut self,
         pat: &'static Pat,
     ) -> Option<Runtime<'a, 'i, C, I>>
     where
-        I::Slice: InputMatch<Pat>,
+        I: InputRangeMatch<Pat>,
     {
-        match self.parser.input_consume_left(pat) {
+        match self
+            .parser
+            .input_consume_left(self.current.enclosing_rule(), pat)
+        {
             Some(parser) => Some(Runtime {
                 parser,
-                state: self.state,
+                state: self.state.reborrow(),
                 current: self.current,
                 saved: self.saved,
             }),
@@ -109,17 +270,38 @@ where
         }
     }
 
-    pub fn input_consume_right<'a, Pat>(
+    pub fn input_peek_left<Pat: DescribePat>(&mut self, rule: P, pat: &'static Pat) -> bool
+    where
+        I: InputRangeMatch<Pat>,
+    {
+        self.parser.input_peek_left(rule, pat)
+    }
+
+    pub fn input_match_all_left<T, Pat: DescribePat>(
+        &mut self,
+        trie: &'static Trie<T, Pat>,
+    ) -> Vec<usize>
+    where
+        I: InputRangeMatchAll<Trie<T, Pat>>,
+    {
+        self.parser
+            .input_match_all_left(self.current.enclosing_rule(), trie)
+    }
+
+    pub fn input_consume_right<'a, Pat: DescribePat>(
         &'a mut self,
         pat: &'static Pat,
     ) -> Option<Runtime<'a, 'i, C, I>>
     where
-        I::Slice: InputMatch<Pat>,
+        I: InputRangeMatch<Pat>,
     {
-        match self.parser.input_consume_right(pat) {
+        match self
+            .parser
+            .input_consume_right(self.current.enclosing_rule(), pat)
+        {
             Some(parser) => Some(Runtime {
                 parser,
-                state: self.state,
+                state: self.state.reborrow(),
                 current: self.current,
                 saved: self.saved,
             }),
@@ -150,7 +332,7 @@ where
     }
 
     pub fn spawn(&mut self, next: C) {
-        self.state.threads.spawn(
+        self.state.spawn(
             Continuation {
                 code: next,
                 saved: self.saved,
@@ -170,30 +352,44 @@ where
             saved: self.saved,
             result: self.parser.result(),
         };
-        let returns = self.state.gss.returns.entry(call).or_default();
-        if returns.insert(next) {
-            if returns.len() > 1 {
-                if let Some(lengths) = self.state.memoizer.lengths.get(&call) {
-                    for &len in lengths {
-                        let (call_result, remaining, _) = call.range.split_at(len);
-                        self.state.threads.spawn(
-                            Continuation {
-                                result: Range(next.result.join(call_result).unwrap()),
-                                ..next
-                            },
-                            Range(remaining),
-                        );
+        self.state.call(call, next);
+    }
+
+    /// Run `ops`, generated by the table-driven backend, each of them doing
+    /// what the code generated for them by the default backend would do.
+    pub fn interpret(&mut self, ops: &'static [Op<C, P>])
+    where
+        C: OpPats<I>,
+    {
+        for op in ops {
+            match *op {
+                Op::Spawn(next) => self.spawn(next),
+                Op::Call(callee, next) => self.call(callee, next),
+                Op::Ret => self.ret(),
+                Op::Save(kind) => self.save(kind),
+                Op::ForestAddChoice(kind, choice) => self.forest_add_choice(kind, choice),
+                Op::ForestAddSplit(kind) => {
+                    let saved = self.take_saved();
+                    self.forest_add_split(kind, saved);
+                }
+                Op::ConsumeLeft(pat, then) => {
+                    if let Some(mut rt) = C::input_consume_left(self, pat) {
+                        rt.interpret(then);
+                    }
+                }
+                Op::PeekLeft(pats, then) => {
+                    if pats
+                        .iter()
+                        .any(|&(rule, pat)| C::input_peek_left(self, rule, pat))
+                    {
+                        self.interpret(then);
+                    }
+                }
+                Op::MatchAllLeft(trie, cases) => {
+                    for case in C::input_match_all_left(self, trie) {
+                        self.interpret(cases[case]);
                     }
                 }
-            } else {
-                self.state.threads.spawn(
-                    Continuation {
-                        code: call.callee,
-                        saved: None,
-                        result: Range(call.range.frontiers().0),
-                    },
-                    call.range,
-                );
             }
         }
     }
@@ -205,42 +401,232 @@ where
             callee: self.current.enclosing_fn(),
             range: Range(call_result.join(remaining.0).unwrap()),
         };
-        if self
-            .state
-            .memoizer
-            .lengths
-            .entry(call)
-            .or_default()
-            .insert(call_result.len())
-        {
-            if let Some(returns) = self.state.gss.returns.get(&call) {
-                for &next in returns {
-                    self.state.threads.spawn(
+        self.state.ret(call, call_result, remaining);
+    }
+}
+
+impl<'i, C: CodeLabel> StateRef<'_, 'i, C> {
+    fn reborrow(&mut self) -> StateRef<'_, 'i, C> {
+        match self {
+            StateRef::Exclusive(state) => StateRef::Exclusive(&mut **state),
+            #[cfg(feature = "parallel")]
+            StateRef::Shared(state) => StateRef::Shared(&**state),
+        }
+    }
+
+    fn spawn(&mut self, next: Continuation<'i, C>, range: Range<'i>) {
+        match self {
+            StateRef::Exclusive(state) => state.threads.spawn(next, range),
+            #[cfg(feature = "parallel")]
+            StateRef::Shared(state) => state.spawn(next, range),
+        }
+    }
+
+    fn call(&mut self, call: Call<'i, C>, next: Continuation<'i, C>) {
+        match self {
+            StateRef::Exclusive(state) => {
+                let threads = &mut state.threads;
+                call_with(
+                    &mut state.gss,
+                    &state.memoizer,
+                    call,
+                    next,
+                    |next, range| threads.spawn(next, range),
+                );
+            }
+            #[cfg(feature = "parallel")]
+            StateRef::Shared(state) => state.call(call, next),
+        }
+    }
+
+    fn ret(&mut self, call: Call<'i, C>, call_result: Range<'i>, remaining: Range<'i>) {
+        match self {
+            StateRef::Exclusive(state) => {
+                let threads = &mut state.threads;
+                ret_with(
+                    &state.gss,
+                    &mut state.memoizer,
+                    call,
+                    call_result,
+                    remaining,
+                    |next, range| threads.spawn(next, range),
+                );
+            }
+            #[cfg(feature = "parallel")]
+            StateRef::Shared(state) => state.ret(call, call_result, remaining),
+        }
+    }
+}
+
+/// Add the GSS edge from `call` to `next`, spawning either the threads that
+/// return to `next` (from the memoized results of `call`), or, if `next` is
+/// the first continuation of `call`, a thread to start running the callee.
+fn call_with<'i, C: CodeLabel>(
+    gss: &mut GraphStack<'i, C>,
+    memoizer: &Memoizer<'i, C>,
+    call: Call<'i, C>,
+    next: Continuation<'i, C>,
+    mut spawn: impl FnMut(Continuation<'i, C>, Range<'i>),
+) {
+    let returns = gss.returns.entry(call).or_default();
+    if returns.insert(next) {
+        gss.edges += 1;
+        if returns.len() > 1 {
+            if let Some(lengths) = memoizer.lengths.get(&call) {
+                for &len in lengths {
+                    let (call_result, remaining, _) = call.range.split_at(len);
+                    spawn(
                         Continuation {
-                            result: Range(next.result.join(call_result.0).unwrap()),
+                            result: Range(next.result.join(call_result).unwrap()),
                             ..next
                         },
-                        remaining,
+                        Range(remaining),
                     );
                 }
             }
+        } else {
+            gss.nodes += 1;
+            spawn(
+                Continuation {
+                    code: call.callee,
+                    saved: None,
+                    result: Range(call.range.frontiers().0),
+                },
+                call.range,
+            );
+        }
+    }
+}
+
+/// Memoize `call_result` as a result of `call`, and, if it's new, spawn
+/// threads for all the continuations (found in the GSS) to return to.
+fn ret_with<'i, C: CodeLabel>(
+    gss: &GraphStack<'i, C>,
+    memoizer: &mut Memoizer<'i, C>,
+    call: Call<'i, C>,
+    call_result: Range<'i>,
+    remaining: Range<'i>,
+    mut spawn: impl FnMut(Continuation<'i, C>, Range<'i>),
+) {
+    if memoizer
+        .lengths
+        .entry(call)
+        .or_default()
+        .insert(call_result.len())
+    {
+        memoizer.entries += 1;
+        if let Some(returns) = gss.returns.get(&call) {
+            for &next in returns {
+                spawn(
+                    Continuation {
+                        result: Range(next.result.join(call_result.0).unwrap()),
+                        ..next
+                    },
+                    remaining,
+                );
+            }
         }
     }
 }
 
+impl<'i, C: CodeLabel> RuntimeState<'i, C> {
+    fn live_calls(&self) -> usize {
+        self.gss.returns.len() + self.memoizer.lengths.len()
+    }
+
+    /// Remove the GSS edges and memoized results which can't be used anymore,
+    /// now that `current` was stolen, and all threads are at or past its start.
+    fn collect_garbage(
+        &mut self,
+        root: Call<'i, C>,
+        suffix: bool,
+        current: Call<'i, Continuation<'i, C>>,
+    ) {
+        // NOTE nothing else removes calls, so checking before each
+        // collection (and at the end) finds the most ever kept at once.
+        self.max_live_calls = self.max_live_calls.max(self.live_calls());
+        if self.live_calls() < self.gc_threshold {
+            return;
+        }
+        let start = Instant::now();
+
+        let pos = current.range.start();
+
+        // New calls can only start at (or past) `pos`, so memoized results for
+        // calls starting before it will never be looked up again, except for
+        // the one that the whole parse started with (or, in `suffix` mode,
+        // all the calls to the same rule, which all run to the end).
+        self.memoizer.lengths.retain(|&call, _| {
+            call.range.start() >= pos || call == root || (suffix && call.callee == root.callee)
+        });
+
+        // Calls that started before `pos` can still return, but only to the
+        // callers reachable (through the GSS) from the calls threads are in.
+        let gss = &self.gss;
+        let reachable = reachable_calls(
+            self.threads.queue.iter().chain(iter::once(&current)),
+            |call| gss.returns.get(&call),
+        );
+        self.gss
+            .returns
+            .retain(|call, _| call.range.start() >= pos || reachable.contains(call));
+
+        self.gc_threshold = 2 * self.live_calls();
+        self.gc_runs += 1;
+        self.gc_time += start.elapsed();
+    }
+}
+
+/// All the calls reachable from the calls `threads` are in, through
+/// the GSS edges (i.e. the continuations) `returns` finds for each call.
+fn reachable_calls<'a, 'i: 'a, C: CodeLabel>(
+    threads: impl Iterator<Item = &'a Call<'i, Continuation<'i, C>>>,
+    returns: impl Fn(Call<'i, C>) -> Option<&'a BTreeSet<Continuation<'i, C>>>,
+) -> HashSet<Call<'i, C>> {
+    let mut reachable = HashSet::new();
+    let mut stack: Vec<_> = threads
+        .map(|thread| thread.callee.enclosing_call(thread.range))
+        .collect();
+    while let Some(call) = stack.pop() {
+        if reachable.insert(call) {
+            if let Some(returns) = returns(call) {
+                stack.extend(returns.iter().map(|next| next.enclosing_call(call.range)));
+            }
+        }
+    }
+    reachable
+}
+
 struct Threads<'i, C: CodeLabel> {
     queue: BinaryHeap<Call<'i, Continuation<'i, C>>>,
     seen: BTreeSet<Call<'i, Continuation<'i, C>>>,
+    // Only used for `ParseStats`.
+    spawned: usize,
+    deduplicated: usize,
+    max_queue_len: usize,
 }
 
 impl<'i, C: CodeLabel> Threads<'i, C> {
+    fn new() -> Self {
+        Threads {
+            queue: BinaryHeap::new(),
+            seen: BTreeSet::new(),
+            spawned: 0,
+            deduplicated: 0,
+            max_queue_len: 0,
+        }
+    }
     fn spawn(&mut self, next: Continuation<'i, C>, range: Range<'i>) {
         let t = Call {
             callee: next,
             range,
         };
+        self.spawned += 1;
         if self.seen.insert(t) {
             self.queue.push(t);
+            self.max_queue_len = self.max_queue_len.max(self.queue.len());
+        } else {
+            self.deduplicated += 1;
         }
     }
     fn steal(&mut self) -> Option<Call<'i, Continuation<'i, C>>> {
@@ -276,6 +662,17 @@ struct Continuation<'i, C: CodeLabel> {
     result: Range<'i>,
 }
 
+impl<'i, C: CodeLabel> Continuation<'i, C> {
+    /// Get the call this continuation will eventually return from,
+    /// given the `remaining` input after its `result`.
+    fn enclosing_call(&self, remaining: Range<'i>) -> Call<'i, C> {
+        Call {
+            callee: self.code.enclosing_fn(),
+            range: Range(self.result.join(remaining.0).unwrap()),
+        }
+    }
+}
+
 // TODO(eddyb) figure out if `Call<Continuation<C>>` can be optimized,
 // based on the fact that `result.end == range.start` should always hold.
 // (Also, `range.end` is constant across a whole parse)
@@ -311,9 +708,20 @@ impl<C: Ord> Ord for Call<'_, C> {
 
 struct GraphStack<'i, C: CodeLabel> {
     returns: HashMap<Call<'i, C>, BTreeSet<Continuation<'i, C>>>,
+    // Only used for `ParseStats`.
+    nodes: usize,
+    edges: usize,
 }
 
 impl<C: CodeLabel> GraphStack<'_, C> {
+    fn new() -> Self {
+        GraphStack {
+            returns: HashMap::new(),
+            nodes: 0,
+            edges: 0,
+        }
+    }
+
     // FIXME(eddyb) figure out what to do here, now that
     // the GSS is no longer exposed in the public API.
     #[allow(unused)]
@@ -326,10 +734,7 @@ impl<C: CodeLabel> GraphStack<'_, C> {
                     out,
                     r#"    "{:?}" -> "{:?}" [label="{:?}"]"#,
                     call,
-                    Call {
-                        callee: next.code.enclosing_fn(),
-                        range: Range(next.result.join(call.range.0).unwrap()),
-                    },
+                    next.enclosing_call(call.range),
                     next.code
                 )?;
             }
@@ -340,9 +745,18 @@ impl<C: CodeLabel> GraphStack<'_, C> {
 
 struct Memoizer<'i, C: CodeLabel> {
     lengths: HashMap<Call<'i, C>, BTreeSet<usize>>,
+    // Only used for `ParseStats`.
+    entries: usize,
 }
 
 impl<'i, C: CodeLabel> Memoizer<'i, C> {
+    fn new() -> Self {
+        Memoizer {
+            lengths: HashMap::new(),
+            entries: 0,
+        }
+    }
+
     fn results<'a>(&'a self, call: Call<'i, C>) -> impl DoubleEndedIterator<Item = Range<'i>> + 'a {
         self.lengths
             .get(&call)
@@ -365,8 +779,55 @@ pub trait CodeLabel: fmt::Debug + Ord + Hash + Copy + 'static {
     type ParseNodeKind: fmt::Debug + Ord + Hash + Copy;
 
     fn enclosing_fn(self) -> Self;
+
+    /// The named rule this code is part of, for error reporting.
+    fn enclosing_rule(self) -> Self::ParseNodeKind;
 }
 
 pub trait CodeStep<I: Input>: CodeLabel {
     fn step<'i>(self, rt: Runtime<'_, 'i, Self, I>);
 }
+
+/// One step of the code generated for a `CodeLabel` by the table-driven
+/// backend (see `Runtime::interpret`), each doing what the `Runtime`
+/// method of the same name does, with patterns (and `Trie`s) referred
+/// to by their index, as they're only matched through `OpPats`.
+#[derive(Copy, Clone, Debug)]
+pub enum Op<C: 'static, P: 'static> {
+    Spawn(C),
+    Call(C, C),
+    Ret,
+    Save(P),
+    ForestAddChoice(P, P),
+
+    /// `forest_add_split` with the node `take_saved` returns.
+    ForestAddSplit(P),
+
+    /// Only run the `Op`s if the pattern matches, after consuming it.
+    ConsumeLeft(usize, &'static [Op<C, P>]),
+
+    /// Only run the `Op`s if any of the patterns matches (each checked
+    /// on behalf of a rule, for error reporting), without consuming it.
+    PeekLeft(&'static [(P, usize)], &'static [Op<C, P>]),
+
+    /// Match all the patterns of the `Trie` at once, and run the `Op`s
+    /// corresponding to each of the patterns which matched.
+    MatchAllLeft(usize, &'static [&'static [Op<C, P>]]),
+}
+
+/// Matching the patterns (and `Trie`s) `Op`s refer to, by their index,
+/// which has to be generated, as they all have different types.
+pub trait OpPats<I: Input>: CodeLabel {
+    fn input_consume_left<'a, 'i>(
+        rt: &'a mut Runtime<'_, 'i, Self, I>,
+        pat: usize,
+    ) -> Option<Runtime<'a, 'i, Self, I>>;
+
+    fn input_peek_left(
+        rt: &mut Runtime<'_, '_, Self, I>,
+        rule: Self::ParseNodeKind,
+        pat: usize,
+    ) -> bool;
+
+    fn input_match_all_left(rt: &mut Runtime<'_, '_, Self, I>, trie: usize) -> Vec<usize>;
+}
@@ -0,0 +1,326 @@
+//! Parsing on multiple OS threads ("workers"), enabled by the `parallel`
+//! feature, which run all the GLL threads at the same input position (a
+//! "batch") concurrently, as their order doesn't affect the result.
+
+use super::{
+    call_with, reachable_calls, ret_with, Call, CodeLabel, CodeStep, Continuation, GraphStack,
+    Memoizer, Runtime, StateRef, Threads,
+};
+use crate::forest::{GrammarReflector, OwnedParseForestAndNode, ParseNode};
+use crate::input::{Input, Range};
+use crate::parser::{ParseResult, Parser, ParserState};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::{Barrier, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+/// How many `Shard`s to create for each worker, to keep the chance
+/// of two workers needing the same one (at the same time) low.
+const SHARDS_PER_WORKER: usize = 4;
+
+/// The equivalent of `RuntimeState`, shared between all the workers.
+pub(super) struct SharedState<'i, C: CodeLabel> {
+    /// The GSS and memoizer, split by (the hash of) the `Call`s they're keyed
+    /// on, so that each call (and return) only has to lock one `Shard`.
+    shards: Vec<Mutex<Shard<'i, C>>>,
+    batch: Mutex<Batch<'i, C>>,
+    /// Notified when threads are added to the current batch, or when
+    /// the batch ends (i.e. no more threads can be added to it).
+    batch_changed: Condvar,
+}
+
+struct Shard<'i, C: CodeLabel> {
+    gss: GraphStack<'i, C>,
+    memoizer: Memoizer<'i, C>,
+}
+
+struct Batch<'i, C: CodeLabel> {
+    /// Threads left to run, past the current batch (which also tracks
+    /// the ones seen so far, including those in the current batch).
+    threads: Threads<'i, C>,
+    /// The input range all the threads in the current batch have left.
+    range: Option<Range<'i>>,
+    queue: Vec<Call<'i, Continuation<'i, C>>>,
+    /// How many threads from `queue` are being run (by different workers),
+    /// which means the batch isn't over, even if `queue` is empty.
+    running: usize,
+    /// Set when there are no threads left, for the workers to stop.
+    done: bool,
+    /// Combined size of the `Shard`s that triggers the next `collect_garbage`.
+    gc_threshold: usize,
+}
+
+impl<'i, P, G, C, I: Input> Runtime<'_, 'i, C, I>
+where
+    // FIXME these shouldn't be needed, as they are bounds on
+    // `GrammarReflector::ParseNodeKind`, but that's ignored currently.
+    P: fmt::Debug + Ord + Hash + Copy + Send + Sync,
+    G: GrammarReflector<ParseNodeKind = P> + Sync,
+    C: CodeStep<I, GrammarReflector = G, ParseNodeKind = P> + Send + Sync,
+    I::Container: Sync,
+{
+    /// Like `parse`, but using `workers` OS threads, and producing
+    /// the same result (including the errors) as `parse` would.
+    pub fn parse_parallel(
+        grammar: G,
+        input: I,
+        callee: C,
+        kind: P,
+        workers: usize,
+    ) -> ParseResult<I::SourceInfoPoint, OwnedParseForestAndNode<G, P, I>> {
+        assert!(workers > 0, "parsing needs at least one worker");
+        Parser::parse_with(grammar, input, |parser| {
+            Self::run_parallel(parser, callee, kind, workers)
+        })
+    }
+
+    fn run_parallel<'i2>(
+        mut parser: Parser<'_, 'i2, G, I>,
+        callee: C,
+        kind: P,
+        workers: usize,
+    ) -> Option<ParseNode<'i2, P>> {
+        let call = Call {
+            callee,
+            range: parser.remaining(),
+        };
+        let state = SharedState {
+            shards: (0..workers * SHARDS_PER_WORKER)
+                .map(|_| {
+                    Mutex::new(Shard {
+                        gss: GraphStack::new(),
+                        memoizer: Memoizer::new(),
+                    })
+                })
+                .collect(),
+            batch: Mutex::new(Batch {
+                threads: Threads::new(),
+                range: None,
+                queue: vec![],
+                running: 0,
+                done: false,
+                gc_threshold: 0,
+            }),
+            batch_changed: Condvar::new(),
+        };
+
+        // Start with one thread, at the provided entry-point.
+        state.spawn(
+            Continuation {
+                code: call.callee,
+                saved: None,
+                result: Range(call.range.frontiers().0),
+            },
+            call.range,
+        );
+
+        // Run all threads to completion, one batch at a time, with each
+        // worker recording parse nodes (and errors) in its own `ParserState`.
+        let forest = parser.forest();
+        let barrier = Barrier::new(workers);
+        let (state, barrier) = (&state, &barrier);
+        let parser_states: Vec<_> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(move || {
+                        let mut parser_state = ParserState::new(call.range);
+                        let mut parser = Parser::with_state(forest, &mut parser_state, call.range);
+                        loop {
+                            // Only start the next batch once all the workers
+                            // are done with the previous one.
+                            if barrier.wait().is_leader() {
+                                state.next_batch(call);
+                            }
+                            barrier.wait();
+                            if state.batch.lock().unwrap().done {
+                                break;
+                            }
+
+                            let mut finished = false;
+                            while let Some(next) = state.steal(finished) {
+                                let Call {
+                                    callee:
+                                        Continuation {
+                                            code,
+                                            saved,
+                                            result,
+                                        },
+                                    range,
+                                } = next;
+                                code.step(Runtime {
+                                    parser: parser.with_result_and_remaining(result, range),
+                                    state: StateRef::Shared(state),
+                                    current: code,
+                                    saved,
+                                });
+                                finished = true;
+                            }
+                        }
+                        parser_state
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().unwrap())
+                .collect()
+        });
+        for parser_state in parser_states {
+            parser.merge_state(parser_state);
+        }
+
+        // If the function call we started with ever returned,
+        // we will find an entry for it in the memoizer, from
+        // which we pick the longest match.
+        let longest_result = state.shard(call).memoizer.longest_result(call);
+        longest_result.map(|range| ParseNode { kind, range })
+    }
+}
+
+impl<'i, C: CodeLabel> SharedState<'i, C> {
+    fn shard_index(&self, call: Call<'i, C>) -> usize {
+        let mut hasher = DefaultHasher::new();
+        call.hash(&mut hasher);
+        (hasher.finish() % self.shards.len() as u64) as usize
+    }
+
+    fn shard(&self, call: Call<'i, C>) -> MutexGuard<'_, Shard<'i, C>> {
+        self.shards[self.shard_index(call)].lock().unwrap()
+    }
+
+    pub(super) fn spawn(&self, next: Continuation<'i, C>, range: Range<'i>) {
+        let mut batch = self.batch.lock().unwrap();
+        let t = Call {
+            callee: next,
+            range,
+        };
+        if batch.threads.seen.insert(t) {
+            if batch.range == Some(range) {
+                batch.queue.push(t);
+                self.batch_changed.notify_one();
+            } else {
+                batch.threads.queue.push(t);
+            }
+        }
+    }
+
+    // NOTE the threads are only spawned after unlocking the `Shard`,
+    // but the call (or return) itself happens atomically, so it can't miss
+    // a return from (or to) the same call, happening on another worker.
+    pub(super) fn call(&self, call: Call<'i, C>, next: Continuation<'i, C>) {
+        let mut spawned = vec![];
+        {
+            let shard = &mut *self.shard(call);
+            call_with(
+                &mut shard.gss,
+                &shard.memoizer,
+                call,
+                next,
+                |next, range| spawned.push((next, range)),
+            );
+        }
+        for (next, range) in spawned {
+            self.spawn(next, range);
+        }
+    }
+
+    pub(super) fn ret(&self, call: Call<'i, C>, call_result: Range<'i>, remaining: Range<'i>) {
+        let mut spawned = vec![];
+        {
+            let shard = &mut *self.shard(call);
+            ret_with(
+                &shard.gss,
+                &mut shard.memoizer,
+                call,
+                call_result,
+                remaining,
+                |next, range| spawned.push((next, range)),
+            );
+        }
+        for (next, range) in spawned {
+            self.spawn(next, range);
+        }
+    }
+
+    /// Take a thread from the current batch, if there are any left, waiting
+    /// for other workers to spawn more, as long as they're still running.
+    /// `finished` indicates this worker is done with the last thread it took.
+    fn steal(&self, finished: bool) -> Option<Call<'i, Continuation<'i, C>>> {
+        let mut batch = self.batch.lock().unwrap();
+        if finished {
+            batch.running -= 1;
+        }
+        loop {
+            if let Some(t) = batch.queue.pop() {
+                batch.running += 1;
+                return Some(t);
+            }
+            if batch.running == 0 {
+                self.batch_changed.notify_all();
+                return None;
+            }
+            batch = self.batch_changed.wait(batch).unwrap();
+        }
+    }
+
+    /// Start a new batch with all the threads at the next input position,
+    /// or set `done`, if there are none left. Must only be called while no
+    /// workers are running threads, e.g. between batches.
+    fn next_batch(&self, root: Call<'i, C>) {
+        let mut batch = self.batch.lock().unwrap();
+        let batch = &mut *batch;
+        let first = match batch.threads.steal() {
+            Some(t) => t,
+            None => {
+                batch.done = true;
+                return;
+            }
+        };
+        batch.range = Some(first.range);
+        batch.queue.push(first);
+        while batch.threads.queue.peek().map(|t| t.range) == Some(first.range) {
+            let t = batch.threads.steal().unwrap();
+            batch.queue.push(t);
+        }
+        self.collect_garbage(root, batch);
+    }
+
+    /// Like `RuntimeState::collect_garbage`, but across all the `Shard`s,
+    /// with the threads in `batch` as the ones which can still return.
+    fn collect_garbage(&self, root: Call<'i, C>, batch: &mut Batch<'i, C>) {
+        let mut shards: Vec<_> = self
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap())
+            .collect();
+        let size = |shards: &[MutexGuard<'_, Shard<'i, C>>]| -> usize {
+            shards
+                .iter()
+                .map(|shard| shard.gss.returns.len() + shard.memoizer.lengths.len())
+                .sum()
+        };
+        if size(&shards) < batch.gc_threshold {
+            return;
+        }
+
+        let pos = batch.range.unwrap().start();
+        for shard in &mut shards {
+            shard
+                .memoizer
+                .lengths
+                .retain(|&call, _| call.range.start() >= pos || call == root);
+        }
+        let reachable = reachable_calls(batch.threads.queue.iter().chain(&batch.queue), |call| {
+            shards[self.shard_index(call)].gss.returns.get(&call)
+        });
+        for shard in &mut shards {
+            shard
+                .gss
+                .returns
+                .retain(|call, _| call.range.start() >= pos || reachable.contains(call));
+        }
+
+        batch.gc_threshold = 2 * size(&shards);
+    }
+}
@@ -1,13 +1,161 @@
+use crate::analysis::OverlapsFirst;
 use grammer::rule::{MatchesEmpty, MaybeKnown};
 use std::char;
+use std::fmt;
 use std::ops::{self, Bound, RangeBounds};
+use unicode_xid::UnicodeXID;
 
 pub type Context<S = String> = grammer::context::Context<Pat<S>>;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub enum Pat<S = String, C = char> {
+// NOTE `L` is only a parameter so that `Pat` can stay `Copy` (e.g. with
+// `&'static [ClassItem]`), like it was before character classes.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Pat<S = String, C = char, L = Vec<ClassItem>> {
     String(S),
+    CaseInsensitive(S),
     Range(C, C),
+    Class(CharClass<L>),
+}
+
+// NOTE this can't be derived, as `CharClass<L>: Debug` needs `L` to be a list.
+impl<S: fmt::Debug, C: fmt::Debug, L: AsRef<[ClassItem]>> fmt::Debug for Pat<S, C, L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pat::String(s) => f.debug_tuple("String").field(s).finish(),
+            Pat::CaseInsensitive(s) => f.debug_tuple("CaseInsensitive").field(s).finish(),
+            Pat::Range(start, end) => f.debug_tuple("Range").field(start).field(end).finish(),
+            Pat::Class(class) => f.debug_tuple("Class").field(class).finish(),
+        }
+    }
+}
+
+/// A string matching any input that differs from it only in case,
+/// e.g. `i"select"` in grammars. Characters are compared one at a time
+/// (see `CaseInsensitive::chars_eq`), without full Unicode case folding,
+/// so e.g. `i"ss"` doesn't match `ß`.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CaseInsensitive<S>(pub S);
+
+impl CaseInsensitive<&str> {
+    pub fn chars_eq(a: char, b: char) -> bool {
+        a == b || a.to_lowercase().eq(b.to_lowercase())
+    }
+}
+
+/// A set of characters, matching any one character in it (or, if `negated`,
+/// any one character not in it), e.g. `['a'..='z' '_']` or `!'"'` in grammars.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CharClass<L = Vec<ClassItem>> {
+    pub negated: bool,
+    pub items: L,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ClassItem {
+    Range(char, char),
+    Property(UnicodeProperty),
+}
+
+/// Unicode character properties, usable in grammars by their names,
+/// in character classes (e.g. `[XID_Continue '-']`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum UnicodeProperty {
+    XidStart,
+    XidContinue,
+    WhiteSpace,
+    Alphabetic,
+    Lowercase,
+    Uppercase,
+    Numeric,
+}
+
+impl UnicodeProperty {
+    pub const ALL: &'static [Self] = &[
+        UnicodeProperty::XidStart,
+        UnicodeProperty::XidContinue,
+        UnicodeProperty::WhiteSpace,
+        UnicodeProperty::Alphabetic,
+        UnicodeProperty::Lowercase,
+        UnicodeProperty::Uppercase,
+        UnicodeProperty::Numeric,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            UnicodeProperty::XidStart => "XID_Start",
+            UnicodeProperty::XidContinue => "XID_Continue",
+            UnicodeProperty::WhiteSpace => "White_Space",
+            UnicodeProperty::Alphabetic => "Alphabetic",
+            UnicodeProperty::Lowercase => "Lowercase",
+            UnicodeProperty::Uppercase => "Uppercase",
+            UnicodeProperty::Numeric => "Numeric",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().cloned().find(|p| p.name() == name)
+    }
+
+    pub fn contains(self, c: char) -> bool {
+        match self {
+            UnicodeProperty::XidStart => c.is_xid_start(),
+            UnicodeProperty::XidContinue => c.is_xid_continue(),
+            UnicodeProperty::WhiteSpace => c.is_whitespace(),
+            UnicodeProperty::Alphabetic => c.is_alphabetic(),
+            UnicodeProperty::Lowercase => c.is_lowercase(),
+            UnicodeProperty::Uppercase => c.is_uppercase(),
+            // NOTE not an actual property, but `Numeric_Type != None`.
+            UnicodeProperty::Numeric => c.is_numeric(),
+        }
+    }
+}
+
+impl ClassItem {
+    pub fn contains(self, c: char) -> bool {
+        match self {
+            ClassItem::Range(start, end) => start <= c && c <= end,
+            ClassItem::Property(property) => property.contains(c),
+        }
+    }
+}
+
+impl<L: AsRef<[ClassItem]>> CharClass<L> {
+    pub fn contains(&self, c: char) -> bool {
+        self.items.as_ref().iter().any(|item| item.contains(c)) != self.negated
+    }
+}
+
+// NOTE these use the grammar syntax, for descriptions and error messages.
+impl fmt::Debug for ClassItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            ClassItem::Range(start, end) if start == end => write!(f, "{:?}", start),
+            ClassItem::Range(start, end) => write!(f, "{:?}..={:?}", start, end),
+            ClassItem::Property(property) => write!(f, "{}", property.name()),
+        }
+    }
+}
+
+impl<L: AsRef<[ClassItem]>> fmt::Debug for CharClass<L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            write!(f, "!")?;
+        }
+        write!(f, "[")?;
+        for (i, item) in self.items.as_ref().iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{:?}", item)?;
+        }
+        write!(f, "]")
+    }
+}
+
+impl<S: fmt::Debug> fmt::Debug for CaseInsensitive<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "i{:?}", self.0)
+    }
 }
 
 impl<'a, C> From<&'a str> for Pat<&'a str, C> {
@@ -73,8 +221,84 @@ range_impls! {
 impl<S: AsRef<str>> MatchesEmpty for Pat<S> {
     fn matches_empty(&self) -> MaybeKnown<bool> {
         MaybeKnown::Known(match self {
-            Pat::String(s) => s.as_ref().is_empty(),
-            Pat::Range(..) => false,
+            Pat::String(s) | Pat::CaseInsensitive(s) => s.as_ref().is_empty(),
+            Pat::Range(..) | Pat::Class(_) => false,
         })
     }
 }
+
+impl<S: AsRef<str>> Pat<S> {
+    /// Whether a match can start with `c` (always true for empty strings).
+    fn can_start_with(&self, c: char) -> bool {
+        match self {
+            Pat::String(s) => s.as_ref().chars().next().map_or(true, |first| first == c),
+            Pat::CaseInsensitive(s) => s
+                .as_ref()
+                .chars()
+                .next()
+                .map_or(true, |first| CaseInsensitive::chars_eq(first, c)),
+            &Pat::Range(start, end) => start <= c && c <= end,
+            Pat::Class(class) => class.contains(c),
+        }
+    }
+
+    /// All the characters a match can start with, if there are only a few.
+    fn few_first_chars(&self) -> Option<Vec<char>> {
+        match self {
+            Pat::String(s) => Some(vec![s.as_ref().chars().next()?]),
+            Pat::CaseInsensitive(s) => {
+                let first = s.as_ref().chars().next()?;
+                Some(
+                    Some(first)
+                        .into_iter()
+                        .chain(first.to_lowercase())
+                        .chain(first.to_uppercase())
+                        .collect(),
+                )
+            }
+            Pat::Range(..) | Pat::Class(_) => None,
+        }
+    }
+
+    /// The smallest range of characters containing all those a match can
+    /// start with (using the whole range, if that's not easy to find).
+    fn first_char_bounds(&self) -> (char, char) {
+        let all = ('\0', char::MAX);
+        match self {
+            &Pat::Range(start, end) => (start, end),
+            Pat::Class(class) if !class.negated => {
+                let mut bounds: Option<(char, char)> = None;
+                for &item in &class.items {
+                    match item {
+                        ClassItem::Range(start, end) => {
+                            bounds = Some(
+                                bounds.map_or((start, end), |(s, e)| (s.min(start), e.max(end))),
+                            );
+                        }
+                        ClassItem::Property(_) => return all,
+                    }
+                }
+                bounds.unwrap_or(all)
+            }
+            _ => all,
+        }
+    }
+}
+
+impl<S: AsRef<str>> OverlapsFirst for Pat<S> {
+    fn overlaps_first(&self, other: &Self) -> bool {
+        if let Some(chars) = self.few_first_chars() {
+            return chars.into_iter().any(|c| other.can_start_with(c));
+        }
+        if let Some(chars) = other.few_first_chars() {
+            return chars.into_iter().any(|c| self.can_start_with(c));
+        }
+        // NOTE this checks every character both patterns could start with,
+        // which can be slow for character classes with Unicode properties.
+        let (a_start, a_end) = self.first_char_bounds();
+        let (b_start, b_end) = other.first_char_bounds();
+        (a_start.max(b_start) as u32..=a_end.min(b_end) as u32)
+            .filter_map(char::from_u32)
+            .any(|c| self.can_start_with(c) && other.can_start_with(c))
+    }
+}